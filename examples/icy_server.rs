@@ -0,0 +1,132 @@
+// 本地 ICY 测试服务器，用于测试网络电台播放、曲目信息和断线重连。
+// 用法: cargo run --example icy_server -- <音频文件> [端口] [断线秒数]
+// 然后在电台面板中添加 http://127.0.0.1:<端口>/
+use std::env;
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// 每发送多少字节音频数据插入一次元数据
+const META_INT: usize = 8192;
+// 每块数据的发送间隔，8192 字节 / 200ms 约为 320kbps
+const BLOCK_INTERVAL: Duration = Duration::from_millis(200);
+// 每隔多少块切换一次曲目名称，约 10 秒
+const BLOCKS_PER_TRACK: usize = 50;
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let file = match args.first() {
+        Some(f) => f.to_owned(),
+        None => {
+            println!("用法: icy_server <音频文件> [端口] [断线秒数]");
+            return;
+        }
+    };
+    let port: u16 = args.get(1).and_then(|p| p.parse().ok()).unwrap_or(8000);
+    let drop_after = args
+        .get(2)
+        .and_then(|s| s.parse().ok())
+        .map(Duration::from_secs);
+    let data = fs::read(&file).expect("无法读取音频文件");
+    if data.is_empty() {
+        println!("音频文件为空");
+        return;
+    }
+    let listener = TcpListener::bind(("127.0.0.1", port)).expect("无法监听端口");
+    println!("电台地址: http://127.0.0.1:{}/", port);
+    for stream in listener.incoming().flatten() {
+        let data = data.clone();
+        let content_type = content_type(&file);
+        thread::spawn(move || {
+            if let Err(e) = serve(stream, &data, content_type, drop_after) {
+                println!("连接结束: {}", e);
+            }
+        });
+    }
+}
+
+fn content_type(f: &str) -> &'static str {
+    let f = f.to_lowercase();
+    if f.ends_with(".mp3") {
+        "audio/mpeg"
+    } else if f.ends_with(".aac") {
+        "audio/aac"
+    } else if f.ends_with(".ogg") || f.ends_with(".opus") {
+        "audio/ogg"
+    } else if f.ends_with(".flac") {
+        "audio/flac"
+    } else {
+        "application/octet-stream"
+    }
+}
+
+// ICY 元数据块：第一个字节为长度/16，后面补零到 16 的倍数
+fn metadata(title: &str) -> Vec<u8> {
+    let text = format!("StreamTitle='{}';", title.replace('\'', ""));
+    let blocks = (text.len() + 15) / 16;
+    let mut block = vec![blocks as u8];
+    block.extend(text.bytes());
+    block.resize(1 + blocks * 16, 0);
+    block
+}
+
+fn serve(
+    mut stream: TcpStream,
+    data: &[u8],
+    content_type: &str,
+    drop_after: Option<Duration>,
+) -> std::io::Result<()> {
+    // 读取请求头，客户端发送 Icy-MetaData: 1 时才插入元数据
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut icy = false;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            break;
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if line.to_lowercase().starts_with("icy-metadata:") && line.ends_with('1') {
+            icy = true;
+        }
+    }
+    let mut header = format!(
+        "HTTP/1.0 200 OK\r\nContent-Type: {}\r\nicy-name: flac_music test\r\n",
+        content_type
+    );
+    if icy {
+        header.push_str(&format!("icy-metaint: {}\r\n", META_INT));
+    }
+    header.push_str("\r\n");
+    stream.write_all(header.as_bytes())?;
+
+    let started = Instant::now();
+    let mut offset = 0;
+    let mut blocks = 0;
+    // 循环发送文件内容
+    loop {
+        let mut block = Vec::with_capacity(META_INT);
+        while block.len() < META_INT {
+            let n = (META_INT - block.len()).min(data.len() - offset);
+            block.extend_from_slice(&data[offset..offset + n]);
+            offset = (offset + n) % data.len();
+        }
+        stream.write_all(&block)?;
+        if icy {
+            let title = format!("Test Artist - Track {}", blocks / BLOCKS_PER_TRACK + 1);
+            stream.write_all(&metadata(&title))?;
+        }
+        blocks += 1;
+        if let Some(d) = drop_after {
+            if started.elapsed() >= d {
+                println!("模拟断线");
+                return Ok(());
+            }
+        }
+        thread::sleep(BLOCK_INTERVAL);
+    }
+}
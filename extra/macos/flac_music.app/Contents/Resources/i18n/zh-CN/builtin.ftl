@@ -10,3 +10,78 @@ Pause = 暂停
 Stop = 停止
 File = 文件
 Import = 导入
+Search = 搜索
+Genre = 流派
+Year = 年代
+Folder = 文件夹
+Play Next = 下一首播放
+Queue = 播放队列
+Clear = 清空
+Resume on launch = 启动时继续播放
+Output device = 输出设备
+Refresh = 刷新
+Bit-perfect = 原始格式输出
+Resampler = 重采样
+Broken only = 只看无法播放
+Radio = 网络电台
+Add = 添加
+Podcasts = 播客
+Subscribe = 订阅
+Auto-delete played = 自动删除已播放
+Download = 下载
+Played = 已播放
+Lyrics = 歌词
+Edit lyrics = 编辑歌词
+Load text = 导入文本
+Tap = 打点
+Preview = 预览
+Save LRC = 保存 LRC
+Embed = 写入文件
+Done = 完成
+Select all = 全选
+Clear selection = 取消选择
+Edit tags = 编辑标签
+Album artist = 专辑艺人
+Track = 音轨号
+Disc = 碟号
+Keep existing = 保留原值
+Save = 保存
+Cancel = 取消
+Rename files = 重命名文件
+Pattern = 规则
+Apply = 执行
+Undo = 撤销
+Close = 关闭
+Infer tags = 推断标签
+Write tags = 写入标签
+Save as UTF-8 = 转换为 UTF-8
+Find duplicates = 查找重复
+Scan = 扫描
+Keep = 保留
+Keep checked = 保留勾选的版本
+Match album = 匹配专辑
+Export = 导出
+Export to = 导出目录
+Format = 格式
+Sample rate = 采样率
+Bit depth = 位深
+Same as source = 和原文件相同
+Export hint = 勾选歌曲后导出，没有勾选时导出当前列表中的所有歌曲。目标文件已存在时跳过
+Sync = 同步
+Sync to = 同步目录
+Playlist name = 播放列表名称
+Save as playlist = 保存为播放列表
+Delete = 删除
+Copy all = 全部复制
+Convert lossless = 转换无损文件
+Convert all = 全部转换
+Sync convert hint = 转换时使用导出窗口中选择的格式、采样率和位深。只删除以前同步过的文件
+Properties = 属性
+Tags = 标签
+Pictures = 内嵌图片
+MD5 = MD5
+Verify MD5 = 解码校验 MD5
+Verify library = 校验资料库
+Verify = 校验
+Show damaged = 只显示有问题的歌曲
+Damaged only = 只看校验有问题
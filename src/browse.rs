@@ -0,0 +1,172 @@
+use crate::Song;
+use druid::im::{vector, Vector};
+use druid::{Data, Lens};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+// 资料库浏览方式
+#[derive(Clone, Data, PartialEq, Debug)]
+pub enum BrowseMode {
+    Artist,
+    Album,
+    Genre,
+    Year,
+    Folder,
+}
+
+// 浏览面板中的一个节点，level 为生成该节点时所在的路径深度
+#[derive(Clone, Data, Lens, Default, Debug)]
+pub struct BrowseNode {
+    pub label: String,
+    pub key: String,
+    pub count: usize,
+    pub cover: String,
+    pub level: usize,
+}
+
+const UNKNOWN: &str = "Unknown";
+
+fn or_unknown(s: &str) -> String {
+    if s.trim().is_empty() {
+        UNKNOWN.to_owned()
+    } else {
+        s.trim().to_owned()
+    }
+}
+
+// 从日期标签中取出年份，例如 1959-08-17 -> 1959
+pub fn year_of(song: &Song) -> String {
+    let year: String = song.date.chars().take(4).collect();
+    if year.len() == 4 && year.chars().all(|c| c.is_ascii_digit()) {
+        year
+    } else {
+        UNKNOWN.to_owned()
+    }
+}
+
+fn decade_of(song: &Song) -> String {
+    let year = year_of(song);
+    if year == UNKNOWN {
+        year
+    } else {
+        format!("{}0s", &year[..3])
+    }
+}
+
+// 歌曲文件是否在目录 dir 之下(包括子目录)
+fn in_dir(song: &Song, dir: &str) -> bool {
+    Path::new(&song.file).starts_with(dir)
+}
+
+// 在歌曲所在目录查找常见的封面图片文件
+pub fn find_cover(song: &Song) -> String {
+    let names = [
+        "cover.jpg",
+        "cover.png",
+        "folder.jpg",
+        "folder.png",
+        "front.jpg",
+        "front.png",
+    ];
+    if let Some(dir) = Path::new(&song.file).parent() {
+        for name in names {
+            let p = dir.join(name);
+            if p.is_file() {
+                return p.display().to_string();
+            }
+        }
+    }
+    "".to_owned()
+}
+
+// 路径 path 选中的歌曲
+pub fn songs_at(songs: &Vector<Song>, mode: &BrowseMode, path: &Vector<String>) -> Vector<Song> {
+    songs
+        .iter()
+        .filter(|s| match mode {
+            BrowseMode::Artist => {
+                path.get(0).map_or(true, |a| or_unknown(&s.artist) == *a)
+                    && path.get(1).map_or(true, |b| or_unknown(&s.album) == *b)
+            }
+            BrowseMode::Album => path.get(0).map_or(true, |a| or_unknown(&s.album) == *a),
+            BrowseMode::Genre => path.get(0).map_or(true, |g| or_unknown(&s.genre) == *g),
+            BrowseMode::Year => {
+                path.get(0).map_or(true, |d| decade_of(s) == *d)
+                    && path.get(1).map_or(true, |y| year_of(s) == *y)
+            }
+            BrowseMode::Folder => path.last().map_or(true, |d| in_dir(s, d)),
+        })
+        .cloned()
+        .collect()
+}
+
+// 路径 path 下一级的节点列表
+pub fn nodes_at(
+    songs: &Vector<Song>,
+    mode: &BrowseMode,
+    path: &Vector<String>,
+    roots: &Vector<String>,
+) -> Vector<BrowseNode> {
+    let level = path.len();
+    let selected = songs_at(songs, mode, path);
+    // key -> (数量, 封面)
+    let mut groups: BTreeMap<String, (usize, String)> = BTreeMap::new();
+    let mut add = |key: String, song: &Song| {
+        let entry = groups.entry(key).or_insert((0, "".to_owned()));
+        entry.0 += 1;
+        if entry.1.is_empty() && *mode == BrowseMode::Album {
+            entry.1 = find_cover(song);
+        }
+    };
+    match (mode, level) {
+        (BrowseMode::Artist, 0) => selected.iter().for_each(|s| add(or_unknown(&s.artist), s)),
+        (BrowseMode::Artist, 1) => selected.iter().for_each(|s| add(or_unknown(&s.album), s)),
+        (BrowseMode::Album, 0) => selected.iter().for_each(|s| add(or_unknown(&s.album), s)),
+        (BrowseMode::Genre, 0) => selected.iter().for_each(|s| add(or_unknown(&s.genre), s)),
+        (BrowseMode::Year, 0) => selected.iter().for_each(|s| add(decade_of(s), s)),
+        (BrowseMode::Year, 1) => selected.iter().for_each(|s| add(year_of(s), s)),
+        (BrowseMode::Folder, 0) => {
+            for root in roots.iter() {
+                groups.insert(
+                    root.to_owned(),
+                    (
+                        songs.iter().filter(|s| in_dir(s, root)).count(),
+                        "".to_owned(),
+                    ),
+                );
+            }
+        }
+        (BrowseMode::Folder, _) => {
+            // 只列出直接子目录，歌曲数包括子目录下所有歌曲
+            let dir = Path::new(path.last().unwrap());
+            for s in selected.iter() {
+                if let Ok(rest) = Path::new(&s.file).strip_prefix(dir) {
+                    let mut parts = rest.components();
+                    if let (Some(first), Some(_)) = (parts.next(), parts.next()) {
+                        add(dir.join(first).display().to_string(), s);
+                    }
+                }
+            }
+        }
+        _ => {}
+    }
+
+    let mut nodes = vector![];
+    for (key, (count, cover)) in groups {
+        let label = match mode {
+            BrowseMode::Folder if level > 0 => Path::new(&key)
+                .file_name()
+                .map(|n| n.to_string_lossy().to_string())
+                .unwrap_or_else(|| key.to_owned()),
+            _ => key.to_owned(),
+        };
+        nodes.push_back(BrowseNode {
+            label,
+            key,
+            count,
+            cover,
+            level,
+        });
+    }
+    nodes
+}
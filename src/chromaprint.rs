@@ -0,0 +1,218 @@
+use crate::transcode;
+use std::fs;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// Chromaprint 压缩格式：每个值和前一个值异或后，记录置位的位置差，
+// 位置差用 3 位保存，大于等于 7 的部分另外用 5 位保存
+const NORMAL_BITS: usize = 3;
+const EXCEPTION_BITS: usize = 5;
+const MAX_NORMAL: u8 = 7;
+
+// fpcalc 和 AcoustID 使用的 base64 字母表，不带填充
+const BASE64: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+// 临时文件编号，多个线程同时计算时不冲突
+static NEXT_TMP: AtomicUsize = AtomicUsize::new(0);
+
+fn base64_decode(text: &str) -> Option<Vec<u8>> {
+    let mut bytes = vec![];
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    for c in text.trim_end_matches('=').bytes() {
+        // 也接受标准 base64 的 + 和 /
+        let value = match c {
+            b'+' => 62,
+            b'/' => 63,
+            _ => BASE64.iter().position(|b| *b == c)? as u32,
+        };
+        buffer = (buffer << 6) | value;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+            buffer &= (1 << bits) - 1;
+        }
+    }
+    Some(bytes)
+}
+
+// 按低位在前的顺序读取 count 个 width 位的值
+fn unpack(data: &[u8], width: usize, count: usize) -> Option<Vec<u8>> {
+    (0..count)
+        .map(|i| {
+            let mut value = 0u8;
+            for bit in 0..width {
+                let pos = i * width + bit;
+                let byte = data.get(pos / 8)?;
+                value |= ((byte >> (pos % 8)) & 1) << bit;
+            }
+            Some(value)
+        })
+        .collect()
+}
+
+// 解压 fpcalc 输出的压缩指纹: 算法(1 字节) 数量(3 字节) 3 位的值 5 位的值
+pub fn decompress(data: &[u8]) -> Option<Vec<u32>> {
+    if data.len() < 4 {
+        return None;
+    }
+    let count = (data[1] as usize) << 16 | (data[2] as usize) << 8 | data[3] as usize;
+    if count == 0 {
+        return Some(vec![]);
+    }
+    let body = &data[4..];
+    // 每个值以 0 结束，找到 count 个 0 为止
+    let mut values = unpack(body, NORMAL_BITS, body.len() * 8 / NORMAL_BITS)?;
+    let mut ends = 0;
+    let len = values.iter().position(|v| {
+        if *v == 0 {
+            ends += 1;
+        }
+        ends == count
+    });
+    values.truncate(len.map_or(0, |l| l + 1));
+    if ends != count {
+        return None;
+    }
+    let exceptions = values.iter().filter(|v| **v == MAX_NORMAL).count();
+    let offset = (values.len() * NORMAL_BITS).div_ceil(8);
+    let extra = unpack(body.get(offset..)?, EXCEPTION_BITS, exceptions)?;
+    let mut extra = extra.into_iter();
+    let mut print: Vec<u32> = Vec::with_capacity(count);
+    let (mut value, mut last_bit) = (0u32, 0u32);
+    for v in values {
+        if v == 0 {
+            let previous = print.last().copied().unwrap_or(0);
+            print.push(value ^ previous);
+            value = 0;
+            last_bit = 0;
+            continue;
+        }
+        let mut delta = u32::from(v);
+        if v == MAX_NORMAL {
+            delta += u32::from(extra.next()?);
+        }
+        last_bit += delta;
+        if last_bit > 32 {
+            return None;
+        }
+        value |= 1 << (last_bit - 1);
+    }
+    Some(print)
+}
+
+// fpcalc 的 FINGERPRINT 和 AcoustID 中保存的指纹
+pub fn decode(text: &str) -> Option<Vec<u32>> {
+    decompress(&base64_decode(text.trim())?)
+}
+
+// 计算文件的 Chromaprint 指纹，和 fpcalc 的结果一致
+pub fn compute(file: &str) -> Result<Vec<u32>, String> {
+    let tmp = std::env::temp_dir().join(format!(
+        "flac_music_{}_{}.chromaprint",
+        std::process::id(),
+        NEXT_TMP.fetch_add(1, Ordering::Relaxed)
+    ));
+    let result =
+        transcode::chromaprint(file, &tmp).and_then(|_| fs::read(&tmp).map_err(|e| e.to_string()));
+    let _ = fs::remove_file(&tmp);
+    // 原始格式为本机字节序的 32 位整数
+    let print: Vec<u32> = result?
+        .chunks_exact(4)
+        .map(|c| u32::from_ne_bytes([c[0], c[1], c[2], c[3]]))
+        .collect();
+    if print.is_empty() {
+        return Err("音频太短".to_owned());
+    }
+    Ok(print)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 和 Chromaprint 的 FingerprintCompressor 相同的压缩方式
+    fn compress(print: &[u32], algorithm: u8) -> Vec<u8> {
+        let mut values = vec![];
+        for (i, v) in print.iter().enumerate() {
+            let mut x = if i > 0 { v ^ print[i - 1] } else { *v };
+            let (mut bit, mut last_bit) = (1, 0);
+            while x != 0 {
+                if x & 1 != 0 {
+                    values.push(bit - last_bit);
+                    last_bit = bit;
+                }
+                x >>= 1;
+                bit += 1;
+            }
+            values.push(0);
+        }
+        let pack = |values: &[u32], width: usize| {
+            let mut bytes = vec![0u8; (values.len() * width).div_ceil(8)];
+            for (i, v) in values.iter().enumerate() {
+                for bit in 0..width {
+                    if v >> bit & 1 != 0 {
+                        let pos = i * width + bit;
+                        bytes[pos / 8] |= 1 << (pos % 8);
+                    }
+                }
+            }
+            bytes
+        };
+        let normal: Vec<u32> = values.iter().map(|v| (*v).min(7)).collect();
+        let extra: Vec<u32> = values.iter().filter(|v| **v >= 7).map(|v| v - 7).collect();
+        let n = print.len();
+        let mut data = vec![algorithm, (n >> 16) as u8, (n >> 8) as u8, n as u8];
+        data.extend(pack(&normal, NORMAL_BITS));
+        data.extend(pack(&extra, EXCEPTION_BITS));
+        data
+    }
+
+    #[test]
+    fn chromaprint_vectors() {
+        // Chromaprint 的 FingerprintCompressor 测试数据
+        assert_eq!(decompress(b"\0\0\0\x01\x01"), Some(vec![1]));
+        assert_eq!(decompress(b"\0\0\0\x01\x49\0"), Some(vec![7]));
+        assert_eq!(decompress(b"\0\0\0\x01\x07\0"), Some(vec![1 << 6]));
+        assert_eq!(decompress(b"\0\0\0\x01\x07\x02"), Some(vec![1 << 8]));
+        assert_eq!(decompress(b"\0\0\0\x02\x41\0"), Some(vec![1, 0]));
+        assert_eq!(decompress(b"\0\0\0\x02\x01\0"), Some(vec![1, 1]));
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut x = 0x1234_5678u32;
+        let print: Vec<u32> = (0..1000)
+            .map(|i| {
+                x ^= x << 13;
+                x ^= x >> 17;
+                x ^= x << 5;
+                if i % 7 == 0 {
+                    x | 0x8000_0001
+                } else {
+                    x
+                }
+            })
+            .collect();
+        let data = compress(&print, 1);
+        assert_eq!(decompress(&data), Some(print.to_owned()));
+        let text: String = data
+            .chunks(3)
+            .flat_map(|c| {
+                let n = c.len();
+                let v = c.iter().fold(0u32, |acc, b| acc << 8 | *b as u32) << (8 * (3 - n));
+                (0..=n).map(move |i| BASE64[(v >> (18 - 6 * i) & 63) as usize] as char)
+            })
+            .collect();
+        assert_eq!(decode(&text), Some(print));
+    }
+
+    #[test]
+    fn invalid() {
+        assert_eq!(decompress(b"\0\0"), None);
+        // 数量比数据多
+        assert_eq!(decompress(b"\0\0\0\x03\x01"), None);
+        assert_eq!(decode("not base64!"), None);
+        assert_eq!(decompress(b"\0\0\0\0"), Some(vec![]));
+    }
+}
@@ -0,0 +1,533 @@
+use crate::sync::SyncTranscode;
+use crate::transcode::ExportFormat;
+use crate::{encoding, infer};
+use druid::{Color, ExtEventSink, KbKey, KeyEvent, Modifiers, Selector, Target};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread::{sleep, spawn};
+use std::time::{Duration, SystemTime};
+
+// 用户配置文件，默认位置 ~/.config/flac_music/config.toml，可用 --config 指定。
+// 字段说明见 extra/config.example.toml
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub library: LibraryConfig,
+    pub audio: AudioConfig,
+    pub window: WindowConfig,
+    pub theme: ThemeConfig,
+    pub keys: KeysConfig,
+    pub export: ExportConfig,
+    pub sync: SyncConfig,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct LibraryConfig {
+    // 启动时导入的音乐目录
+    pub roots: Vec<String>,
+    // 导入的音乐文件扩展名
+    pub extensions: Vec<String>,
+    // 按标签重命名文件的默认规则
+    pub rename_pattern: String,
+    // 导入时按文件名推断缺少的标签的规则，为空时不推断
+    pub filename_pattern: String,
+    // MP3 中按 Latin-1 保存的旧标签实际使用的编码，encodings 可以为每个音乐目录单独设置
+    pub encoding: String,
+    pub encodings: HashMap<String, String>,
+    // 本地 MusicBrainz JSON 数据包所在目录，用于离线匹配专辑
+    pub musicbrainz_dump: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct AudioConfig {
+    // 输出设备名称，为空时使用系统默认设备
+    pub device: String,
+    // 没有保存过音量时的默认音量，0.0 - 1.0
+    pub volume: f64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct WindowConfig {
+    pub width: f64,
+    pub height: f64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ThemeConfig {
+    // 颜色使用 #rrggbb 格式
+    pub background: String,
+    pub text: String,
+    pub button: String,
+    pub text_size: f64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct ExportConfig {
+    // 默认的导出目录
+    pub dir: String,
+    pub format: ExportFormat,
+    // 0 表示和原文件相同
+    pub sample_rate: u32,
+    pub bit_depth: u16,
+    // 并行转换的线程数，0 表示和 CPU 核数相同
+    pub workers: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct SyncConfig {
+    // 默认的同步目录，例如 U 盘或 SD 卡
+    pub dir: String,
+    // 什么时候转换格式，转换时使用 [export] 中的格式
+    pub transcode: SyncTranscode,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct KeysConfig {
+    // 快捷键格式: "space", "ctrl+right", "shift+n"
+    pub play_pause: String,
+    pub stop: String,
+    pub next: String,
+    pub prev: String,
+    pub volume_up: String,
+    pub volume_down: String,
+    // 歌词编辑时记录当前行的时间
+    pub tap_sync: String,
+}
+
+impl Default for LibraryConfig {
+    fn default() -> Self {
+        LibraryConfig {
+            roots: vec![],
+            extensions: [
+                ".flac", ".mp3", ".wav", ".m4a", ".ogg", ".opus", ".ape", ".wv", ".aiff", ".aif",
+                ".dsf", ".dff", ".tta", ".mka",
+            ]
+            .iter()
+            .map(|s| s.to_string())
+            .collect(),
+            rename_pattern: "{albumartist}/{year} - {album}/{disc}-{track:02} {title}.{ext}"
+                .to_owned(),
+            filename_pattern: "".to_owned(),
+            encoding: "auto".to_owned(),
+            encodings: HashMap::new(),
+            musicbrainz_dump: "".to_owned(),
+        }
+    }
+}
+
+impl Default for AudioConfig {
+    fn default() -> Self {
+        AudioConfig {
+            device: "".to_owned(),
+            volume: 0.3,
+        }
+    }
+}
+
+impl Default for WindowConfig {
+    fn default() -> Self {
+        WindowConfig {
+            width: 1200.0,
+            height: 600.0,
+        }
+    }
+}
+
+impl Default for ThemeConfig {
+    fn default() -> Self {
+        ThemeConfig {
+            background: "#ffffff".to_owned(),
+            text: "#000000".to_owned(),
+            button: "#ffffff".to_owned(),
+            text_size: 13.0,
+        }
+    }
+}
+
+impl Default for KeysConfig {
+    fn default() -> Self {
+        KeysConfig {
+            play_pause: "space".to_owned(),
+            stop: "s".to_owned(),
+            next: "ctrl+right".to_owned(),
+            prev: "ctrl+left".to_owned(),
+            volume_up: "ctrl+up".to_owned(),
+            volume_down: "ctrl+down".to_owned(),
+            tap_sync: "enter".to_owned(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(PathBuf, std::io::Error),
+    Parse(PathBuf, toml::de::Error),
+    Invalid(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Io(path, e) => write!(f, "无法读取配置文件 {}: {}", path.display(), e),
+            ConfigError::Parse(path, e) => write!(f, "配置文件 {} 格式错误: {}", path.display(), e),
+            ConfigError::Invalid(msg) => write!(f, "配置无效: {}", msg),
+        }
+    }
+}
+
+// 快捷键对应的操作
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Action {
+    PlayPause,
+    Stop,
+    Next,
+    Prev,
+    VolumeUp,
+    VolumeDown,
+    TapSync,
+}
+
+// 解析后的快捷键
+#[derive(Clone, Debug, PartialEq)]
+pub struct KeyBinding {
+    mods: Modifiers,
+    key: KbKey,
+}
+
+impl KeyBinding {
+    pub fn parse(s: &str) -> Result<KeyBinding, String> {
+        let mut mods = Modifiers::empty();
+        let parts: Vec<String> = s.split('+').map(|p| p.trim().to_lowercase()).collect();
+        let (name, mod_names) = match parts.split_last() {
+            Some((name, mod_names)) if !name.is_empty() => (name, mod_names),
+            _ => return Err(format!("快捷键 \"{}\" 为空", s)),
+        };
+        for m in mod_names {
+            mods |= match m.as_str() {
+                "ctrl" | "control" => Modifiers::CONTROL,
+                "shift" => Modifiers::SHIFT,
+                "alt" | "option" => Modifiers::ALT,
+                "cmd" | "meta" | "super" => Modifiers::META,
+                other => return Err(format!("快捷键 \"{}\" 中的修饰键 \"{}\" 无效", s, other)),
+            };
+        }
+        let key = match name.as_str() {
+            "space" => KbKey::Character(" ".to_owned()),
+            "enter" | "return" => KbKey::Enter,
+            "tab" => KbKey::Tab,
+            "left" => KbKey::ArrowLeft,
+            "right" => KbKey::ArrowRight,
+            "up" => KbKey::ArrowUp,
+            "down" => KbKey::ArrowDown,
+            "home" => KbKey::Home,
+            "end" => KbKey::End,
+            c if c.chars().count() == 1 => KbKey::Character(c.to_owned()),
+            other => return Err(format!("快捷键 \"{}\" 中的按键 \"{}\" 无效", s, other)),
+        };
+        Ok(KeyBinding { mods, key })
+    }
+
+    pub fn matches(&self, event: &KeyEvent) -> bool {
+        let key = match &event.key {
+            KbKey::Character(c) => KbKey::Character(c.to_lowercase()),
+            other => other.to_owned(),
+        };
+        // 字母键按下 shift 时 key 为大写，这里统一转成小写比较，并忽略 CapsLock 等锁定键
+        let mods =
+            event.mods & (Modifiers::CONTROL | Modifiers::SHIFT | Modifiers::ALT | Modifiers::META);
+        key == self.key && mods == self.mods
+    }
+}
+
+impl KeysConfig {
+    fn bindings(&self) -> Vec<(Action, &String)> {
+        vec![
+            (Action::PlayPause, &self.play_pause),
+            (Action::Stop, &self.stop),
+            (Action::Next, &self.next),
+            (Action::Prev, &self.prev),
+            (Action::VolumeUp, &self.volume_up),
+            (Action::VolumeDown, &self.volume_down),
+            (Action::TapSync, &self.tap_sync),
+        ]
+    }
+
+    // 查找按键对应的操作，未配置的快捷键(空字符串)忽略
+    pub fn action_for(&self, event: &KeyEvent) -> Option<Action> {
+        self.bindings()
+            .into_iter()
+            .filter(|(_, s)| !s.is_empty())
+            .find(|(_, s)| KeyBinding::parse(s).map_or(false, |b| b.matches(event)))
+            .map(|(action, _)| action)
+    }
+}
+
+pub fn parse_color(s: &str) -> Result<Color, String> {
+    Color::from_hex_str(s).map_err(|_| format!("颜色 \"{}\" 无效，应为 #rrggbb 格式", s))
+}
+
+impl Config {
+    // 检查配置值，返回所有错误
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let mut errors = vec![];
+        if !(0.0..=1.0).contains(&self.audio.volume) {
+            errors.push(format!(
+                "audio.volume = {} 超出范围 0.0 - 1.0",
+                self.audio.volume
+            ));
+        }
+        for ext in &self.library.extensions {
+            if !ext.starts_with('.') || ext.len() < 2 {
+                errors.push(format!("library.extensions 中的 \"{}\" 应以 . 开头", ext));
+            }
+        }
+        if let Err(e) = infer::validate(&self.library.filename_pattern) {
+            errors.push(format!("library.filename_pattern: {}", e));
+        }
+        for name in std::iter::once(&self.library.encoding).chain(self.library.encodings.values()) {
+            if !encoding::NAMES.contains(&name.as_str()) {
+                errors.push(format!(
+                    "library.encoding \"{}\" 无效，可以是 {}",
+                    name,
+                    encoding::NAMES.join(", ")
+                ));
+            }
+        }
+        if ![0, 44100, 48000, 88200, 96000, 176400, 192000].contains(&self.export.sample_rate) {
+            errors.push(format!(
+                "export.sample_rate = {} 无效，可以是 0, 44100, 48000, 88200, 96000, 176400, 192000",
+                self.export.sample_rate
+            ));
+        }
+        if ![0, 16, 24].contains(&self.export.bit_depth) {
+            errors.push(format!(
+                "export.bit_depth = {} 无效，可以是 0, 16, 24",
+                self.export.bit_depth
+            ));
+        }
+        if self.window.width < 200.0 || self.window.height < 200.0 {
+            errors.push("window.width 和 window.height 不能小于 200".to_owned());
+        }
+        for color in [&self.theme.background, &self.theme.text, &self.theme.button] {
+            if let Err(e) = parse_color(color) {
+                errors.push(format!("theme: {}", e));
+            }
+        }
+        if self.theme.text_size <= 0.0 {
+            errors.push("theme.text_size 必须大于 0".to_owned());
+        }
+        // 同一个快捷键只能对应一个操作
+        let mut seen: Vec<(KeyBinding, &String)> = vec![];
+        for (_, binding) in self.keys.bindings() {
+            if binding.is_empty() {
+                continue;
+            }
+            match KeyBinding::parse(binding) {
+                Ok(parsed) => match seen.iter().find(|(b, _)| *b == parsed) {
+                    Some((_, other)) => errors.push(format!(
+                        "keys: \"{}\" 和 \"{}\" 是同一个快捷键",
+                        other, binding
+                    )),
+                    None => seen.push((parsed, binding)),
+                },
+                Err(e) => errors.push(format!("keys: {}", e)),
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(ConfigError::Invalid(errors.join("; ")))
+        }
+    }
+
+    // 文件所在音乐目录设置的编码，没有单独设置时使用 library.encoding
+    pub fn encoding_for(&self, f: &str) -> &str {
+        self.library
+            .encodings
+            .iter()
+            .filter(|(root, _)| Path::new(f).starts_with(root))
+            .max_by_key(|(root, _)| root.len())
+            .map_or(&self.library.encoding, |(_, name)| name)
+    }
+
+    // 扩展名是否在支持列表中，不区分大小写
+    pub fn is_music_ext(&self, f: &str) -> bool {
+        let f = f.to_lowercase();
+        self.library
+            .extensions
+            .iter()
+            .any(|ext| f.ends_with(&ext.to_lowercase()))
+    }
+}
+
+// 命令行 --config <path> 或 --config=<path> 指定配置文件，否则使用默认位置
+pub fn config_path() -> PathBuf {
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--config" {
+            if let Some(p) = args.next() {
+                return PathBuf::from(p);
+            }
+        } else if let Some(p) = arg.strip_prefix("--config=") {
+            return PathBuf::from(p);
+        }
+    }
+    dirs::config_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join("flac_music")
+        .join("config.toml")
+}
+
+// 配置文件不存在时使用默认配置
+pub fn load(path: &Path) -> Result<Config, ConfigError> {
+    if !path.exists() {
+        return Ok(Config::default());
+    }
+    let text = fs::read_to_string(path).map_err(|e| ConfigError::Io(path.to_owned(), e))?;
+    let config: Config =
+        toml::from_str(&text).map_err(|e| ConfigError::Parse(path.to_owned(), e))?;
+    config.validate()?;
+    Ok(config)
+}
+
+pub fn modified_time(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+// 配置文件重新加载的结果
+pub const CONFIG_RELOADED: Selector<Result<Config, String>> =
+    Selector::new("flac-music.config-reloaded");
+
+// 后台线程每两秒检查配置文件修改时间，修改后重新加载并通知界面
+pub fn watch(path: PathBuf, sink: ExtEventSink) {
+    spawn(move || {
+        let mut last = modified_time(&path);
+        loop {
+            sleep(Duration::from_secs(2));
+            let now = modified_time(&path);
+            if now == last {
+                continue;
+            }
+            last = now;
+            let result = load(&path).map_err(|e| e.to_string());
+            if sink
+                .submit_command(CONFIG_RELOADED, result, Target::Auto)
+                .is_err()
+            {
+                // 程序已退出
+                break;
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn binding(mods: Modifiers, key: KbKey) -> KeyBinding {
+        KeyBinding { mods, key }
+    }
+
+    fn invalid(config: &Config) -> String {
+        match config.validate() {
+            Err(ConfigError::Invalid(msg)) => msg,
+            other => panic!("expected invalid config, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn parse_bindings() {
+        assert_eq!(
+            KeyBinding::parse("space"),
+            Ok(binding(
+                Modifiers::empty(),
+                KbKey::Character(" ".to_owned())
+            ))
+        );
+        assert_eq!(
+            KeyBinding::parse("Ctrl + Right"),
+            Ok(binding(Modifiers::CONTROL, KbKey::ArrowRight))
+        );
+        assert_eq!(
+            KeyBinding::parse("shift+alt+P"),
+            Ok(binding(
+                Modifiers::SHIFT | Modifiers::ALT,
+                KbKey::Character("p".to_owned())
+            ))
+        );
+        assert_eq!(
+            KeyBinding::parse("cmd+return"),
+            Ok(binding(Modifiers::META, KbKey::Enter))
+        );
+        // 修饰键的顺序不影响结果
+        assert_eq!(
+            KeyBinding::parse("ctrl+shift+x"),
+            KeyBinding::parse("shift+control+x")
+        );
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert!(KeyBinding::parse("").is_err());
+        assert!(KeyBinding::parse("ctrl+").is_err());
+        let e = KeyBinding::parse("hyper+x").unwrap_err();
+        assert!(e.contains("\"hyper\""), "{}", e);
+        let e = KeyBinding::parse("ctrl+f13").unwrap_err();
+        assert!(e.contains("\"f13\""), "{}", e);
+    }
+
+    #[test]
+    fn default_is_valid() {
+        assert!(Config::default().validate().is_ok());
+        // 空字符串表示不使用该快捷键
+        let mut config = Config::default();
+        config.keys.stop = "".to_owned();
+        config.keys.tap_sync = "".to_owned();
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn invalid_keys() {
+        let mut config = Config::default();
+        config.keys.stop = "meta+nope".to_owned();
+        config.keys.next = "fn+n".to_owned();
+        let msg = invalid(&config);
+        assert!(msg.contains("\"nope\""), "{}", msg);
+        assert!(msg.contains("\"fn\""), "{}", msg);
+    }
+
+    #[test]
+    fn duplicate_bindings() {
+        let mut config = Config::default();
+        config.keys.stop = "Ctrl+Right".to_owned();
+        let msg = invalid(&config);
+        assert!(msg.contains("\"Ctrl+Right\" 和 \"ctrl+right\""), "{}", msg);
+        let mut config = Config::default();
+        config.keys.stop = "shift+ctrl+s".to_owned();
+        config.keys.tap_sync = "ctrl+shift+S".to_owned();
+        assert!(invalid(&config).contains("是同一个快捷键"));
+    }
+
+    #[test]
+    fn other_values() {
+        let mut config = Config::default();
+        config.audio.volume = 1.5;
+        config.export.bit_depth = 12;
+        config.theme.text = "red".to_owned();
+        let msg = invalid(&config);
+        assert!(msg.contains("audio.volume"), "{}", msg);
+        assert!(msg.contains("export.bit_depth"), "{}", msg);
+        assert!(msg.contains("\"red\""), "{}", msg);
+    }
+}
@@ -0,0 +1,146 @@
+use encoding_rs::{Encoding, BIG5, GB18030, GBK, SHIFT_JIS};
+
+// 配置中可以使用的编码，auto 为自动识别，none 为不转换
+pub const NAMES: [&str; 6] = ["auto", "none", "gbk", "gb18030", "big5", "shift_jis"];
+
+fn by_name(name: &str) -> Option<(&'static Encoding, &'static str)> {
+    match name {
+        "gbk" => Some((GBK, "gbk")),
+        "gb18030" => Some((GB18030, "gb18030")),
+        "big5" => Some((BIG5, "big5")),
+        "shift_jis" => Some((SHIFT_JIS, "shift_jis")),
+        _ => None,
+    }
+}
+
+// ffmpeg 按 Latin-1 读出的 ID3v1 和 ID3v2.3 标签，每个字符对应原来的一个字节。
+// 只有包含 0x80 以上的字符时才可能是乱码
+fn latin1_bytes(value: &str) -> Option<Vec<u8>> {
+    if value.chars().any(|c| c as u32 > 0xff) || value.is_ascii() {
+        return None;
+    }
+    Some(value.chars().map(|c| c as u8).collect())
+}
+
+fn is_cjk(c: char) -> bool {
+    matches!(c as u32,
+        0x3040..=0x30ff | 0x3400..=0x4dbf | 0x4e00..=0x9fff | 0xac00..=0xd7af | 0xff01..=0xff5e)
+}
+
+fn is_kana(c: char) -> bool {
+    matches!(c as u32, 0x3040..=0x30ff)
+}
+
+// 双字节编码的第二个字节是否吃掉了 ASCII 字符，正常的 Latin-1 文本如 "Rós" 会这样被误识别
+fn eats_ascii(bytes: &[u8], decoded: &str) -> bool {
+    bytes.iter().filter(|b| b.is_ascii()).count()
+        != decoded.chars().filter(|c| c.is_ascii()).count()
+}
+
+fn decode(encoding: &'static Encoding, bytes: &[u8]) -> Option<String> {
+    encoding
+        .decode_without_bom_handling_and_without_replacement(bytes)
+        .map(|s| s.to_string())
+}
+
+// 自动识别：UTF-8 被当作 Latin-1 读出的情况直接还原，否则依次尝试 Shift-JIS(含假名时)，GBK 和 Big5。
+// 自动识别时要求所有非 ASCII 字符都是中日韩文字，避免把正常的西文标签转成乱码
+fn detect(bytes: &[u8]) -> Option<(String, &'static str)> {
+    if let Ok(s) = std::str::from_utf8(bytes) {
+        return Some((s.to_owned(), "utf-8"));
+    }
+    let plausible =
+        |s: &str| !eats_ascii(bytes, s) && s.chars().filter(|c| !c.is_ascii()).all(is_cjk);
+    if let Some(s) = decode(SHIFT_JIS, bytes) {
+        if plausible(&s) && s.chars().any(is_kana) {
+            return Some((s, "shift_jis"));
+        }
+    }
+    for (encoding, name) in [(GBK, "gbk"), (BIG5, "big5"), (GB18030, "gb18030")] {
+        if let Some(s) = decode(encoding, bytes) {
+            if plausible(&s) {
+                return Some((s, name));
+            }
+        }
+    }
+    None
+}
+
+// 还原乱码的标签值，返回还原后的文本和使用的编码。preferred 为资料库设置的编码
+pub fn fix(value: &str, preferred: &str) -> Option<(String, &'static str)> {
+    if preferred == "none" {
+        return None;
+    }
+    let bytes = latin1_bytes(value)?;
+    match by_name(preferred) {
+        Some((encoding, name)) => decode(encoding, &bytes).map(|s| (s, name)),
+        None => detect(&bytes),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 模拟 ffmpeg 按 Latin-1 读出的旧标签：每个字节变成一个字符
+    fn mojibake(text: &str, encoding: &'static Encoding) -> String {
+        let (bytes, _, unmappable) = encoding.encode(text);
+        assert!(!unmappable);
+        bytes.iter().map(|b| *b as char).collect()
+    }
+
+    #[test]
+    fn auto_detects_gbk() {
+        assert_eq!(
+            fix(&mojibake("周杰伦", GBK), "auto"),
+            Some(("周杰伦".to_owned(), "gbk"))
+        );
+        assert_eq!(
+            fix(&mojibake("七里香 - 周杰伦", GBK), "auto"),
+            Some(("七里香 - 周杰伦".to_owned(), "gbk"))
+        );
+    }
+
+    #[test]
+    fn auto_detects_shift_jis_with_kana() {
+        assert_eq!(
+            fix(&mojibake("ありがとう", SHIFT_JIS), "auto"),
+            Some(("ありがとう".to_owned(), "shift_jis"))
+        );
+    }
+
+    #[test]
+    fn utf8_read_as_latin1() {
+        let bytes = "中文 Title".as_bytes();
+        let value: String = bytes.iter().map(|b| *b as char).collect();
+        assert_eq!(
+            fix(&value, "auto"),
+            Some(("中文 Title".to_owned(), "utf-8"))
+        );
+    }
+
+    #[test]
+    fn preferred_encoding() {
+        // Big5 和 GBK 的字节范围重叠，自动识别会优先当作 GBK，繁体目录需要指定编码
+        assert_eq!(
+            fix(&mojibake("周杰倫", BIG5), "big5"),
+            Some(("周杰倫".to_owned(), "big5"))
+        );
+        assert_eq!(
+            fix(&mojibake("周杰伦", GBK), "gb18030"),
+            Some(("周杰伦".to_owned(), "gb18030"))
+        );
+        assert_eq!(fix(&mojibake("周杰伦", GBK), "none"), None);
+    }
+
+    #[test]
+    fn leaves_normal_tags_alone() {
+        assert_eq!(fix("Kind of Blue", "auto"), None);
+        // 已经是正确的 Unicode
+        assert_eq!(fix("周杰伦", "auto"), None);
+        // 正常的西文 Latin-1 标签
+        assert_eq!(fix("Rós", "auto"), None);
+        assert_eq!(fix("Beyoncé", "auto"), None);
+        assert_eq!(fix("Café del Mar", "auto"), None);
+    }
+}
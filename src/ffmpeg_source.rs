@@ -0,0 +1,197 @@
+use ffmpeg::format::sample::Type as SampleType;
+use ffmpeg::format::Sample as SampleFormat;
+use ffmpeg::software::resampling;
+use ffmpeg::{codec, format, frame, media, ChannelLayout, Packet};
+use ffmpeg_next as ffmpeg;
+use rodio::Source;
+use std::ffi::{c_void, CStr, CString};
+use std::os::raw::c_char;
+use std::path::Path;
+use std::time::Duration;
+
+// 用 ffmpeg 解码的音频源，rodio(symphonia) 不支持的格式(ALAC, Opus, APE, WavPack, DSD 等)使用它播放。
+// 解码后的采样统一转换成交错的 f32，采样率和声道数保持不变。
+pub struct FfmpegSource {
+    input: format::context::Input,
+    stream_index: usize,
+    decoder: codec::decoder::Audio,
+    codec: String,
+    resampler: resampling::Context,
+    layout: ChannelLayout,
+    channels: u16,
+    rate: u32,
+    duration: Option<Duration>,
+    buffer: Vec<f32>,
+    pos: usize,
+    eof_sent: bool,
+}
+
+impl FfmpegSource {
+    pub fn new(path: &str) -> Result<FfmpegSource, ffmpeg::Error> {
+        ffmpeg::init()?;
+        let input = format::input(&Path::new(path))?;
+        FfmpegSource::from_input(input)
+    }
+
+    pub fn from_input(input: format::context::Input) -> Result<FfmpegSource, ffmpeg::Error> {
+        let stream = input
+            .streams()
+            .best(media::Type::Audio)
+            .ok_or(ffmpeg::Error::StreamNotFound)?;
+        let stream_index = stream.index();
+        let context = codec::context::Context::from_parameters(stream.parameters())?;
+        let codec = context.id().name().to_owned();
+        let decoder = context.decoder().audio()?;
+        let channels = decoder.channels();
+        // 部分格式没有声道布局，按声道数取默认布局
+        let layout = if decoder.channel_layout().is_empty() {
+            ChannelLayout::default(channels as i32)
+        } else {
+            decoder.channel_layout()
+        };
+        let rate = decoder.rate();
+        let resampler = resampling::Context::get(
+            decoder.format(),
+            layout,
+            rate,
+            SampleFormat::F32(SampleType::Packed),
+            layout,
+            rate,
+        )?;
+        let duration = if input.duration() > 0 {
+            Some(Duration::from_micros(
+                (input.duration() as f64 * 1_000_000.0 / f64::from(ffmpeg::ffi::AV_TIME_BASE))
+                    as u64,
+            ))
+        } else {
+            None
+        };
+        Ok(FfmpegSource {
+            input,
+            stream_index,
+            decoder,
+            codec,
+            resampler,
+            layout,
+            channels,
+            rate,
+            duration,
+            buffer: vec![],
+            pos: 0,
+            eof_sent: false,
+        })
+    }
+
+    pub fn codec_name(&self) -> &str {
+        &self.codec
+    }
+
+    // 网络流的 ICY 元数据，例如 StreamTitle='Artist - Title';
+    pub fn icy_metadata(&self) -> Option<String> {
+        let name = CString::new("icy_metadata_packet").ok()?;
+        unsafe {
+            let pb = (*self.input.as_ptr()).pb;
+            if pb.is_null() {
+                return None;
+            }
+            let mut value: *mut u8 = std::ptr::null_mut();
+            let ret = ffmpeg::ffi::av_opt_get(
+                pb as *mut c_void,
+                name.as_ptr(),
+                ffmpeg::ffi::AV_OPT_SEARCH_CHILDREN as i32,
+                &mut value,
+            );
+            if ret < 0 || value.is_null() {
+                return None;
+            }
+            let text = CStr::from_ptr(value as *const c_char)
+                .to_string_lossy()
+                .to_string();
+            ffmpeg::ffi::av_free(value as *mut c_void);
+            Some(text).filter(|t| !t.is_empty())
+        }
+    }
+
+    // 解码下一帧到 buffer，没有更多数据时返回 false
+    fn refill(&mut self) -> bool {
+        loop {
+            let mut decoded = frame::Audio::empty();
+            if self.decoder.receive_frame(&mut decoded).is_ok() {
+                if decoded.channel_layout().is_empty() {
+                    decoded.set_channel_layout(self.layout);
+                }
+                let mut converted = frame::Audio::empty();
+                if let Err(e) = self.resampler.run(&decoded, &mut converted) {
+                    println!("ffmpeg 采样格式转换失败: {}", e);
+                    continue;
+                }
+                let len = converted.samples() * self.channels as usize * 4;
+                self.buffer = converted.data(0)[..len]
+                    .chunks_exact(4)
+                    .map(|b| f32::from_ne_bytes([b[0], b[1], b[2], b[3]]))
+                    .collect();
+                self.pos = 0;
+                if self.buffer.is_empty() {
+                    continue;
+                }
+                return true;
+            }
+            if self.eof_sent {
+                return false;
+            }
+            let mut packet = Packet::empty();
+            match packet.read(&mut self.input) {
+                Ok(()) => {
+                    if packet.stream() == self.stream_index {
+                        // 损坏的数据包跳过，继续解码后面的数据
+                        if let Err(e) = self.decoder.send_packet(&packet) {
+                            println!("ffmpeg 解码失败: {}", e);
+                        }
+                    }
+                }
+                Err(ffmpeg::Error::Eof) => {
+                    let _ = self.decoder.send_eof();
+                    self.eof_sent = true;
+                }
+                Err(e) => {
+                    println!("ffmpeg 读取数据失败: {}", e);
+                    let _ = self.decoder.send_eof();
+                    self.eof_sent = true;
+                }
+            }
+        }
+    }
+}
+
+impl Iterator for FfmpegSource {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if self.pos >= self.buffer.len() && !self.refill() {
+            return None;
+        }
+        let sample = self.buffer[self.pos];
+        self.pos += 1;
+        Some(sample)
+    }
+}
+
+impl Source for FfmpegSource {
+    // 输出格式在整个文件中不变。
+    // 返回 Some(0) 会让 rodio 的 skip_duration 直接结束，无法跳到指定位置
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.rate
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.duration
+    }
+}
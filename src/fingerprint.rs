@@ -0,0 +1,599 @@
+use crate::ffmpeg_source::FfmpegSource;
+use crate::player;
+use crate::Song;
+use druid::im::Vector;
+use druid::{Data, ExtEventSink, Lens, Selector, Target};
+use ffmpeg_next as ffmpeg;
+use rodio::Source;
+use rustfft::num_complex::Complex;
+use rustfft::FftPlanner;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::spawn;
+use std::time::UNIX_EPOCH;
+
+// 扫描进度，(已完成, 总数)
+pub const SCAN_PROGRESS: Selector<(usize, usize)> =
+    Selector::new("flac-music.fingerprint-progress");
+// 扫描完成后的重复歌曲分组
+pub const SCAN_DONE: Selector<Vector<DuplicateGroup>> =
+    Selector::new("flac-music.fingerprint-done");
+
+// 和 Chromaprint 一样降采样到 11025Hz，只取开头两分钟，但生成的指纹和 Chromaprint 不兼容
+const RATE: u32 = 11025;
+const MAX_SECONDS: usize = 120;
+const FRAME: usize = 4096;
+const HOP: usize = FRAME / 3;
+// 忽略开头的静音，不同版本的开头静音长度经常不同
+const SILENCE: f32 = 0.01;
+// 对齐时最多错开的帧数，约 6 秒
+const MAX_OFFSET: usize = 50;
+// 相似度达到该值算作同一录音，随机的两首歌约为 0.5
+const THRESHOLD: f64 = 0.8;
+// 时长相差超过该比例的不比较
+const DURATION_TOLERANCE: f64 = 0.05;
+// 预筛选只使用高 20 位：相邻半音的比较和平滑后的变化比较稳定，
+// 低 12 位是逐帧的变化，受编码影响较大
+const INDEX_SHIFT: u32 = 12;
+// 和 AcoustID 一样先用倒排索引找出有相同值的歌曲，至少有这么多个相同值才做完整的对齐比较
+const MIN_SHARED: usize = 8;
+
+// 重复分组中的一首歌曲，keep 为要保留的版本，默认勾选音质最好的
+#[derive(Clone, Data, Lens, Debug, Default, PartialEq)]
+pub struct DuplicateItem {
+    pub file: String,
+    pub title: String,
+    pub artist: String,
+    pub quality: String,
+    pub keep: bool,
+}
+
+#[derive(Clone, Data, Lens, Debug, Default, PartialEq)]
+pub struct DuplicateGroup {
+    pub items: Vector<DuplicateItem>,
+}
+
+// 解码为 11025Hz 单声道，去掉开头的静音
+fn decode_mono(file: &str) -> Result<Vec<f32>, String> {
+    let source = FfmpegSource::new(file).map_err(|e| e.to_string())?;
+    let channels = source.channels().max(1) as usize;
+    let rate = source.sample_rate().max(1);
+    let max_frames = (rate as usize) * MAX_SECONDS;
+    let mut mono = Vec::with_capacity(max_frames);
+    let mut frame = Vec::with_capacity(channels);
+    let mut started = false;
+    for sample in source {
+        frame.push(sample);
+        if frame.len() < channels {
+            continue;
+        }
+        let value = frame.iter().sum::<f32>() / channels as f32;
+        frame.clear();
+        if !started && value.abs() < SILENCE {
+            continue;
+        }
+        started = true;
+        mono.push(value);
+        if mono.len() >= max_frames {
+            break;
+        }
+    }
+    // 线性插值降采样
+    let step = rate as f64 / RATE as f64;
+    let len = (mono.len() as f64 / step) as usize;
+    Ok((0..len)
+        .map(|i| {
+            let pos = i as f64 * step;
+            let j = pos as usize;
+            let frac = (pos - j as f64) as f32;
+            let next = mono.get(j + 1).copied().unwrap_or(mono[j]);
+            mono[j] * (1.0 - frac) + next * frac
+        })
+        .collect())
+}
+
+// 每帧的 12 个半音能量，频率范围 28Hz - 3520Hz
+fn chroma(samples: &[f32]) -> Vec<[f32; 12]> {
+    let mut planner = FftPlanner::<f32>::new();
+    let fft = planner.plan_fft_forward(FRAME);
+    let window: Vec<f32> = (0..FRAME)
+        .map(|i| 0.5 - 0.5 * (2.0 * std::f32::consts::PI * i as f32 / FRAME as f32).cos())
+        .collect();
+    // 每个频率桶对应的半音
+    let notes: Vec<Option<usize>> = (0..FRAME / 2)
+        .map(|bin| {
+            let freq = bin as f32 * RATE as f32 / FRAME as f32;
+            if !(28.0..=3520.0).contains(&freq) {
+                return None;
+            }
+            let note = 12.0 * (freq / 440.0).log2() + 69.0;
+            Some((note.round() as i64).rem_euclid(12) as usize)
+        })
+        .collect();
+    let mut frames = vec![];
+    let mut buffer = vec![Complex::new(0.0, 0.0); FRAME];
+    let mut start = 0;
+    while start + FRAME <= samples.len() {
+        for ((b, s), w) in buffer
+            .iter_mut()
+            .zip(samples[start..start + FRAME].iter())
+            .zip(window.iter())
+        {
+            *b = Complex::new(s * w, 0.0);
+        }
+        fft.process(&mut buffer);
+        let mut bins = [0.0f32; 12];
+        for (bin, note) in notes.iter().enumerate() {
+            if let Some(note) = note {
+                bins[*note] += buffer[bin].norm_sqr();
+            }
+        }
+        let total: f32 = bins.iter().sum();
+        if total > 0.0 {
+            bins.iter_mut().for_each(|b| *b /= total);
+        }
+        frames.push(bins);
+        start += HOP;
+    }
+    frames
+}
+
+// 每帧生成 32 位的指纹：半音能量随时间的变化(12 位)，相邻半音的比较(12 位)，
+// 以及前后两帧平滑后的变化(8 位)
+fn sub_fingerprints(frames: &[[f32; 12]]) -> Vec<u32> {
+    (2..frames.len())
+        .map(|t| {
+            let (a, b, c) = (&frames[t - 2], &frames[t - 1], &frames[t]);
+            let mut bits = 0u32;
+            for (i, (now, prev)) in c.iter().zip(b.iter()).enumerate() {
+                if now > prev {
+                    bits |= 1 << i;
+                }
+                if *now > c[(i + 1) % 12] {
+                    bits |= 1 << (12 + i);
+                }
+            }
+            let pairs = |f: &[f32; 12]| -> Vec<f32> { (0..8).map(|i| f[i] + f[i + 4]).collect() };
+            for (i, (now, before)) in pairs(c).iter().zip(pairs(a).iter()).enumerate() {
+                if now > before {
+                    bits |= 1 << (24 + i);
+                }
+            }
+            bits
+        })
+        .collect()
+}
+
+// 本程序自己的声纹，只借鉴了 Chromaprint 的降采样和半音能量，每一位的含义不同，
+// 和 Chromaprint、AcoustID 的指纹不兼容，只用于资料库内查找重复。和 AcoustID 比较要用 chromaprint::compute
+pub fn compute(file: &str) -> Result<Vec<u32>, String> {
+    let samples = decode_mono(file)?;
+    let print = sub_fingerprints(&chroma(&samples));
+    if print.is_empty() {
+        return Err("音频太短".to_owned());
+    }
+    Ok(print)
+}
+
+// 两个指纹的相似度，在一定范围内错开对齐，取相同位数比例最高的位置
+pub fn similarity(a: &[u32], b: &[u32]) -> f64 {
+    let mut best = 0.0;
+    for offset in 0..=MAX_OFFSET {
+        for (x, y) in [(a, b), (b, a)] {
+            if offset >= x.len() {
+                continue;
+            }
+            let x = &x[offset..];
+            let len = x.len().min(y.len());
+            // 重叠太少时不可靠
+            if len < 100 {
+                continue;
+            }
+            let same: u32 = x[..len]
+                .iter()
+                .zip(y[..len].iter())
+                .map(|(p, q)| 32 - (p ^ q).count_ones())
+                .sum();
+            let score = same as f64 / (len * 32) as f64;
+            if score > best {
+                best = score;
+            }
+        }
+    }
+    best
+}
+
+// 指纹缓存，文件修改时间和大小不变时不重新计算
+fn cache_path() -> Option<PathBuf> {
+    dirs::data_dir().map(|d| d.join("flac_music").join("fingerprints.txt"))
+}
+
+// 文件的 (修改时间, 大小)，用于判断文件是否修改过
+pub fn file_stamp(file: &str) -> Option<(u64, u64)> {
+    let meta = fs::metadata(file).ok()?;
+    let mtime = meta
+        .modified()
+        .ok()?
+        .duration_since(UNIX_EPOCH)
+        .ok()?
+        .as_secs();
+    Some((mtime, meta.len()))
+}
+
+// 指纹的文本格式，每个值 8 位十六进制
+pub fn to_hex(print: &[u32]) -> String {
+    print.iter().map(|v| format!("{:08x}", v)).collect()
+}
+
+pub fn from_hex(hex: &str) -> Option<Vec<u32>> {
+    (0..hex.len() / 8)
+        .map(|i| u32::from_str_radix(hex.get(i * 8..i * 8 + 8)?, 16).ok())
+        .collect()
+}
+
+// 每行: 文件\t修改时间\t大小\t十六进制指纹
+fn load_cache() -> HashMap<String, (u64, u64, Vec<u32>)> {
+    let text = match cache_path().and_then(|p| fs::read_to_string(p).ok()) {
+        Some(t) => t,
+        None => return HashMap::new(),
+    };
+    text.lines()
+        .filter_map(|line| {
+            let mut parts = line.split('\t');
+            let file = parts.next()?.to_owned();
+            let mtime = parts.next()?.parse().ok()?;
+            let size = parts.next()?.parse().ok()?;
+            let print = from_hex(parts.next()?)?;
+            Some((file, (mtime, size, print)))
+        })
+        .collect()
+}
+
+fn save_cache(cache: &HashMap<String, (u64, u64, Vec<u32>)>) {
+    let path = match cache_path() {
+        Some(p) => p,
+        None => return,
+    };
+    let mut text = String::new();
+    for (file, (mtime, size, print)) in cache {
+        if file.contains(['\t', '\n']) {
+            continue;
+        }
+        text.push_str(&format!(
+            "{}\t{}\t{}\t{}\n",
+            file,
+            mtime,
+            size,
+            to_hex(print)
+        ));
+    }
+    if let Some(dir) = path.parent() {
+        let _ = fs::create_dir_all(dir);
+    }
+    let tmp = path.with_extension("txt.tmp");
+    if let Err(e) = fs::write(&tmp, text).and_then(|_| fs::rename(&tmp, &path)) {
+        println!("保存指纹缓存失败 {}: {}", path.display(), e);
+    }
+}
+
+// 是否为无损编码
+pub fn is_lossless(codec: &str) -> bool {
+    [
+        "flac", "alac", "ape", "wavpack", "tta", "truehd", "mlp", "pcm_", "dsd_",
+    ]
+    .iter()
+    .any(|c| codec.starts_with(c))
+}
+
+// 音质说明和排序用的分数：无损优先，然后比较位深，采样率和码率
+fn quality(file: &str) -> (String, (bool, u16, u32, usize)) {
+    let format = match player::probe_format(file) {
+        Some(f) => f,
+        None => return ("".to_owned(), (false, 0, 0, 0)),
+    };
+    let bit_rate = ffmpeg::format::input(&Path::new(file))
+        .map(|c| c.bit_rate().max(0) as usize)
+        .unwrap_or(0);
+    let lossless = is_lossless(&format.codec);
+    let bits = if lossless { format.bits } else { 0 };
+    let text = format!("{} {}kbps", format.describe(), bit_rate / 1000);
+    (text, (lossless, bits, format.rate, bit_rate))
+}
+
+// 时长相近的两首歌是否需要比较
+fn close_duration(a: f64, b: f64) -> bool {
+    (a - b).abs() <= a.max(b) * DURATION_TOLERANCE + 2.0
+}
+
+// 找出可能相同的歌曲对。先按时长排序，只和时长相近的歌曲比较，
+// 再用倒排索引统计相同的指纹值，避免对每一对歌曲做完整的对齐比较
+fn candidates(songs: &[Song], prints: &HashMap<String, Vec<u32>>) -> Vec<(usize, usize)> {
+    let mut order: Vec<usize> = (0..songs.len())
+        .filter(|i| prints.contains_key(&songs[*i].file))
+        .collect();
+    order.sort_by(|a, b| songs[*a].duration.total_cmp(&songs[*b].duration));
+    let keys: Vec<HashSet<u32>> = order
+        .iter()
+        .map(|i| {
+            prints[&songs[*i].file]
+                .iter()
+                .map(|v| v >> INDEX_SHIFT)
+                .collect()
+        })
+        .collect();
+    // 索引值 -> 按时长排序后的位置，每个列表都是升序的
+    let mut index: HashMap<u32, Vec<usize>> = HashMap::new();
+    for (pos, k) in keys.iter().enumerate() {
+        for key in k {
+            index.entry(*key).or_default().push(pos);
+        }
+    }
+    let mut pairs = vec![];
+    let mut limit = 0;
+    for (pos, pos_keys) in keys.iter().enumerate() {
+        // 时长在范围内的最后一个位置
+        limit = limit.max(pos);
+        while limit + 1 < order.len()
+            && close_duration(songs[order[pos]].duration, songs[order[limit + 1]].duration)
+        {
+            limit += 1;
+        }
+        if limit == pos {
+            continue;
+        }
+        let mut shared: HashMap<usize, usize> = HashMap::new();
+        for key in pos_keys {
+            let postings = &index[key];
+            let start = postings.partition_point(|p| *p <= pos);
+            for other in postings[start..].iter().take_while(|p| **p <= limit) {
+                *shared.entry(*other).or_insert(0) += 1;
+            }
+        }
+        pairs.extend(
+            shared
+                .into_iter()
+                .filter(|(_, count)| *count >= MIN_SHARED)
+                .map(|(other, _)| (order[pos], order[other])),
+        );
+    }
+    pairs
+}
+
+// 把相似的歌曲合并为分组
+fn group(songs: &[Song], prints: &HashMap<String, Vec<u32>>) -> Vector<DuplicateGroup> {
+    let mut parent: Vec<usize> = (0..songs.len()).collect();
+    fn root(parent: &mut [usize], i: usize) -> usize {
+        let mut i = i;
+        while parent[i] != i {
+            parent[i] = parent[parent[i]];
+            i = parent[i];
+        }
+        i
+    }
+    for (i, j) in candidates(songs, prints) {
+        if root(&mut parent, i) == root(&mut parent, j) {
+            continue;
+        }
+        if similarity(&prints[&songs[i].file], &prints[&songs[j].file]) >= THRESHOLD {
+            let (ri, rj) = (root(&mut parent, i), root(&mut parent, j));
+            parent[rj] = ri;
+        }
+    }
+    let mut groups: HashMap<usize, Vec<usize>> = HashMap::new();
+    for i in 0..songs.len() {
+        let r = root(&mut parent, i);
+        groups.entry(r).or_default().push(i);
+    }
+    let mut result: Vec<DuplicateGroup> = groups
+        .into_values()
+        .filter(|members| members.len() > 1)
+        .map(|members| {
+            let mut items: Vec<(DuplicateItem, (bool, u16, u32, usize))> = members
+                .iter()
+                .map(|i| {
+                    let song = &songs[*i];
+                    let (text, score) = quality(&song.file);
+                    let item = DuplicateItem {
+                        file: song.file.to_owned(),
+                        title: song.title.to_owned(),
+                        artist: song.artist.to_owned(),
+                        quality: text,
+                        keep: false,
+                    };
+                    (item, score)
+                })
+                .collect();
+            items.sort_by(|a, b| b.1.cmp(&a.1));
+            items[0].0.keep = true;
+            DuplicateGroup {
+                items: items.into_iter().map(|(item, _)| item).collect(),
+            }
+        })
+        .collect();
+    result.sort_by(|a, b| a.items[0].title.cmp(&b.items[0].title));
+    result.into_iter().collect()
+}
+
+// 优先使用缓存中的指纹，没有缓存或文件修改过时重新计算并更新缓存
+fn cached_print(
+    cache: &mut HashMap<String, (u64, u64, Vec<u32>)>,
+    file: &str,
+) -> Result<Vec<u32>, String> {
+    let stamp = file_stamp(file);
+    if let Some((_, _, print)) = cache
+        .get(file)
+        .filter(|(mtime, size, _)| Some((*mtime, *size)) == stamp)
+    {
+        return Ok(print.to_owned());
+    }
+    let print = compute(file)?;
+    if let Some((mtime, size)) = stamp {
+        cache.insert(file.to_owned(), (mtime, size, print.to_owned()));
+    }
+    Ok(print)
+}
+
+// 后台计算指纹并查找重复歌曲，cancel 设置后尽快结束
+pub fn scan_async(songs: Vec<Song>, cancel: Arc<AtomicBool>, events: ExtEventSink) {
+    spawn(move || {
+        let mut cache = load_cache();
+        let mut prints: HashMap<String, Vec<u32>> = HashMap::new();
+        let total = songs.len();
+        for (i, song) in songs.iter().enumerate() {
+            if cancel.load(Ordering::Relaxed) {
+                break;
+            }
+            match cached_print(&mut cache, &song.file) {
+                Ok(print) => {
+                    prints.insert(song.file.to_owned(), print);
+                }
+                Err(e) => println!("计算指纹失败 {}: {}", song.file, e),
+            }
+            let _ = events.submit_command(SCAN_PROGRESS, (i + 1, total), Target::Auto);
+        }
+        // 删除已经不在资料库中的文件的缓存
+        cache.retain(|file, _| Path::new(file).exists());
+        save_cache(&cache);
+        let groups = if cancel.load(Ordering::Relaxed) {
+            Vector::new()
+        } else {
+            group(&songs, &prints)
+        };
+        let _ = events.submit_command(SCAN_DONE, groups, Target::Auto);
+    });
+}
+
+// 把不保留的版本移到数据目录下的 duplicates 文件夹，而不是直接删除
+pub fn set_aside(file: &str) -> Result<PathBuf, String> {
+    let dir = dirs::data_dir()
+        .or_else(dirs::home_dir)
+        .unwrap_or_default()
+        .join("flac_music")
+        .join("duplicates");
+    fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+    let name = Path::new(file)
+        .file_name()
+        .ok_or_else(|| "文件名无效".to_owned())?;
+    let mut target = dir.join(name);
+    let mut n = 1;
+    while target.exists() {
+        target = dir.join(format!("{} ({})", n, name.to_string_lossy()));
+        n += 1;
+    }
+    if fs::rename(file, &target).is_err() {
+        fs::copy(file, &target).map_err(|e| e.to_string())?;
+        fs::remove_file(file).map_err(|e| e.to_string())?;
+    }
+    Ok(target)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 固定种子的伪随机指纹
+    fn random_print(seed: u32, len: usize) -> Vec<u32> {
+        let mut x = seed;
+        (0..len)
+            .map(|_| {
+                x ^= x << 13;
+                x ^= x >> 17;
+                x ^= x << 5;
+                x
+            })
+            .collect()
+    }
+
+    fn song(file: &str, duration: f64) -> Song {
+        Song {
+            file: file.to_owned(),
+            title: file.to_owned(),
+            duration,
+            ..Song::default()
+        }
+    }
+
+    #[test]
+    fn similarity_with_offset() {
+        let a = random_print(1, 600);
+        // 开头多了 10 帧，或者少了开头的 30 帧
+        let mut later = random_print(2, 10);
+        later.extend(a.iter());
+        assert_eq!(similarity(&a, &later), 1.0);
+        assert_eq!(similarity(&a, &a[30..]), 1.0);
+        // 超过最大错开范围后和不相关的歌曲一样
+        let shifted = &a[MAX_OFFSET + 10..];
+        assert!(similarity(&a, shifted) < 0.6);
+        assert!(similarity(&a, &random_print(3, 600)) < 0.6);
+        // 重叠太少时不比较
+        assert_eq!(similarity(&a[..50], &a[..50]), 0.0);
+    }
+
+    #[test]
+    fn similarity_counts_bits() {
+        let a = random_print(4, 500);
+        // 每个值有 4 位不同
+        let b: Vec<u32> = a.iter().map(|v| v ^ 0x0f).collect();
+        assert_eq!(similarity(&a, &b), 28.0 / 32.0);
+    }
+
+    #[test]
+    fn duration_window() {
+        let print = random_print(5, 500);
+        let mut prints = HashMap::new();
+        prints.insert("a".to_owned(), print.to_owned());
+        prints.insert("b".to_owned(), print.to_owned());
+        // 指纹相同但时长相差太多，不作为候选
+        let songs = vec![song("a", 100.0), song("b", 200.0)];
+        assert!(candidates(&songs, &prints).is_empty());
+        assert!(group(&songs, &prints).is_empty());
+        let songs = vec![song("a", 100.0), song("b", 103.0)];
+        assert_eq!(candidates(&songs, &prints), vec![(0, 1)]);
+        // 没有指纹的歌曲不比较
+        let songs = vec![song("a", 100.0), song("c", 100.0)];
+        assert!(candidates(&songs, &prints).is_empty());
+    }
+
+    #[test]
+    fn few_shared_keys() {
+        // 高 20 位都不同时不做完整比较
+        let a = random_print(6, 500);
+        let b: Vec<u32> = a.iter().map(|v| v ^ (1 << 31)).collect();
+        let mut prints = HashMap::new();
+        prints.insert("a".to_owned(), a);
+        prints.insert("b".to_owned(), b);
+        let songs = vec![song("a", 100.0), song("b", 100.0)];
+        assert!(candidates(&songs, &prints).is_empty());
+    }
+
+    #[test]
+    fn transitive_groups() {
+        // a 和 b、b 和 c 各有 4 位不同，a 和 c 有 8 位不同，低于阈值
+        let b = random_print(7, 500);
+        let a: Vec<u32> = b.iter().map(|v| v ^ 0x00f).collect();
+        let c: Vec<u32> = b.iter().map(|v| v ^ 0x0f0).collect();
+        assert!(similarity(&a, &b) >= THRESHOLD);
+        assert!(similarity(&b, &c) >= THRESHOLD);
+        assert!(similarity(&a, &c) < THRESHOLD);
+        let mut prints = HashMap::new();
+        prints.insert("a".to_owned(), a);
+        prints.insert("b".to_owned(), b);
+        prints.insert("c".to_owned(), c);
+        prints.insert("d".to_owned(), random_print(8, 500));
+        let songs = vec![
+            song("a", 180.0),
+            song("c", 181.0),
+            song("d", 180.0),
+            song("b", 182.0),
+        ];
+        let groups = group(&songs, &prints);
+        assert_eq!(groups.len(), 1);
+        let mut files: Vec<String> = groups[0].items.iter().map(|i| i.file.to_owned()).collect();
+        files.sort();
+        assert_eq!(files, vec!["a", "b", "c"]);
+        assert_eq!(groups[0].items.iter().filter(|i| i.keep).count(), 1);
+    }
+}
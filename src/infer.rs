@@ -0,0 +1,295 @@
+use crate::Song;
+use druid::{Data, Lens};
+use std::path::Path;
+
+// 规则中可以使用的字段
+const FIELDS: [&str; 8] = [
+    "title",
+    "artist",
+    "album",
+    "albumartist",
+    "year",
+    "genre",
+    "track",
+    "disc",
+];
+
+// 没有配置规则时，推断标签窗口中预填的规则
+pub const DEFAULT_PATTERN: &str = "%artist% - %title%";
+
+// 预览中的一行，fields 为空表示文件名不符合规则
+#[derive(Clone, Data, Lens, Debug, Default, PartialEq)]
+pub struct InferRow {
+    pub file: String,
+    pub fields: String,
+}
+
+#[derive(Debug, PartialEq)]
+enum Part {
+    Text(String),
+    Field(String),
+}
+
+// 把一级目录或文件名的规则拆分为文字和字段，例如 "%track% %title%"
+fn parse(pattern: &str) -> Result<Vec<Part>, String> {
+    let mut parts = vec![];
+    let mut rest = pattern;
+    while let Some(start) = rest.find('%') {
+        if start > 0 {
+            parts.push(Part::Text(rest[..start].to_owned()));
+        }
+        let end = rest[start + 1..]
+            .find('%')
+            .ok_or_else(|| format!("规则 \"{}\" 中的 % 没有成对出现", pattern))?
+            + start
+            + 1;
+        let name = rest[start + 1..end].to_lowercase();
+        if !FIELDS.contains(&name.as_str()) {
+            return Err(format!("规则中的字段 %{}% 无效", name));
+        }
+        // 两个字段相连时无法确定分界
+        if let Some(Part::Field(_)) = parts.last() {
+            return Err("规则中的两个字段之间需要有分隔文字".to_owned());
+        }
+        parts.push(Part::Field(name));
+        rest = &rest[end + 1..];
+    }
+    if !rest.is_empty() {
+        parts.push(Part::Text(rest.to_owned()));
+    }
+    Ok(parts)
+}
+
+// 检查规则是否有效，空规则表示不推断
+pub fn validate(pattern: &str) -> Result<(), String> {
+    for segment in pattern.split('/') {
+        parse(segment)?;
+    }
+    Ok(())
+}
+
+// 字段取到下一段文字第一次出现的位置，最后一个字段取到末尾。
+// 音轨号和碟号只能是数字。
+fn match_segment(parts: &[Part], text: &str, result: &mut Vec<(String, String)>) -> bool {
+    let mut rest = text;
+    let mut i = 0;
+    while i < parts.len() {
+        match &parts[i] {
+            Part::Text(t) => match rest.strip_prefix(t.as_str()) {
+                Some(r) => rest = r,
+                None => return false,
+            },
+            Part::Field(name) => {
+                let end = match parts.get(i + 1) {
+                    Some(Part::Text(next)) => match rest.find(next.as_str()) {
+                        Some(e) => e,
+                        None => return false,
+                    },
+                    _ => rest.len(),
+                };
+                let value = rest[..end].trim();
+                if value.is_empty() {
+                    return false;
+                }
+                if (name == "track" || name == "disc" || name == "year")
+                    && !value.chars().all(|c| c.is_ascii_digit())
+                {
+                    return false;
+                }
+                result.push((name.to_owned(), value.to_owned()));
+                rest = &rest[end..];
+            }
+        }
+        i += 1;
+    }
+    rest.is_empty()
+}
+
+// 按规则从路径中取出字段。规则中的 / 对应目录，最后一级对应去掉扩展名的文件名。
+// 例如 "%artist% - %album%/%track% %title%" 匹配 ".../Miles Davis - Kind of Blue/01 So What.flac"
+pub fn infer(pattern: &str, file: &str) -> Option<Vec<(String, String)>> {
+    let pattern = pattern.trim().trim_matches('/');
+    if pattern.is_empty() {
+        return None;
+    }
+    let path = Path::new(file);
+    let mut names: Vec<String> = path
+        .parent()
+        .map(|p| {
+            p.components()
+                .map(|c| c.as_os_str().to_string_lossy().to_string())
+                .collect()
+        })
+        .unwrap_or_default();
+    names.push(path.file_stem()?.to_string_lossy().to_string());
+    let segments: Vec<&str> = pattern.split('/').collect();
+    if segments.len() > names.len() {
+        return None;
+    }
+    let mut result = vec![];
+    for (segment, name) in segments
+        .iter()
+        .zip(names[names.len() - segments.len()..].iter())
+    {
+        let parts = parse(segment).ok()?;
+        if !match_segment(&parts, name, &mut result) {
+            return None;
+        }
+    }
+    Some(result)
+}
+
+// 用推断的值填充缺少的字段，没有标题标签时标题也算缺少。返回填充的字段
+pub fn fill_missing(song: &mut Song, pattern: &str) -> Vec<String> {
+    let fields = match infer(pattern, &song.file) {
+        Some(f) => f,
+        None => return vec![],
+    };
+    let mut filled = vec![];
+    for (name, value) in fields {
+        let target = match name.as_str() {
+            "title" if song.title_from_file => {
+                song.title_from_file = false;
+                &mut song.title
+            }
+            "title" => continue,
+            "artist" => &mut song.artist,
+            "album" => &mut song.album,
+            "albumartist" => &mut song.album_artist,
+            "year" => &mut song.date,
+            "genre" => &mut song.genre,
+            "track" => &mut song.track,
+            "disc" => &mut song.disc,
+            _ => continue,
+        };
+        if target.is_empty() || name == "title" {
+            *target = value;
+            filled.push(name);
+        }
+    }
+    filled
+}
+
+// 写入文件时使用的 ffmpeg 标签名
+pub fn tag_key(name: &str) -> &str {
+    match name {
+        "albumartist" => "album_artist",
+        "year" => "date",
+        other => other,
+    }
+}
+
+// 预览中显示的字段
+pub fn describe(fields: &[(String, String)]) -> String {
+    fields
+        .iter()
+        .map(|(name, value)| format!("{}: {}", name, value))
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fields(pairs: &[(&str, &str)]) -> Option<Vec<(String, String)>> {
+        Some(
+            pairs
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn file_name_only() {
+        assert_eq!(
+            infer("%artist% - %title%", "/music/Miles Davis - So What.flac"),
+            fields(&[("artist", "Miles Davis"), ("title", "So What")])
+        );
+        // 分隔文字取第一次出现的位置，后面的 - 属于标题
+        assert_eq!(
+            infer("%artist% - %title%", "/music/A - B - C.mp3"),
+            fields(&[("artist", "A"), ("title", "B - C")])
+        );
+    }
+
+    #[test]
+    fn directories() {
+        assert_eq!(
+            infer(
+                "%artist% - %album%/%track% %title%",
+                "/music/Miles Davis - Kind of Blue/01 So What.flac"
+            ),
+            fields(&[
+                ("artist", "Miles Davis"),
+                ("album", "Kind of Blue"),
+                ("track", "01"),
+                ("title", "So What"),
+            ])
+        );
+        // 规则的级数多于路径时不匹配
+        assert_eq!(infer("%artist%/%album%/%title%", "a.flac"), None);
+    }
+
+    #[test]
+    fn numbers_only_for_track_disc_year() {
+        assert_eq!(infer("%track% %title%", "/music/Intro Song.flac"), None);
+        assert_eq!(
+            infer("%year% - %album%", "/music/1959 - Kind of Blue.flac"),
+            fields(&[("year", "1959"), ("album", "Kind of Blue")])
+        );
+        assert_eq!(infer("%year% - %album%", "/music/Late 59 - X.flac"), None);
+    }
+
+    #[test]
+    fn no_match() {
+        assert_eq!(infer("%artist% - %title%", "/music/Title Only.flac"), None);
+        assert_eq!(
+            infer("[%artist%] %title%", "/music/Artist Title.flac"),
+            None
+        );
+        // 字段不能为空
+        assert_eq!(infer("%artist% - %title%", "/music/ - Title.flac"), None);
+        assert_eq!(infer("", "/music/A - B.flac"), None);
+        assert_eq!(infer("/", "/music/A - B.flac"), None);
+    }
+
+    #[test]
+    fn invalid_patterns() {
+        assert!(validate("").is_ok());
+        assert!(validate("%artist% - %album%/%track% %title%").is_ok());
+        assert!(validate("%artist%%title%").is_err());
+        assert!(validate("%artist - %title%").is_err());
+        assert!(validate("%composer% - %title%").is_err());
+        // 字段名不区分大小写
+        assert!(validate("%Artist% - %TITLE%").is_ok());
+    }
+
+    #[test]
+    fn fill_keeps_existing_tags() {
+        let mut song = Song {
+            file: "/music/Someone - Song.flac".to_owned(),
+            title: "Someone - Song".to_owned(),
+            title_from_file: true,
+            artist: "Tagged".to_owned(),
+            ..Song::default()
+        };
+        let filled = fill_missing(&mut song, "%artist% - %title%");
+        assert_eq!(filled, vec!["title".to_owned()]);
+        assert_eq!(song.artist, "Tagged");
+        assert_eq!(song.title, "Song");
+        assert!(!song.title_from_file);
+
+        // 有标题标签时不覆盖标题
+        let mut tagged = Song {
+            file: "/music/Someone - Song.flac".to_owned(),
+            title: "Real Title".to_owned(),
+            ..Song::default()
+        };
+        let filled = fill_missing(&mut tagged, "%artist% - %title%");
+        assert_eq!(filled, vec!["artist".to_owned()]);
+        assert_eq!(tagged.title, "Real Title");
+        assert_eq!(tagged.artist, "Someone");
+    }
+}
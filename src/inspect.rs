@@ -0,0 +1,369 @@
+use crate::player;
+use druid::{Data, ExtEventSink, Lens, Selector, Target};
+use ffmpeg::format::stream::Disposition;
+use ffmpeg::format::Sample as SampleFormat;
+use ffmpeg::{codec, format, frame, media};
+use ffmpeg_next as ffmpeg;
+use std::fs::{self, File};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::spawn;
+
+// MD5 校验完成，(文件, 结果说明)
+pub const MD5_VERIFIED: Selector<(String, String)> = Selector::new("flac-music.md5-verified");
+
+// 属性窗口显示的内容
+#[derive(Clone, Data, Lens, Debug, Default, PartialEq)]
+pub struct Inspection {
+    pub file: String,
+    pub properties: String,
+    pub tags: String,
+    pub pictures: String,
+    // FLAC STREAMINFO 中的 MD5，不是 FLAC 或没有设置时为空
+    pub md5: String,
+    pub verify: String,
+}
+
+// FLAC 的 STREAMINFO 块
+#[derive(Clone, Debug, PartialEq)]
+pub struct StreamInfo {
+    pub rate: u32,
+    pub channels: u16,
+    pub bits: u16,
+    pub samples: u64,
+    pub md5: [u8; 16],
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// 读取 FLAC 文件开头的 STREAMINFO，文件前面可能有 ID3v2 标签
+pub fn read_streaminfo(file: &str) -> Option<StreamInfo> {
+    let mut f = File::open(file).ok()?;
+    let mut header = [0u8; 10];
+    f.read_exact(&mut header).ok()?;
+    let mut skip = 0u64;
+    if &header[..3] == b"ID3" {
+        let size = header[6..10]
+            .iter()
+            .fold(0u64, |acc, b| (acc << 7) | u64::from(b & 0x7f));
+        let footer = if header[5] & 0x10 != 0 { 10 } else { 0 };
+        skip = 10 + size + footer;
+    }
+    f.seek(SeekFrom::Start(skip)).ok()?;
+    // "fLaC" + 块头 4 字节 + STREAMINFO 34 字节
+    let mut block = [0u8; 42];
+    f.read_exact(&mut block).ok()?;
+    if &block[..4] != b"fLaC" || block[4] & 0x7f != 0 {
+        return None;
+    }
+    let data = &block[8..];
+    let v = u64::from_be_bytes(data[10..18].try_into().ok()?);
+    let mut md5 = [0u8; 16];
+    md5.copy_from_slice(&data[18..34]);
+    Some(StreamInfo {
+        rate: (v >> 44) as u32,
+        channels: ((v >> 41) & 0x7) as u16 + 1,
+        bits: ((v >> 36) & 0x1f) as u16 + 1,
+        samples: v & 0xf_ffff_ffff,
+        md5,
+    })
+}
+
+// 时长按采样数计算，FLAC 使用 STREAMINFO 中的精确值
+fn total_samples(
+    streaminfo: &Option<StreamInfo>,
+    stream: &format::stream::Stream,
+    input_duration: i64,
+    rate: u32,
+) -> u64 {
+    let time_base = stream.time_base();
+    match streaminfo {
+        Some(info) if info.samples > 0 => info.samples,
+        _ if stream.duration() > 0 => {
+            (stream.duration() as f64 * f64::from(time_base.numerator()) * f64::from(rate)
+                / f64::from(time_base.denominator()))
+            .round() as u64
+        }
+        _ => {
+            (input_duration.max(0) as f64 * f64::from(rate) / f64::from(ffmpeg::ffi::AV_TIME_BASE))
+                .round() as u64
+        }
+    }
+}
+
+fn size_text(bytes: u64) -> String {
+    format!("{:.2} MB ({} 字节)", bytes as f64 / 1024.0 / 1024.0, bytes)
+}
+
+// 读取文件的格式，标签和封面信息
+pub fn inspect(file: &str) -> Result<Inspection, String> {
+    ffmpeg::init().map_err(|e| e.to_string())?;
+    let mut input = format::input(&Path::new(file)).map_err(|e| e.to_string())?;
+    let stream = input
+        .streams()
+        .best(media::Type::Audio)
+        .ok_or_else(|| "没有音频流".to_owned())?;
+    let context =
+        codec::context::Context::from_parameters(stream.parameters()).map_err(|e| e.to_string())?;
+    let codec_id = context.id();
+    let codec_text = match codec::decoder::find(codec_id) {
+        Some(c) => format!("{} ({})", c.name(), c.description()),
+        None => codec_id.name().to_owned(),
+    };
+    let decoder = context.decoder().audio().map_err(|e| e.to_string())?;
+    let source = player::probe_format(file);
+    let streaminfo = read_streaminfo(file);
+    let rate = decoder.rate();
+    let bits = source.as_ref().map_or(0, |f| f.bits);
+    let samples = total_samples(&streaminfo, &stream, input.duration(), rate);
+    let stream_bit_rate = unsafe { (*stream.parameters().as_ptr()).bit_rate };
+    let mut properties = vec![
+        format!("文件: {}", file),
+        format!(
+            "大小: {}",
+            size_text(fs::metadata(file).map_or(0, |m| m.len()))
+        ),
+        format!(
+            "封装格式: {} ({})",
+            input.format().name(),
+            input.format().description()
+        ),
+        format!("编码: {}", codec_text),
+        format!("采样率: {} Hz", rate),
+        format!(
+            "位深: {}",
+            if bits > 0 {
+                format!("{} bit", bits)
+            } else {
+                "未知".to_owned()
+            }
+        ),
+        format!("采样格式: {}", decoder.format().name()),
+        format!(
+            "声道: {} ({:?})",
+            decoder.channels(),
+            decoder.channel_layout()
+        ),
+        format!(
+            "码率: {} kbps(音频流 {} kbps)",
+            input.bit_rate().max(0) / 1000,
+            stream_bit_rate.max(0) / 1000
+        ),
+        format!(
+            "时长: {:.3} 秒，{} 个采样",
+            if rate > 0 {
+                samples as f64 / f64::from(rate)
+            } else {
+                0.0
+            },
+            samples
+        ),
+    ];
+    if let Some(info) = &streaminfo {
+        properties.push(format!(
+            "STREAMINFO: {} Hz, {} bit, {} 声道, {} 个采样",
+            info.rate, info.bits, info.channels, info.samples
+        ));
+    }
+
+    let mut tags = vec![];
+    for (k, v) in input.metadata().iter() {
+        tags.push(format!("{} = {}", k, v));
+    }
+    let mut picture_streams = vec![];
+    for s in input.streams() {
+        for (k, v) in s.metadata().iter() {
+            tags.push(format!("[流 {}] {} = {}", s.index(), k, v));
+        }
+        if s.disposition().contains(Disposition::ATTACHED_PIC) {
+            // FLAC 和 MP3 的封面类型保存在 comment 中，例如 Cover (front)
+            let comment = s
+                .metadata()
+                .iter()
+                .find(|(k, _)| *k == "comment")
+                .map_or("".to_owned(), |(_, v)| v.to_owned());
+            let (width, height) = unsafe {
+                let par = s.parameters().as_ptr();
+                ((*par).width, (*par).height)
+            };
+            picture_streams.push((
+                s.index(),
+                format!(
+                    "{} {}x{} {}",
+                    s.parameters().id().name(),
+                    width,
+                    height,
+                    comment
+                ),
+            ));
+        }
+    }
+    // 封面的数据在开头，只读取到封面为止
+    let mut pictures = vec![];
+    if !picture_streams.is_empty() {
+        for (s, packet) in input.packets() {
+            if let Some((_, text)) = picture_streams.iter().find(|(i, _)| *i == s.index()) {
+                pictures.push(format!("{} {}", text, size_text(packet.size() as u64)));
+                if pictures.len() == picture_streams.len() {
+                    break;
+                }
+            }
+        }
+    }
+
+    let md5 = match &streaminfo {
+        Some(info) if info.md5.iter().any(|b| *b != 0) => hex(&info.md5),
+        _ => "".to_owned(),
+    };
+    Ok(Inspection {
+        file: file.to_owned(),
+        properties: properties.join("\n"),
+        tags: if tags.is_empty() {
+            "没有标签".to_owned()
+        } else {
+            tags.join("\n")
+        },
+        pictures: if pictures.is_empty() {
+            "没有内嵌图片".to_owned()
+        } else {
+            pictures.join("\n")
+        },
+        verify: match &streaminfo {
+            Some(_) if md5.is_empty() => "STREAMINFO 中没有设置 MD5".to_owned(),
+            Some(_) => "".to_owned(),
+            None => "不是 FLAC 文件，没有 MD5".to_owned(),
+        },
+        md5,
+    })
+}
+
+// FLAC 的 MD5 按解码后的采样计算：交错排列，小端，每个采样占 (位深+7)/8 字节。
+// ffmpeg 输出的 16/32 位采样是左对齐的，需要右移还原
+fn append_md5(md5: &mut md5::Context, decoded: &frame::Audio, bits: u16) -> Result<(), String> {
+    let (width, planar) = match decoded.format() {
+        SampleFormat::I16(t) => (2usize, t == ffmpeg::format::sample::Type::Planar),
+        SampleFormat::I32(t) => (4usize, t == ffmpeg::format::sample::Type::Planar),
+        other => return Err(format!("不支持的采样格式 {}", other.name())),
+    };
+    let shift = width as u32 * 8 - u32::from(bits);
+    let bytes = usize::from(bits).div_ceil(8);
+    let channels = decoded.channels() as usize;
+    let mut out = Vec::with_capacity(decoded.samples() * channels * bytes);
+    for i in 0..decoded.samples() {
+        for c in 0..channels {
+            let (plane, index) = if planar {
+                (c, i)
+            } else {
+                (0, i * channels + c)
+            };
+            let data = decoded.data(plane);
+            let at = index * width;
+            let value: i32 = if width == 2 {
+                i32::from(i16::from_ne_bytes([data[at], data[at + 1]]))
+            } else {
+                i32::from_ne_bytes([data[at], data[at + 1], data[at + 2], data[at + 3]])
+            };
+            out.extend_from_slice(&(value >> shift).to_le_bytes()[..bytes]);
+        }
+    }
+    md5.consume(&out);
+    Ok(())
+}
+
+// 完整解码的结果
+#[derive(Clone, Debug, Default)]
+pub struct DecodeReport {
+    pub rate: u32,
+    // 解码出的采样数
+    pub decoded: u64,
+    // 文件中记录的采样数，未知时为 0
+    pub expected: u64,
+    // expected 来自 FLAC STREAMINFO，是精确值
+    pub exact: bool,
+    // 解码出错的包数
+    pub errors: usize,
+    // MD5 是否一致，没有 MD5 时为 None
+    pub md5: Option<bool>,
+}
+
+// 解码整个文件，统计解码错误和采样数，FLAC 同时计算 MD5。cancel 设置后返回错误
+pub fn decode_file(file: &str, cancel: &AtomicBool) -> Result<DecodeReport, String> {
+    let streaminfo = read_streaminfo(file);
+    ffmpeg::init().map_err(|e| e.to_string())?;
+    let mut input = format::input(&Path::new(file)).map_err(|e| e.to_string())?;
+    let input_duration = input.duration();
+    let stream = input
+        .streams()
+        .best(media::Type::Audio)
+        .ok_or_else(|| "没有音频流".to_owned())?;
+    let index = stream.index();
+    let mut decoder = codec::context::Context::from_parameters(stream.parameters())
+        .and_then(|c| c.decoder().audio())
+        .map_err(|e| e.to_string())?;
+    let mut report = DecodeReport {
+        rate: decoder.rate(),
+        expected: total_samples(&streaminfo, &stream, input_duration, decoder.rate()),
+        exact: matches!(&streaminfo, Some(info) if info.samples > 0),
+        ..DecodeReport::default()
+    };
+    let signature = streaminfo.filter(|info| info.md5.iter().any(|b| *b != 0));
+    let mut md5 = signature.as_ref().map(|_| md5::Context::new());
+    let bits = signature.as_ref().map_or(0, |info| info.bits);
+    let mut decoded = frame::Audio::empty();
+    for (s, packet) in input.packets() {
+        if cancel.load(Ordering::Relaxed) {
+            return Err("已取消".to_owned());
+        }
+        if s.index() != index {
+            continue;
+        }
+        // 损坏的包跳过继续解码，最后按错误数和采样数判断
+        if decoder.send_packet(&packet).is_err() {
+            report.errors += 1;
+            continue;
+        }
+        while decoder.receive_frame(&mut decoded).is_ok() {
+            report.decoded += decoded.samples() as u64;
+            if let Some(md5) = md5.as_mut() {
+                append_md5(md5, &decoded, bits)?;
+            }
+        }
+    }
+    if decoder.send_eof().is_err() {
+        report.errors += 1;
+    }
+    while decoder.receive_frame(&mut decoded).is_ok() {
+        report.decoded += decoded.samples() as u64;
+        if let Some(md5) = md5.as_mut() {
+            append_md5(md5, &decoded, bits)?;
+        }
+    }
+    if let (Some(md5), Some(info)) = (md5, signature) {
+        report.md5 = Some(md5.compute().0 == info.md5);
+    }
+    Ok(report)
+}
+
+// 解码整个文件并和 STREAMINFO 中的 MD5 比较，cancel 设置后返回错误
+pub fn verify_md5(file: &str, cancel: &AtomicBool) -> Result<bool, String> {
+    let info = read_streaminfo(file).ok_or_else(|| "不是 FLAC 文件".to_owned())?;
+    if info.md5.iter().all(|b| *b == 0) {
+        return Err("STREAMINFO 中没有设置 MD5".to_owned());
+    }
+    Ok(decode_file(file, cancel)?.md5 == Some(true))
+}
+
+// 后台校验 MD5，完成后通知属性窗口
+pub fn verify_async(file: String, events: ExtEventSink) {
+    spawn(move || {
+        let result = match verify_md5(&file, &AtomicBool::new(false)) {
+            Ok(true) => "MD5 校验通过，解码结果和 STREAMINFO 一致".to_owned(),
+            Ok(false) => "MD5 不一致，文件已损坏或被修改过".to_owned(),
+            Err(e) => format!("校验失败: {}", e),
+        };
+        let _ = events.submit_command(MD5_VERIFIED, (file, result), Target::Auto);
+    });
+}
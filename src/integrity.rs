@@ -0,0 +1,154 @@
+use crate::fingerprint;
+use crate::inspect;
+use druid::{ExtEventSink, Selector, Target};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::spawn;
+
+// 校验进度，(已处理, 总数)
+pub const CHECK_PROGRESS: Selector<(usize, usize)> = Selector::new("flac-music.check-progress");
+// 校验结束，每个已校验文件的 (文件, 问题)，问题为空表示正常
+pub const CHECK_DONE: Selector<Vec<(String, String)>> = Selector::new("flac-music.check-done");
+
+// 校验结果中表示正常的状态
+const OK: &str = "ok";
+// 每校验这么多个文件保存一次结果，中途退出后可以继续
+const SAVE_EVERY: usize = 20;
+
+// 校验结果，文件修改时间和大小不变时不重新解码
+fn results_path() -> Option<PathBuf> {
+    dirs::data_dir().map(|d| d.join("flac_music").join("integrity.txt"))
+}
+
+// 每行: 文件\t修改时间\t大小\t状态
+fn load_results() -> HashMap<String, (u64, u64, String)> {
+    let text = match results_path().and_then(|p| fs::read_to_string(p).ok()) {
+        Some(t) => t,
+        None => return HashMap::new(),
+    };
+    text.lines()
+        .filter_map(|line| {
+            let mut parts = line.split('\t');
+            let file = parts.next()?.to_owned();
+            let mtime = parts.next()?.parse().ok()?;
+            let size = parts.next()?.parse().ok()?;
+            let status = parts.next()?.to_owned();
+            Some((file, (mtime, size, status)))
+        })
+        .collect()
+}
+
+fn save_results(results: &HashMap<String, (u64, u64, String)>) {
+    let path = match results_path() {
+        Some(p) => p,
+        None => return,
+    };
+    let mut text = String::new();
+    for (file, (mtime, size, status)) in results {
+        if file.contains(['\t', '\n']) {
+            continue;
+        }
+        text.push_str(&format!("{}\t{}\t{}\t{}\n", file, mtime, size, status));
+    }
+    if let Some(dir) = path.parent() {
+        let _ = fs::create_dir_all(dir);
+    }
+    let tmp = path.with_extension("txt.tmp");
+    if let Err(e) = fs::write(&tmp, text).and_then(|_| fs::rename(&tmp, &path)) {
+        println!("保存校验结果失败 {}: {}", path.display(), e);
+    }
+}
+
+// 上次校验发现问题并且之后没有修改过的文件，(文件, 问题)
+pub fn load_damaged() -> HashMap<String, String> {
+    load_results()
+        .into_iter()
+        .filter(|(file, (mtime, size, status))| {
+            status != OK && fingerprint::file_stamp(file) == Some((*mtime, *size))
+        })
+        .map(|(file, (_, _, status))| (file, status))
+        .collect()
+}
+
+// 解码文件并返回发现的问题，正常时为 OK，取消时为 None
+fn check(file: &str, cancel: &AtomicBool) -> Option<String> {
+    let report = match inspect::decode_file(file, cancel) {
+        Ok(r) => r,
+        Err(_) if cancel.load(Ordering::Relaxed) => return None,
+        Err(e) => return Some(format!("无法解码: {}", e)),
+    };
+    let mut problems = vec![];
+    if report.errors > 0 {
+        problems.push(format!("{} 个解码错误", report.errors));
+    }
+    if report.md5 == Some(false) {
+        problems.push("MD5 不一致".to_owned());
+    }
+    // STREAMINFO 中的采样数是精确的，其他格式的时长是估算值，允许 1% 或 1 秒的误差
+    let tolerance = if report.exact {
+        0
+    } else {
+        (report.expected / 100).max(u64::from(report.rate))
+    };
+    if report.decoded == 0 {
+        problems.push("没有解码出任何采样".to_owned());
+    } else if report.decoded + tolerance < report.expected {
+        problems.push(format!(
+            "文件被截断，解码 {} 个采样，应为 {} 个",
+            report.decoded, report.expected
+        ));
+    }
+    Some(if problems.is_empty() {
+        OK.to_owned()
+    } else {
+        // 状态保存在一行中
+        problems.join("，").replace(['\t', '\n'], " ")
+    })
+}
+
+// 在后台逐个解码文件，跳过上次校验后没有修改过的文件，cancel 设置后保存已完成的结果
+pub fn check_async(files: Vec<String>, cancel: Arc<AtomicBool>, events: ExtEventSink) {
+    spawn(move || {
+        let mut results = load_results();
+        let mut checked = vec![];
+        let mut unsaved = 0;
+        let total = files.len();
+        for (i, file) in files.iter().enumerate() {
+            if cancel.load(Ordering::Relaxed) {
+                break;
+            }
+            let stamp = fingerprint::file_stamp(file);
+            let cached = results
+                .get(file)
+                .filter(|(mtime, size, _)| Some((*mtime, *size)) == stamp)
+                .map(|(_, _, status)| status.to_owned());
+            let status = match cached {
+                Some(status) => status,
+                None => match check(file, &cancel) {
+                    Some(status) => {
+                        if let Some((mtime, size)) = stamp {
+                            results.insert(file.to_owned(), (mtime, size, status.to_owned()));
+                            unsaved += 1;
+                        }
+                        status
+                    }
+                    None => break,
+                },
+            };
+            if unsaved >= SAVE_EVERY {
+                save_results(&results);
+                unsaved = 0;
+            }
+            let problem = if status == OK { "".to_owned() } else { status };
+            checked.push((file.to_owned(), problem));
+            let _ = events.submit_command(CHECK_PROGRESS, (i + 1, total), Target::Auto);
+        }
+        // 删除已经不存在的文件的结果
+        results.retain(|file, _| Path::new(file).exists());
+        save_results(&results);
+        let _ = events.submit_command(CHECK_DONE, checked, Target::Auto);
+    });
+}
@@ -0,0 +1,677 @@
+use crate::radio;
+use druid::im::Vector;
+use druid::{Data, Lens};
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::Path;
+
+// 增强格式 LRC 中带时间的单词，例如 <00:12.30>word
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct LyricWord {
+    pub time_ms: u64,
+    pub text: String,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct LyricLine {
+    // 不同步的歌词为 0
+    pub time_ms: u64,
+    pub text: String,
+    // 没有逐字时间时为空
+    pub words: Vec<LyricWord>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Lyrics {
+    // 是否带时间标签，不带时只按顺序显示
+    pub synced: bool,
+    pub lines: Vec<LyricLine>,
+}
+
+// 歌词面板显示的一行，sung 为当前行已经唱过的部分
+#[derive(Clone, Data, Lens, Debug, Default, PartialEq)]
+pub struct LyricRow {
+    pub sung: String,
+    pub rest: String,
+    pub current: bool,
+}
+
+// 同步歌词显示当前行之前和之后的行数，当前行始终在面板上方，相当于自动滚动
+const ROWS_BEFORE: usize = 4;
+const ROWS_AFTER: usize = 8;
+
+impl LyricLine {
+    // 按播放位置拆分为已唱和未唱部分
+    fn split_sung(&self, position_ms: u64) -> (String, String) {
+        if self.words.is_empty() {
+            return (self.text.to_owned(), "".to_owned());
+        }
+        let mut sung = String::new();
+        let mut rest = String::new();
+        for word in self.words.iter() {
+            if word.time_ms <= position_ms && rest.is_empty() {
+                sung.push_str(&word.text);
+            } else {
+                rest.push_str(&word.text);
+            }
+        }
+        (sung, rest)
+    }
+}
+
+impl Lyrics {
+    pub fn is_empty(&self) -> bool {
+        self.lines.is_empty()
+    }
+
+    // 当前播放位置对应的行
+    pub fn current(&self, position_ms: u64) -> Option<usize> {
+        if !self.synced {
+            return None;
+        }
+        self.lines.iter().rposition(|l| l.time_ms <= position_ms)
+    }
+
+    pub fn rows(&self, position_ms: u64) -> Vector<LyricRow> {
+        let current = self.current(position_ms);
+        let (start, end) = if self.synced {
+            let start = current.unwrap_or(0).saturating_sub(ROWS_BEFORE);
+            let end = (current.unwrap_or(0) + ROWS_AFTER + 1).min(self.lines.len());
+            (start, end)
+        } else {
+            (0, self.lines.len())
+        };
+        (start..end)
+            .map(|i| {
+                let line = &self.lines[i];
+                if Some(i) == current {
+                    let (sung, rest) = line.split_sung(position_ms);
+                    LyricRow {
+                        sung,
+                        rest,
+                        current: true,
+                    }
+                } else {
+                    LyricRow {
+                        sung: "".to_owned(),
+                        rest: line.text.to_owned(),
+                        current: false,
+                    }
+                }
+            })
+            .collect()
+    }
+}
+
+// 解析 mm:ss, mm:ss.xx, mm:ss.xxx 或 mm:ss:xx 格式的时间
+pub fn parse_time(s: &str) -> Option<u64> {
+    let (min, rest) = s.trim().split_once(':')?;
+    let min: u64 = min.parse().ok()?;
+    let (sec, frac) = match rest.find(['.', ':']) {
+        Some(i) => (&rest[..i], &rest[i + 1..]),
+        None => (rest, ""),
+    };
+    let sec: u64 = sec.parse().ok()?;
+    if sec >= 60 || !frac.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    // 小数部分补齐到毫秒
+    let digits: String = frac.chars().chain("000".chars()).take(3).collect();
+    let ms: u64 = digits.parse().ok()?;
+    Some(min * 60_000 + sec * 1000 + ms)
+}
+
+// 解析增强格式中的逐字时间，返回去掉时间标签的文本
+fn parse_words(s: &str) -> (String, Vec<LyricWord>) {
+    let mut text = String::new();
+    let mut words: Vec<LyricWord> = vec![];
+    let mut time = 0;
+    let mut segment = String::new();
+    let mut rest = s;
+    let mut tagged = false;
+    while let Some(start) = rest.find('<') {
+        let tag_time = rest[start..]
+            .find('>')
+            .and_then(|end| parse_time(&rest[start + 1..start + end]).map(|t| (t, end)));
+        match tag_time {
+            Some((t, end)) => {
+                segment.push_str(&rest[..start]);
+                if !segment.is_empty() {
+                    words.push(LyricWord {
+                        time_ms: time,
+                        text: segment.to_owned(),
+                    });
+                }
+                text.push_str(&segment);
+                segment.clear();
+                time = t;
+                tagged = true;
+                rest = &rest[start + end + 1..];
+            }
+            None => {
+                // 不是时间标签，按普通文本处理
+                segment.push_str(&rest[..=start]);
+                rest = &rest[start + 1..];
+            }
+        }
+    }
+    segment.push_str(rest);
+    text.push_str(&segment);
+    if !tagged {
+        return (text, vec![]);
+    }
+    if !segment.is_empty() {
+        words.push(LyricWord {
+            time_ms: time,
+            text: segment,
+        });
+    }
+    (text, words)
+}
+
+// 解析 LRC 歌词，支持一行多个时间标签，[offset:] 和增强格式的逐字时间。
+// 没有时间标签时作为不同步的纯文本歌词。
+pub fn parse_lrc(text: &str) -> Lyrics {
+    let mut offset: i64 = 0;
+    let mut lines = vec![];
+    let mut plain = vec![];
+    for raw in text.lines() {
+        let mut rest = raw.trim();
+        let mut times = vec![];
+        let mut has_tag = false;
+        while rest.starts_with('[') {
+            let end = match rest.find(']') {
+                Some(e) => e,
+                None => break,
+            };
+            let tag = &rest[1..end];
+            if let Some(t) = parse_time(tag) {
+                times.push(t);
+            } else if let Some((key, value)) = tag.split_once(':') {
+                // [offset:+500] 表示歌词提前 500 毫秒
+                if key.trim().eq_ignore_ascii_case("offset") {
+                    offset = value.trim().trim_start_matches('+').parse().unwrap_or(0);
+                }
+            } else {
+                break;
+            }
+            has_tag = true;
+            rest = rest[end + 1..].trim();
+        }
+        if times.is_empty() {
+            // [ar:] [ti:] 等信息标签不算歌词
+            if !has_tag {
+                plain.push(rest.to_owned());
+            }
+            continue;
+        }
+        let (text, words) = parse_words(rest);
+        for time_ms in times {
+            lines.push(LyricLine {
+                time_ms,
+                text: text.to_owned(),
+                words: words.to_owned(),
+            });
+        }
+    }
+    if lines.is_empty() {
+        // 去掉首尾空行
+        while plain.last().map_or(false, |l| l.is_empty()) {
+            plain.pop();
+        }
+        let start = plain
+            .iter()
+            .position(|l| !l.is_empty())
+            .unwrap_or(plain.len());
+        return Lyrics {
+            synced: false,
+            lines: plain[start..]
+                .iter()
+                .map(|text| LyricLine {
+                    text: text.to_owned(),
+                    ..LyricLine::default()
+                })
+                .collect(),
+        };
+    }
+    let shift = |t: u64| (t as i64 - offset).max(0) as u64;
+    for line in lines.iter_mut() {
+        line.time_ms = shift(line.time_ms);
+        for word in line.words.iter_mut() {
+            word.time_ms = shift(word.time_ms);
+        }
+    }
+    lines.sort_by_key(|l| l.time_ms);
+    Lyrics {
+        synced: true,
+        lines,
+    }
+}
+
+pub fn syncsafe(b: &[u8]) -> usize {
+    b.iter().fold(0, |acc, x| (acc << 7) | (*x as usize & 0x7f))
+}
+
+pub fn be32(b: &[u8]) -> usize {
+    b.iter().fold(0, |acc, x| (acc << 8) | *x as usize)
+}
+
+// 读取 ID3 文本，返回文本和剩余数据
+fn read_text(data: &[u8], encoding: u8) -> Option<(String, &[u8])> {
+    match encoding {
+        // ISO-8859-1 和 UTF-8，以 0 结尾
+        0 | 3 => {
+            let end = data.iter().position(|b| *b == 0).unwrap_or(data.len());
+            let text = if encoding == 0 {
+                data[..end].iter().map(|b| *b as char).collect()
+            } else {
+                String::from_utf8_lossy(&data[..end]).to_string()
+            };
+            Some((text, data.get(end + 1..).unwrap_or(&[])))
+        }
+        // UTF-16 带 BOM 和 UTF-16BE，以 00 00 结尾
+        1 | 2 => {
+            let end = (0..data.len() / 2)
+                .map(|i| i * 2)
+                .find(|i| data[*i] == 0 && data[*i + 1] == 0)
+                .unwrap_or(data.len() / 2 * 2);
+            let mut bytes = &data[..end];
+            // 没有 BOM 时按常见的小端处理
+            let mut little_endian = encoding == 1;
+            if encoding == 1 && bytes.len() >= 2 {
+                if bytes[0] == 0xfe && bytes[1] == 0xff {
+                    little_endian = false;
+                    bytes = &bytes[2..];
+                } else if bytes[0] == 0xff && bytes[1] == 0xfe {
+                    bytes = &bytes[2..];
+                }
+            }
+            let units: Vec<u16> = bytes
+                .chunks_exact(2)
+                .map(|c| {
+                    if little_endian {
+                        u16::from_le_bytes([c[0], c[1]])
+                    } else {
+                        u16::from_be_bytes([c[0], c[1]])
+                    }
+                })
+                .collect();
+            Some((
+                String::from_utf16_lossy(&units),
+                data.get(end + 2..).unwrap_or(&[]),
+            ))
+        }
+        _ => None,
+    }
+}
+
+// SYLT 帧：编码(1) 语言(3) 时间格式(1) 内容类型(1) 描述，然后是多个 文本 + 4 字节时间
+fn parse_sylt(body: &[u8]) -> Option<Lyrics> {
+    if body.len() < 6 {
+        return None;
+    }
+    let encoding = body[0];
+    // 只支持毫秒时间，不支持 MPEG 帧号
+    if body[4] != 2 {
+        return None;
+    }
+    let (_, mut rest) = read_text(&body[6..], encoding)?;
+    let mut lines = vec![];
+    while !rest.is_empty() {
+        let (text, after) = read_text(rest, encoding)?;
+        if after.len() < 4 {
+            break;
+        }
+        lines.push(LyricLine {
+            time_ms: be32(&after[..4]) as u64,
+            text: text.trim_start_matches(['\n', '\r']).to_owned(),
+            words: vec![],
+        });
+        rest = &after[4..];
+    }
+    if lines.is_empty() {
+        return None;
+    }
+    lines.sort_by_key(|l| l.time_ms);
+    Some(Lyrics {
+        synced: true,
+        lines,
+    })
+}
+
+// ffmpeg 不读取 SYLT 帧，直接从 ID3v2.3/2.4 标签中查找
+pub fn read_sylt(path: &str) -> Option<Lyrics> {
+    let mut file = File::open(path).ok()?;
+    let mut header = [0u8; 10];
+    file.read_exact(&mut header).ok()?;
+    if &header[0..3] != b"ID3" || header[3] < 3 {
+        return None;
+    }
+    let version = header[3];
+    let mut tag = vec![0u8; syncsafe(&header[6..10])];
+    file.read_exact(&mut tag).ok()?;
+    let mut pos = 0;
+    // 跳过扩展头
+    if header[5] & 0x40 != 0 && tag.len() >= 4 {
+        pos = if version == 4 {
+            syncsafe(&tag[0..4])
+        } else {
+            be32(&tag[0..4]) + 4
+        };
+    }
+    while pos + 10 <= tag.len() && tag[pos] != 0 {
+        let size = if version == 4 {
+            syncsafe(&tag[pos + 4..pos + 8])
+        } else {
+            be32(&tag[pos + 4..pos + 8])
+        };
+        let body = tag.get(pos + 10..pos + 10 + size)?;
+        if &tag[pos..pos + 4] == b"SYLT" {
+            return parse_sylt(body);
+        }
+        pos += 10 + size;
+    }
+    None
+}
+
+// 和歌曲同名的 .lrc 文件
+pub fn sidecar_path(file: &str) -> std::path::PathBuf {
+    Path::new(file).with_extension("lrc")
+}
+
+fn read_sidecar(file: &str) -> Option<String> {
+    let path = sidecar_path(file);
+    let path = if path.is_file() {
+        path
+    } else {
+        Path::new(file).with_extension("LRC")
+    };
+    let bytes = fs::read(path).ok()?;
+    Some(String::from_utf8_lossy(&bytes).to_string())
+}
+
+// 按优先级读取歌词：同名 .lrc 文件，SYLT 同步歌词，内嵌的 LYRICS/USLT 标签
+pub fn load(file: &str, embedded: &str) -> Lyrics {
+    if file.is_empty() || radio::is_stream(file) {
+        return Lyrics::default();
+    }
+    if let Some(lyrics) = read_sidecar(file)
+        .map(|text| parse_lrc(&text))
+        .filter(|l| !l.is_empty())
+    {
+        return lyrics;
+    }
+    if let Some(lyrics) = read_sylt(file) {
+        return lyrics;
+    }
+    parse_lrc(embedded)
+}
+
+// 歌词编辑器中的一行，next 表示下一次打点记录的行
+#[derive(Clone, Data, Lens, Debug, Default, PartialEq)]
+pub struct EditorLine {
+    pub index: usize,
+    pub time_ms: Option<u64>,
+    pub text: String,
+    pub next: bool,
+}
+
+// 格式化为 LRC 的 mm:ss.xx
+pub fn format_time(ms: u64) -> String {
+    format!(
+        "{:02}:{:02}.{:02}",
+        ms / 60_000,
+        ms / 1000 % 60,
+        ms % 1000 / 10
+    )
+}
+
+// 纯文本每行一句，去掉空行
+pub fn editor_lines_from_text(text: &str) -> Vector<EditorLine> {
+    text.lines()
+        .map(|l| l.trim())
+        .filter(|l| !l.is_empty())
+        .enumerate()
+        .map(|(index, l)| EditorLine {
+            index,
+            time_ms: None,
+            text: l.to_owned(),
+            next: index == 0,
+        })
+        .collect()
+}
+
+// 从已有歌词开始编辑，保留同步歌词的时间
+pub fn editor_lines_from_lyrics(lyrics: &Lyrics) -> Vector<EditorLine> {
+    lyrics
+        .lines
+        .iter()
+        .enumerate()
+        .map(|(index, l)| EditorLine {
+            index,
+            time_ms: if lyrics.synced { Some(l.time_ms) } else { None },
+            text: l.text.to_owned(),
+            next: false,
+        })
+        .collect()
+}
+
+// 生成 LRC 文本，还没有记录时间的行不带时间标签
+pub fn to_lrc(lines: &Vector<EditorLine>) -> String {
+    lines
+        .iter()
+        .map(|l| match l.time_ms {
+            Some(t) => format!("[{}]{}\n", format_time(t), l.text),
+            None => format!("{}\n", l.text),
+        })
+        .collect()
+}
+
+// 写入同名 .lrc 文件
+pub fn save_lrc(file: &str, text: &str) -> Result<std::path::PathBuf, String> {
+    let path = sidecar_path(file);
+    let tmp = path.with_extension("lrc.tmp");
+    fs::write(&tmp, text)
+        .and_then(|_| fs::rename(&tmp, &path))
+        .map_err(|e| e.to_string())?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn time_formats() {
+        assert_eq!(parse_time("01:02"), Some(62_000));
+        assert_eq!(parse_time("01:02.5"), Some(62_500));
+        assert_eq!(parse_time("01:02.34"), Some(62_340));
+        assert_eq!(parse_time("01:02.345"), Some(62_345));
+        // [mm:ss:xx] 中最后一段是百分之一秒
+        assert_eq!(parse_time("01:02:34"), Some(62_340));
+        assert_eq!(parse_time(" 10:00.00 "), Some(600_000));
+        assert_eq!(parse_time("01:60"), None);
+        assert_eq!(parse_time("ar:Artist"), None);
+        assert_eq!(parse_time("01:02.3a"), None);
+        assert_eq!(parse_time("0102"), None);
+    }
+
+    #[test]
+    fn multiple_stamps() {
+        let lyrics = parse_lrc("[00:10.00][00:30.00]Chorus\n[00:20.00]Verse");
+        assert!(lyrics.synced);
+        let lines: Vec<(u64, &str)> = lyrics
+            .lines
+            .iter()
+            .map(|l| (l.time_ms, l.text.as_str()))
+            .collect();
+        assert_eq!(
+            lines,
+            vec![(10_000, "Chorus"), (20_000, "Verse"), (30_000, "Chorus")]
+        );
+    }
+
+    #[test]
+    fn info_tags_are_not_lyrics() {
+        let lyrics = parse_lrc("[ar:Artist]\n[ti:Title]\n[00:01.00]Line");
+        assert_eq!(lyrics.lines.len(), 1);
+        assert_eq!(lyrics.lines[0].text, "Line");
+    }
+
+    #[test]
+    fn offset_sign() {
+        // 正数表示歌词提前
+        let earlier = parse_lrc("[offset:+500]\n[00:10.00]Line");
+        assert_eq!(earlier.lines[0].time_ms, 9_500);
+        let later = parse_lrc("[offset:-500]\n[00:10.00]Line");
+        assert_eq!(later.lines[0].time_ms, 10_500);
+        let unsigned = parse_lrc("[offset:250]\n[00:10.00]Line");
+        assert_eq!(unsigned.lines[0].time_ms, 9_750);
+        // 提前到 0 之前时从 0 开始
+        let clamped = parse_lrc("[offset:2000]\n[00:01.00]Line");
+        assert_eq!(clamped.lines[0].time_ms, 0);
+    }
+
+    #[test]
+    fn word_times() {
+        let lyrics = parse_lrc("[00:01.00]<00:01.00>Hello <00:01.50>world");
+        let line = &lyrics.lines[0];
+        assert_eq!(line.text, "Hello world");
+        assert_eq!(
+            line.words,
+            vec![
+                LyricWord {
+                    time_ms: 1_000,
+                    text: "Hello ".to_owned()
+                },
+                LyricWord {
+                    time_ms: 1_500,
+                    text: "world".to_owned()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn angle_brackets_that_are_not_times() {
+        let (text, words) = parse_words("a <b> c < d");
+        assert_eq!(text, "a <b> c < d");
+        assert!(words.is_empty());
+        let (text, words) = parse_words("<00:01.00>x <y> z");
+        assert_eq!(text, "x <y> z");
+        assert_eq!(words.len(), 1);
+        assert_eq!(words[0].text, "x <y> z");
+    }
+
+    #[test]
+    fn plain_text() {
+        let lyrics = parse_lrc("\n\nFirst\n\nSecond\n\n");
+        assert!(!lyrics.synced);
+        let lines: Vec<&str> = lyrics.lines.iter().map(|l| l.text.as_str()).collect();
+        assert_eq!(lines, vec!["First", "", "Second"]);
+    }
+
+    // SYLT 帧：毫秒时间，歌词内容，空描述
+    fn sylt_frame(encoding: u8, entries: &[(&[u8], u32)], terminator: &[u8]) -> Vec<u8> {
+        let mut body = vec![encoding, b'e', b'n', b'g', 2, 1];
+        body.extend_from_slice(terminator);
+        for (text, time) in entries {
+            body.extend_from_slice(text);
+            body.extend_from_slice(terminator);
+            body.extend_from_slice(&time.to_be_bytes());
+        }
+        body
+    }
+
+    fn utf16(text: &str, bom: Option<bool>, little_endian: bool) -> Vec<u8> {
+        let mut bytes = match bom {
+            Some(true) => vec![0xff, 0xfe],
+            Some(false) => vec![0xfe, 0xff],
+            None => vec![],
+        };
+        for unit in text.encode_utf16() {
+            if little_endian {
+                bytes.extend_from_slice(&unit.to_le_bytes());
+            } else {
+                bytes.extend_from_slice(&unit.to_be_bytes());
+            }
+        }
+        bytes
+    }
+
+    fn texts(lyrics: &Lyrics) -> Vec<(u64, String)> {
+        lyrics
+            .lines
+            .iter()
+            .map(|l| (l.time_ms, l.text.to_owned()))
+            .collect()
+    }
+
+    #[test]
+    fn sylt_utf16_with_bom() {
+        let little = utf16("你好", Some(true), true);
+        let big = utf16("世界", Some(false), false);
+        let body = sylt_frame(1, &[(&little, 1000), (&big, 2000)], &[0, 0]);
+        let lyrics = parse_sylt(&body).expect("sylt");
+        assert_eq!(
+            texts(&lyrics),
+            vec![(1000, "你好".to_owned()), (2000, "世界".to_owned())]
+        );
+    }
+
+    #[test]
+    fn sylt_utf16_without_bom() {
+        // 编码 1 没有 BOM 时按小端处理，编码 2 为 UTF-16BE
+        let little = utf16("Line", None, true);
+        let body = sylt_frame(1, &[(&little, 500)], &[0, 0]);
+        assert_eq!(
+            texts(&parse_sylt(&body).expect("sylt")),
+            vec![(500, "Line".to_owned())]
+        );
+        let big = utf16("Line", None, false);
+        let body = sylt_frame(2, &[(&big, 500)], &[0, 0]);
+        assert_eq!(
+            texts(&parse_sylt(&body).expect("sylt")),
+            vec![(500, "Line".to_owned())]
+        );
+    }
+
+    #[test]
+    fn sylt_latin1_and_frame_numbers() {
+        let body = sylt_frame(0, &[(b"\nB", 2000), (b"A", 1000)], &[0]);
+        assert_eq!(
+            texts(&parse_sylt(&body).expect("sylt")),
+            vec![(1000, "A".to_owned()), (2000, "B".to_owned())]
+        );
+        // MPEG 帧号格式的时间不支持
+        let mut frames = body.to_owned();
+        frames[4] = 1;
+        assert_eq!(parse_sylt(&frames), None);
+    }
+
+    #[test]
+    fn sylt_from_id3v23_file() {
+        let body = sylt_frame(3, &[("歌词".as_bytes(), 1500)], &[0]);
+        let mut frame = b"SYLT".to_vec();
+        frame.extend_from_slice(&(body.len() as u32).to_be_bytes());
+        frame.extend_from_slice(&[0, 0]);
+        frame.extend_from_slice(&body);
+        // 帧后面的填充
+        frame.extend_from_slice(&[0; 16]);
+        let size = frame.len() as u32;
+        let mut data = b"ID3\x03\x00\x00".to_vec();
+        data.extend((0..4).rev().map(|i| ((size >> (i * 7)) & 0x7f) as u8));
+        data.extend_from_slice(&frame);
+        let path = std::env::temp_dir().join(format!("flac_music_sylt_{}.mp3", std::process::id()));
+        File::create(&path)
+            .and_then(|mut f| f.write_all(&data))
+            .expect("write test file");
+        let lyrics = read_sylt(&path.display().to_string());
+        let _ = fs::remove_file(&path);
+        assert_eq!(
+            texts(&lyrics.expect("sylt")),
+            vec![(1500, "歌词".to_owned())]
+        );
+    }
+}
@@ -1,57 +1,304 @@
+mod browse;
+mod chromaprint;
+mod config;
+mod encoding;
+mod ffmpeg_source;
+mod fingerprint;
+mod infer;
+mod inspect;
+mod integrity;
+mod lyrics;
+mod musicbrainz;
+mod organize;
+mod output;
+mod player;
+mod podcast;
+mod queue;
+mod radio;
+mod resample;
+mod search;
+mod session;
+mod sniff;
+mod sync;
+mod tagging;
+mod transcode;
+
+use browse::{BrowseMode, BrowseNode};
+use config::{Action, Config};
 use druid::im::vector;
 use druid::image::Progress;
-use druid::widget::{prelude::*, Button, Container, Label, Padding, Scroll, Slider, Split};
-use druid::widget::{CrossAxisAlignment, List};
+use druid::widget::{
+    prelude::*, Button, Checkbox, Container, Label, Padding, Scroll, Slider, Split,
+};
+use druid::widget::{
+    Controller, CrossAxisAlignment, Image, LineBreaking, List, Radio, TextBox, ViewSwitcher,
+};
 use druid::widget::{Flex, ProgressBar};
 use druid::{
-    commands, theme, AppDelegate, Color, Command, DelegateCtx, FileDialogOptions, Handled,
-    LocalizedString, MenuDesc, MenuItem, SysMods, Target, WidgetExt,
+    commands, lens, theme, AppDelegate, Color, Command, Cursor, DelegateCtx, ExtEventSink,
+    FileDialogOptions, Handled, ImageBuf, LensExt, LocalizedString, MenuDesc, MenuItem, Selector,
+    SysMods, Target, WidgetExt,
 };
-use druid::{im::Vector, AppLauncher, Data, Lens, Widget, WindowDesc};
+use druid::{im::Vector, AppLauncher, Data, Key, Lens, Size, TimerToken, Widget, WindowDesc};
 use ffmpeg_next as ffmpeg;
+use fingerprint::{DuplicateGroup, DuplicateItem};
+use infer::InferRow;
+use inspect::Inspection;
+use lyrics::{EditorLine, LyricRow, Lyrics};
+use musicbrainz::ReleaseCandidate;
+use organize::RenameRow;
+use output::AudioOutput;
+use player::{PlaybackSettings, Player};
+use podcast::{Episode, Podcast};
+use queue::{PlayQueue, QueueItem};
+use radio::Station;
+use resample::ResampleQuality;
 use rodio::{OutputStreamHandle, Source};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
 use std::fs;
 use std::io::BufReader;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
 use std::thread::{sleep, spawn};
+use std::time::Duration;
+use sync::{SyncPlaylist, SyncTranscode};
+use tagging::TagField;
+use transcode::{ExportFormat, ExportSettings};
 
 fn main() {
+    // 读取配置文件，配置错误时使用默认配置并提示
+    let config_path = config::config_path();
+    let mut message = "".to_owned();
+    let config = config::load(&config_path).unwrap_or_else(|e| {
+        println!("{}", e);
+        message = e.to_string();
+        Config::default()
+    });
+    // 恢复上次退出时的状态
+    let session = session::load();
+    let podcast_store = podcast::load();
+    let volume = session.volume.unwrap_or(config.audio.volume);
+    let window_size = Size::new(
+        session.window_width.unwrap_or(config.window.width),
+        session.window_height.unwrap_or(config.window.height),
+    );
     let win = WindowDesc::new(ui_builder)
         .menu(make_menu())
         .title("Flac Music v0.2.3")
-        .window_size((1200., 600.))
+        .window_size(window_size)
         .show_titlebar(true);
 
-    let (_stream, handle) = rodio::OutputStream::try_default().unwrap();
-    let play_sink = Arc::new(Mutex::new(rodio::Sink::try_new(&handle).unwrap()));
-    let init_state = AppState {
+    // 打开上次选择的输出设备，没有设备时也能启动，只是不能播放
+    let device = session
+        .device
+        .to_owned()
+        .unwrap_or_else(|| config.audio.device.to_owned());
+    let (audio_output, handle) = match output::open_or_default(&device) {
+        Ok((o, h)) => (Some(o), Some(h)),
+        Err(e) => {
+            println!("{}", e);
+            message = e;
+            (None, None)
+        }
+    };
+    let device = audio_output
+        .as_ref()
+        .map_or("".to_owned(), |o| o.device.to_owned());
+    let stream = Arc::new(Mutex::new(handle));
+    let play_sink = Arc::new(Mutex::new(output::new_sink(&stream)));
+    play_sink.lock().unwrap().set_volume(volume as f32);
+    let mut init_state = AppState {
         app_status: Arc::new(Mutex::new(Status::Stop)),
         play_lists: Vector::new(),
         current_song: Arc::new(Mutex::new(Song::default())),
-        volume: 0.3,
+        volume,
         progress_rate: 0.5,
-        play_mode: Modes::Order,
+        play_mode: session.play_mode.to_owned(),
         current_play_list: vector![],
+        show_list: vector![],
+        search_text: "".to_owned(),
+        show_broken: false,
+        show_damaged: false,
         music_dir: "".to_owned(),
+        music_dirs: session.music_dirs.iter().cloned().collect(),
+        browse_mode: BrowseMode::Artist,
+        browse_path: vector![],
+        browse_nodes: vector![],
+        queue: Arc::new(Mutex::new(PlayQueue::default())),
+        queue_view: vector![],
+        position: Arc::new(AtomicU64::new(session.position_ms)),
+        window_size,
+        column_widths: {
+            // 旧会话中的列数可能不足
+            let mut widths = session.column_widths.to_owned();
+            widths.resize(COLUMN_WIDTHS.len(), 120.0);
+            widths.into_iter().collect()
+        },
+        auto_resume: session.auto_resume,
+        config: Arc::new(config.to_owned()),
+        message,
         sink: play_sink,
-        stream: Arc::new(handle),
+        stream,
+        output: Arc::new(Mutex::new(audio_output)),
+        device,
+        devices: output::device_names().into_iter().collect(),
+        bit_perfect: session.bit_perfect,
+        resample_quality: session.resample_quality,
+        playback_settings: Arc::new(Mutex::new(PlaybackSettings {
+            bit_perfect: session.bit_perfect,
+            resample: session.resample_quality,
+        })),
+        format_info: Arc::new(Mutex::new("".to_owned())),
+        events: Arc::new(None),
+        stations: session.stations.iter().cloned().collect(),
+        station_name: "".to_owned(),
+        station_url: "".to_owned(),
+        stream_url: "".to_owned(),
+        stream_title: "".to_owned(),
+        side_panel: SidePanel::Queue,
+        podcasts: podcast_store.podcasts.into_iter().collect(),
+        podcast_auto_delete: podcast_store.auto_delete,
+        feed_url: "".to_owned(),
+        bookmarks: Arc::new(Mutex::new(HashMap::new())),
+        lyrics: Arc::new(Lyrics::default()),
+        lyrics_file: "".to_owned(),
+        lyrics_view: vector![],
+        lyrics_editing: false,
+        editor_file: "".to_owned(),
+        editor_text: "".to_owned(),
+        editor_lines: vector![],
+        editor_cursor: 0,
+        tag_files: vector![],
+        tag_fields: vector![],
+        rename_pattern: config.library.rename_pattern.to_owned(),
+        rename_rows: vector![],
+        last_renames: Arc::new(vec![]),
+        infer_pattern: if config.library.filename_pattern.is_empty() {
+            infer::DEFAULT_PATTERN.to_owned()
+        } else {
+            config.library.filename_pattern.to_owned()
+        },
+        infer_rows: vector![],
+        duplicates: vector![],
+        duplicate_scanning: false,
+        duplicate_progress: "".to_owned(),
+        duplicate_cancel: Arc::new(AtomicBool::new(false)),
+        match_candidates: vector![],
+        match_status: "".to_owned(),
+        matching: false,
+        export_dir: config.export.dir.to_owned(),
+        export_format: config.export.format,
+        export_rate: config.export.sample_rate,
+        export_bits: config.export.bit_depth,
+        export_progress: "".to_owned(),
+        exporting: false,
+        export_cancel: Arc::new(AtomicBool::new(false)),
+        new_playlist_name: "".to_owned(),
+        sync_dir: config.sync.dir.to_owned(),
+        sync_transcode: config.sync.transcode,
+        sync_progress: "".to_owned(),
+        syncing: false,
+        sync_cancel: Arc::new(AtomicBool::new(false)),
+        inspection: Inspection::default(),
+        integrity_report: vector![],
+        integrity_checking: false,
+        integrity_progress: "".to_owned(),
+        integrity_cancel: Arc::new(AtomicBool::new(false)),
     };
-    let app = AppLauncher::with_window(win)
+    init_state.restore_session(&session);
+    init_state.sync_podcasts();
+
+    let launcher = AppLauncher::with_window(win)
         .use_simple_logger()
-        .configure_env(|env, _| {
-            env.set(theme::WINDOW_BACKGROUND_COLOR, Color::WHITE);
-            env.set(theme::LABEL_COLOR, Color::BLACK);
-            env.set(theme::TEXT_SIZE_LARGE, 13.0);
-            env.set(theme::BUTTON_LIGHT, Color::WHITE);
-            env.set(theme::BUTTON_DARK, Color::WHITE);
-            env.set(theme::BACKGROUND_DARK, Color::WHITE);
-            env.set(theme::BACKGROUND_LIGHT, Color::WHITE);
-        })
-        .delegate(MenuDelegate)
-        .launch(init_state);
+        .configure_env(move |env, _| apply_theme(env, &config.theme))
+        .delegate(MenuDelegate);
+    // 配置文件修改后自动重新加载
+    config::watch(config_path, launcher.get_external_handle());
+    // 输出设备断开时暂停播放
+    output::watch(
+        Arc::clone(&init_state.output),
+        launcher.get_external_handle(),
+    );
+    // 播放线程通过它通知界面歌曲无法播放
+    init_state.events = Arc::new(Some(launcher.get_external_handle()));
+    if session.auto_resume && session.current.is_some() {
+        *init_state.app_status.lock().unwrap() = Status::Play;
+        init_state
+            .player()
+            .start(Some(Duration::from_millis(session.position_ms)));
+    }
+    let app = launcher.launch(init_state);
+}
+
+// 按配置设置主题颜色
+fn apply_theme(env: &mut Env, theme_config: &config::ThemeConfig) {
+    let background = config::parse_color(&theme_config.background).unwrap_or(Color::WHITE);
+    let text = config::parse_color(&theme_config.text).unwrap_or(Color::BLACK);
+    let button = config::parse_color(&theme_config.button).unwrap_or(Color::WHITE);
+    env.set(theme::WINDOW_BACKGROUND_COLOR, background.to_owned());
+    env.set(theme::LABEL_COLOR, text);
+    env.set(theme::TEXT_SIZE_LARGE, theme_config.text_size);
+    env.set(theme::BUTTON_LIGHT, button.to_owned());
+    env.set(theme::BUTTON_DARK, button);
+    env.set(theme::BACKGROUND_DARK, background.to_owned());
+    env.set(theme::BACKGROUND_LIGHT, background);
 }
 
+// 列表各列宽度：标题，专辑，艺人，时长，日期
+const COLUMN_WIDTHS: [Key<f64>; 5] = [
+    Key::new("flac-music.column-width.title"),
+    Key::new("flac-music.column-width.album"),
+    Key::new("flac-music.column-width.artist"),
+    Key::new("flac-music.column-width.duration"),
+    Key::new("flac-music.column-width.date"),
+];
+// 拖动调整列宽时的最小宽度
+const MIN_COLUMN_WIDTH: f64 = 40.0;
+
+// 定时保存会话的间隔
+const SESSION_SAVE_INTERVAL: Duration = Duration::from_secs(30);
+// 同步播客播放进度的间隔
+const PODCAST_SYNC_INTERVAL: Duration = Duration::from_secs(5);
+// 刷新歌词高亮的间隔
+const LYRICS_REFRESH_INTERVAL: Duration = Duration::from_millis(200);
+
+// 浏览面板选中节点 (节点所在层级, 节点key)
+const BROWSE_SELECT: Selector<(usize, String)> = Selector::new("flac-music.browse-select");
+// 切换输出设备，空字符串为系统默认设备
+const SELECT_DEVICE: Selector<String> = Selector::new("flac-music.select-device");
+
+// 播放队列操作
+const QUEUE_APPEND: Selector<Song> = Selector::new("flac-music.queue-append");
+const QUEUE_PLAY_NEXT: Selector<Song> = Selector::new("flac-music.queue-play-next");
+const QUEUE_REMOVE: Selector<usize> = Selector::new("flac-music.queue-remove");
+// (原位置, 新位置)
+const QUEUE_MOVE: Selector<(usize, usize)> = Selector::new("flac-music.queue-move");
+// 播放电台
+const PLAY_STATION: Selector<Station> = Selector::new("flac-music.play-station");
+// 删除电台，参数为地址
+const REMOVE_STATION: Selector<String> = Selector::new("flac-music.remove-station");
+// 播客操作，参数为订阅地址或 (订阅地址, 单集 id)
+const REFRESH_FEED: Selector<String> = Selector::new("flac-music.refresh-feed");
+const UNSUBSCRIBE: Selector<String> = Selector::new("flac-music.unsubscribe");
+const DOWNLOAD_EPISODE: Selector<(String, String)> = Selector::new("flac-music.download-episode");
+const PLAY_EPISODE: Selector<(String, String)> = Selector::new("flac-music.play-episode");
+// 勾选或取消勾选歌曲，(文件, 是否选中)
+const SELECT_SONG: Selector<(String, bool)> = Selector::new("flac-music.select-song");
+// 保留重复分组中勾选的版本，其余的移到 duplicates 文件夹
+const KEEP_DUPLICATES: Selector<DuplicateGroup> = Selector::new("flac-music.keep-duplicates");
+// 把选中的发行的标签和封面写入专辑的所有文件
+const APPLY_RELEASE: Selector<ReleaseCandidate> = Selector::new("flac-music.apply-release");
+// 删除保存的播放列表
+const REMOVE_PLAYLIST: Selector<String> = Selector::new("flac-music.remove-playlist");
+// 在属性窗口中显示歌曲文件的信息
+const INSPECT_SONG: Selector<String> = Selector::new("flac-music.inspect-song");
+// 解码校验 FLAC 的 MD5
+const VERIFY_MD5: Selector<String> = Selector::new("flac-music.verify-md5");
+// 歌词编辑器中选择下一次打点的行
+const EDITOR_SELECT: Selector<usize> = Selector::new("flac-music.editor-select");
+
 struct MenuDelegate;
 
 impl AppDelegate<AppState> for MenuDelegate {
@@ -67,9 +314,301 @@ impl AppDelegate<AppState> for MenuDelegate {
             data.music_dir.clear();
             let path = e.path();
             data.music_dir = path.display().to_string();
-            data.current_play_list.extend(load_files(&data.music_dir));
+            if !data.music_dirs.contains(&data.music_dir) {
+                data.music_dirs.push_back(data.music_dir.to_owned());
+            }
+            data.current_play_list
+                .extend(load_files(&data.music_dir, &data.config));
             data.current_play_list
                 .sort_by(|left, right| left.album.cmp(&right.album));
+            data.refresh_browse();
+            data.refresh_show_list();
+            return Handled::Yes;
+        }
+        if let Some(device) = cmd.get(SELECT_DEVICE) {
+            switch_device(data, device);
+            return Handled::Yes;
+        }
+        if let Some(device) = cmd.get(output::DEVICE_LOST) {
+            data.sink.lock().unwrap().pause();
+            if *data.app_status.lock().unwrap() == Status::Play {
+                *data.app_status.lock().unwrap() = Status::Suspend;
+            }
+            data.message = format!("输出设备 {} 已断开，播放已暂停，请选择其他设备", device);
+            data.devices = output::device_names().into_iter().collect();
+            return Handled::Yes;
+        }
+        if let Some((file, reason)) = cmd.get(player::TRACK_BROKEN) {
+            let mut title = file.to_owned();
+            for song in data
+                .current_play_list
+                .iter_mut()
+                .filter(|s| s.file == *file)
+            {
+                song.broken = reason.to_owned();
+                title = song.title.to_owned();
+            }
+            data.message = format!("无法播放 {}: {}，已跳到下一首", title, reason);
+            data.refresh_show_list();
+            data.sync_queue_view();
+            return Handled::Yes;
+        }
+        if let Some((done, total)) = cmd.get(fingerprint::SCAN_PROGRESS) {
+            data.duplicate_progress = format!("{}/{}", done, total);
+            return Handled::Yes;
+        }
+        if let Some(groups) = cmd.get(fingerprint::SCAN_DONE) {
+            data.duplicate_scanning = false;
+            data.duplicates = groups.to_owned();
+            data.message = if data.duplicate_cancel.load(Ordering::Relaxed) {
+                "已取消查找重复歌曲".to_owned()
+            } else {
+                format!("找到 {} 组重复歌曲", groups.len())
+            };
+            return Handled::Yes;
+        }
+        if let Some((done, total)) = cmd.get(integrity::CHECK_PROGRESS) {
+            data.integrity_progress = format!("{}/{}", done, total);
+            return Handled::Yes;
+        }
+        if let Some(checked) = cmd.get(integrity::CHECK_DONE) {
+            data.integrity_checking = false;
+            let results: HashMap<&String, &String> = checked.iter().map(|(f, p)| (f, p)).collect();
+            data.apply_integrity(|file| results.get(file).map(|p| p.to_string()));
+            data.message = format!(
+                "{}校验 {} 个文件，{} 个有问题",
+                if data.integrity_cancel.load(Ordering::Relaxed) {
+                    "已取消，"
+                } else {
+                    ""
+                },
+                checked.len(),
+                checked.iter().filter(|(_, p)| !p.is_empty()).count()
+            );
+            return Handled::Yes;
+        }
+        if let Some(file) = cmd.get(INSPECT_SONG) {
+            data.inspection = inspect::inspect(file).unwrap_or_else(|e| Inspection {
+                file: file.to_owned(),
+                properties: format!("无法读取 {}: {}", file, e),
+                ..Inspection::default()
+            });
+            return Handled::Yes;
+        }
+        if let Some(file) = cmd.get(VERIFY_MD5) {
+            if let Some(events) = data.events.as_ref() {
+                inspect::verify_async(file.to_owned(), events.to_owned());
+            }
+            return Handled::Yes;
+        }
+        if let Some((file, result)) = cmd.get(inspect::MD5_VERIFIED) {
+            if data.inspection.file == *file {
+                data.inspection.verify = result.to_owned();
+            }
+            return Handled::Yes;
+        }
+        if let Some((done, total)) = cmd.get(sync::SYNC_PROGRESS) {
+            data.sync_progress = format!("{}/{}", done, total);
+            return Handled::Yes;
+        }
+        if let Some(message) = cmd.get(sync::SYNC_DONE) {
+            data.syncing = false;
+            data.sync_progress = message.to_owned();
+            return Handled::Yes;
+        }
+        if let Some(name) = cmd.get(REMOVE_PLAYLIST) {
+            data.play_lists.retain(|p| p.name != *name);
+            return Handled::Yes;
+        }
+        if let Some((done, total)) = cmd.get(transcode::EXPORT_PROGRESS) {
+            data.export_progress = format!("{}/{}", done, total);
+            return Handled::Yes;
+        }
+        if let Some(message) = cmd.get(transcode::EXPORT_DONE) {
+            data.exporting = false;
+            data.export_progress = message.to_owned();
+            return Handled::Yes;
+        }
+        if let Some(count) = cmd.get(musicbrainz::MATCH_PROGRESS) {
+            data.match_status = format!("已扫描 {} 个发行", count);
+            return Handled::Yes;
+        }
+        if let Some(result) = cmd.get(musicbrainz::MATCH_DONE) {
+            data.matching = false;
+            match result {
+                Ok(candidates) => {
+                    data.match_status = if candidates.is_empty() {
+                        "没有找到音轨数和时长相符的发行".to_owned()
+                    } else {
+                        format!("找到 {} 个候选发行", candidates.len())
+                    };
+                    data.match_candidates = candidates.to_owned();
+                }
+                Err(e) => data.match_status = e.to_owned(),
+            }
+            return Handled::Yes;
+        }
+        if let Some(release) = cmd.get(APPLY_RELEASE) {
+            data.apply_release(release);
+            return Handled::Yes;
+        }
+        if let Some(group) = cmd.get(KEEP_DUPLICATES) {
+            data.keep_duplicates(group);
+            return Handled::Yes;
+        }
+        if let Some(index) = cmd.get(EDITOR_SELECT) {
+            data.select_editor_line(*index);
+            return Handled::Yes;
+        }
+        if let Some((file, selected)) = cmd.get(SELECT_SONG) {
+            for song in data
+                .current_play_list
+                .iter_mut()
+                .filter(|s| s.file == *file)
+            {
+                song.selected = *selected;
+            }
+            return Handled::Yes;
+        }
+        if let Some((file, result)) = cmd.get(tagging::TAGS_WRITTEN) {
+            match result {
+                Ok(()) => {
+                    data.reload_song(file);
+                    data.message = format!("标签已写入 {}", file);
+                }
+                Err(e) => data.message = format!("写入标签失败 {}: {}", file, e),
+            }
+            return Handled::Yes;
+        }
+        if let Some(station) = cmd.get(PLAY_STATION) {
+            play_now(data, station.to_song());
+            return Handled::Yes;
+        }
+        if let Some(result) = cmd.get(podcast::FEED_LOADED) {
+            match result {
+                Ok(fresh) => {
+                    match data.podcasts.iter_mut().find(|p| p.url == fresh.url) {
+                        Some(old) => old.merge(fresh.to_owned()),
+                        None => data.podcasts.push_back(fresh.to_owned()),
+                    }
+                    data.message = format!("已更新播客 {}", fresh.title);
+                }
+                Err(e) => data.message = format!("读取订阅失败: {}", e),
+            }
+            return Handled::Yes;
+        }
+        if let Some(url) = cmd.get(REFRESH_FEED) {
+            podcast::fetch_async(url.to_owned(), ctx.get_external_handle());
+            return Handled::Yes;
+        }
+        if let Some(url) = cmd.get(UNSUBSCRIBE) {
+            data.podcasts.retain(|p| p.url != *url);
+            return Handled::Yes;
+        }
+        if let Some((url, id)) = cmd.get(DOWNLOAD_EPISODE) {
+            let title = data
+                .podcasts
+                .iter()
+                .find(|p| p.url == *url)
+                .map_or("".to_owned(), |p| p.title.to_owned());
+            if let Some(episode) = data.episode_mut(url, id) {
+                episode.downloading = true;
+                podcast::download_async(title, episode.to_owned(), ctx.get_external_handle());
+            }
+            return Handled::Yes;
+        }
+        if let Some((url, id, result)) = cmd.get(podcast::EPISODE_DOWNLOADED) {
+            let mut message = "".to_owned();
+            if let Some(episode) = data.episode_mut(url, id) {
+                episode.downloading = false;
+                match result {
+                    Ok(file) => {
+                        episode.file = file.to_owned();
+                        if episode.duration <= 0.0 {
+                            episode.duration = get_song_meta(file, "none").duration;
+                        }
+                        message = format!("已下载 {}", episode.title);
+                    }
+                    Err(e) => message = format!("下载 {} 失败: {}", episode.title, e),
+                }
+            }
+            data.message = message;
+            return Handled::Yes;
+        }
+        if let Some((url, id)) = cmd.get(PLAY_EPISODE) {
+            let podcast_title = data
+                .podcasts
+                .iter()
+                .find(|p| p.url == *url)
+                .map_or("".to_owned(), |p| p.title.to_owned());
+            let song = match data.episode_mut(url, id) {
+                Some(episode) if !episode.file.is_empty() => {
+                    // 已经播放完的单集从头开始
+                    if episode.played {
+                        episode.played = false;
+                        episode.position_ms = 0;
+                    }
+                    Song {
+                        title: episode.title.to_owned(),
+                        artist: podcast_title.to_owned(),
+                        album: podcast_title,
+                        duration: episode.duration,
+                        date: episode.published.to_owned(),
+                        file: episode.file.to_owned(),
+                        ..Song::default()
+                    }
+                }
+                _ => return Handled::Yes,
+            };
+            data.sync_podcasts();
+            play_now(data, song);
+            return Handled::Yes;
+        }
+        if let Some(url) = cmd.get(REMOVE_STATION) {
+            data.stations.retain(|s| s.url != *url);
+            return Handled::Yes;
+        }
+        if let Some((url, title)) = cmd.get(radio::STREAM_TITLE) {
+            data.stream_url = url.to_owned();
+            data.stream_title = title.to_owned();
+            return Handled::Yes;
+        }
+        if let Some(result) = cmd.get(config::CONFIG_RELOADED) {
+            match result {
+                Ok(config) => {
+                    data.config = Arc::new(config.to_owned());
+                    data.import_roots();
+                    data.message = "配置已重新加载".to_owned();
+                }
+                Err(e) => data.message = e.to_owned(),
+            }
+            return Handled::Yes;
+        }
+        if let Some((level, key)) = cmd.get(BROWSE_SELECT) {
+            data.browse_path.truncate(*level);
+            data.browse_path.push_back(key.to_owned());
+            data.refresh_browse();
+            data.refresh_show_list();
+            return Handled::Yes;
+        }
+        if let Some(song) = cmd.get(QUEUE_APPEND) {
+            data.queue.lock().unwrap().append(song.to_owned());
+            data.sync_queue_view();
+            return Handled::Yes;
+        }
+        if let Some(song) = cmd.get(QUEUE_PLAY_NEXT) {
+            data.queue.lock().unwrap().play_next(song.to_owned());
+            data.sync_queue_view();
+            return Handled::Yes;
+        }
+        if let Some(index) = cmd.get(QUEUE_REMOVE) {
+            data.queue.lock().unwrap().remove(*index);
+            data.sync_queue_view();
+            return Handled::Yes;
+        }
+        if let Some((from, to)) = cmd.get(QUEUE_MOVE) {
+            data.queue.lock().unwrap().move_item(*from, *to);
+            data.sync_queue_view();
             return Handled::Yes;
         }
         Handled::No
@@ -83,6 +622,9 @@ impl AppDelegate<AppState> for MenuDelegate {
         data: &mut AppState,
         env: &Env,
     ) -> Option<Event> {
+        if let Event::WindowSize(size) = &event {
+            data.window_size = *size;
+        }
         Some(event)
     }
 
@@ -102,10 +644,14 @@ impl AppDelegate<AppState> for MenuDelegate {
         env: &Env,
         ctx: &mut DelegateCtx,
     ) {
+        // 关闭窗口时保存会话
+        data.sync_podcasts();
+        session::save(&data.to_session());
+        podcast::save(&data.to_podcast_store());
     }
 }
 
-fn load_files(dir: &str) -> Vector<Song> {
+fn load_files(dir: &str, config: &Config) -> Vector<Song> {
     let mut songs = vector![];
     let dir = Path::new(dir);
     // 读取当前目录下的音乐文件。
@@ -115,7 +661,7 @@ fn load_files(dir: &str) -> Vector<Song> {
         .map(|res| res.ok().map(|e| e.path().display().to_string()))
         .into_iter()
         .map(|x| x.unwrap())
-        .filter(|x| is_music_file(x))
+        .filter(|x| sniff::is_music_file(x, config))
         .collect();
 
     // 读取目录下的子目录的音乐文件
@@ -129,8 +675,8 @@ fn load_files(dir: &str) -> Vector<Song> {
                         .map(|res| res.ok().map(|e| e.path().display().to_string()))
                         .into_iter()
                         .map(|x| x.unwrap())
-                        .filter(|x| is_music_file(x))
-                        .for_each(|f| songs.push_back(get_song_meta(&f)))
+                        .filter(|x| sniff::is_music_file(x, config))
+                        .for_each(|f| songs.push_back(read_song(&f, config)))
                 }
             }
         }
@@ -138,25 +684,55 @@ fn load_files(dir: &str) -> Vector<Song> {
 
     files.sort();
     for i in &files {
-        let s = get_song_meta(i);
+        let s = read_song(i, config);
         songs.push_back(s);
     }
     songs
 }
 
-fn get_song_meta(f: &str) -> Song {
+// 读取标签，缺少的字段按配置的规则从文件名推断
+fn read_song(f: &str, config: &Config) -> Song {
+    let mut song = get_song_meta(f, config.encoding_for(f));
+    infer::fill_missing(&mut song, &config.library.filename_pattern);
+    song
+}
+
+// encoding 为 MP3 旧标签使用的编码，见 encoding::fix
+fn get_song_meta(f: &str, encoding: &str) -> Song {
     let mut song = Song::default();
     ffmpeg::init().unwrap();
+    // 只有 MP3 的 ID3v1 和 ID3v2.3 标签会按 Latin-1 保存中日文
+    let legacy = sniff::detect(f) == Some(sniff::AudioKind::Mpeg);
 
     match ffmpeg::format::input(&Path::new(f)) {
         Ok(context) => {
             let mut is_has_title = false;
-            for (k, v) in context.metadata().iter() {
+            let mut tags: Vec<(String, String)> = context
+                .metadata()
+                .iter()
+                .map(|(k, v)| (k.to_owned(), v.to_owned()))
+                .collect();
+            // Ogg 的标签保存在音频流中
+            if let Some(stream) = context.streams().best(ffmpeg::media::Type::Audio) {
+                tags.extend(
+                    stream
+                        .metadata()
+                        .iter()
+                        .map(|(k, v)| (k.to_owned(), v.to_owned())),
+                );
+            }
+            for (k, v) in tags.iter_mut() {
                 let k_lower = k.to_lowercase();
-                // 跳过???乱码的值
+                // 跳过???乱码的值，这种值在写入时已经丢失了原来的文字，无法还原
                 if v.starts_with("?") {
                     continue;
                 }
+                if legacy {
+                    if let Some((fixed, name)) = encoding::fix(v, encoding) {
+                        *v = fixed;
+                        song.encoding = name.to_owned();
+                    }
+                }
                 match k_lower.as_str() {
                     "title" => {
                         song.title = v.to_string();
@@ -165,19 +741,26 @@ fn get_song_meta(f: &str) -> Song {
                     "album" => song.album = v.to_string(),
                     "artist" => song.artist = v.to_string(),
                     "date" => song.date = v.to_string(),
+                    "genre" => song.genre = v.to_string(),
+                    "album_artist" | "albumartist" | "album artist" => {
+                        song.album_artist = v.to_string()
+                    }
+                    "track" | "tracknumber" => song.track = v.to_string(),
+                    "disc" | "discnumber" => song.disc = v.to_string(),
+                    // FLAC/Vorbis 的 LYRICS, UNSYNCEDLYRICS 和 ID3 的 USLT(lyrics-eng 等)
+                    k if k == "lyrics" || k.starts_with("lyrics-") || k == "unsyncedlyrics" => {
+                        song.lyrics = v.to_string()
+                    }
                     _ => {}
                 }
             }
             if !is_has_title {
-                song.title = {
-                    let split_strs: Vec<&str> = f.split("/").collect();
-                    let mut name: String = split_strs.last().unwrap().to_string();
-                    let music_exts: Vec<&str> = vec![".flac", ".mp3", ".wav", ".m4a", ".ogg"];
-                    for ext in music_exts {
-                        name = name.trim_end_matches(ext).to_owned()
-                    }
-                    name
-                }
+                // 没有标题时使用去掉扩展名的文件名
+                song.title_from_file = true;
+                song.title = Path::new(f)
+                    .file_stem()
+                    .map(|n| n.to_string_lossy().to_string())
+                    .unwrap_or_default();
             }
             song.duration =
                 (context.duration() as f64 / f64::from(ffmpeg::ffi::AV_TIME_BASE)).round();
@@ -189,15 +772,6 @@ fn get_song_meta(f: &str) -> Song {
     song
 }
 
-fn is_music_file(f: &str) -> bool {
-    let music_exts: Vec<&str> = vec![".flac", ".mp3", ".wav", ".m4a", ".ogg"];
-    for x in &music_exts {
-        if f.ends_with(x) {
-            return true;
-        }
-    }
-    return false;
-}
 fn make_menu<T: Data>() -> MenuDesc<T> {
     let mut base = MenuDesc::empty();
     #[cfg(target_os = "macos")]
@@ -261,17 +835,66 @@ fn ui_builder() -> impl Widget<AppState> {
             Slider::new()
                 .with_range(0.0, 1.)
                 .lens(AppState::volume)
+                // bit-perfect 模式下不做软件音量调节
+                .disabled_if(|d: &AppState, _| d.bit_perfect)
                 .on_click(|_ctx, data, _env| {
                     data.sink.lock().unwrap().set_volume(data.volume as f32);
                 }),
         )
+        .with_default_spacer()
+        .with_child(
+            Checkbox::new(LocalizedString::new("Resume on launch")).lens(AppState::auto_resume),
+        )
         .align_right()
         .padding(10.0);
 
+    // 输出设备选择
+    let device_picker = Flex::row()
+        .with_child(Label::new(LocalizedString::new("Output device")))
+        .with_default_spacer()
+        .with_child(Label::dynamic(|d: &AppState, _| {
+            if d.device.is_empty() {
+                "默认设备".to_owned()
+            } else {
+                d.device.to_owned()
+            }
+        }))
+        .with_default_spacer()
+        .with_child(Button::new(LocalizedString::new("Refresh")).on_click(
+            |_ctx, data: &mut AppState, _env| {
+                data.devices = output::device_names().into_iter().collect();
+            },
+        ))
+        .with_default_spacer()
+        .with_child(
+            Button::new("默认设备").on_click(|ctx, _data: &mut AppState, _env| {
+                ctx.submit_command(SELECT_DEVICE.with("".to_owned()));
+            }),
+        )
+        .with_flex_child(
+            Scroll::new(
+                List::new(|| {
+                    Button::dynamic(|name: &String, _| name.to_owned()).on_click(
+                        |ctx, name: &mut String, _env| {
+                            ctx.submit_command(SELECT_DEVICE.with(name.to_owned()));
+                        },
+                    )
+                })
+                .horizontal()
+                .lens(AppState::devices),
+            )
+            .horizontal(),
+            1.0,
+        )
+        .padding(5.0);
+
     // 当天歌曲名称显示
     let current_song_title = Label::dynamic(|d: &AppState, _env| {
         let current = d.current_song.lock().unwrap();
-        if current.playing {
+        if current.playing && current.file == d.stream_url && !d.stream_title.is_empty() {
+            // 电台显示当前曲目和电台名称
+            format!("{}   -   {}", d.stream_title, current.title)
+        } else if current.playing {
             format!("{}   -   {}", current.title, current.artist)
         } else {
             "".to_owned()
@@ -285,176 +908,45 @@ fn ui_builder() -> impl Widget<AppState> {
         Flex::row()
             .with_child(
                 // 上一首按钮
-                Button::new("|<<")
-                    .lens(AppState::current_play_list)
-                    .on_click(|_ctx, data, _env| {
-                        // 通过点击操作主动更新，当前歌曲状态和播放列表标记。
-                        if let Some(current) =
-                            prev_song(data.play_mode.to_owned(), &mut data.current_play_list)
-                        {
-                            *data.current_song.lock().unwrap() = current;
-                            data.sink.lock().unwrap().set_volume(data.volume as f32);
-                            *data.app_status.lock().unwrap() = Status::Prev;
-                        }
-                    }),
+                Button::new("|<<").on_click(|_ctx, data: &mut AppState, _env| {
+                    // 切歌由播放线程从队列中取歌
+                    data.sink.lock().unwrap().set_volume(data.volume as f32);
+                    *data.app_status.lock().unwrap() = Status::Prev;
+                }),
             )
             .with_default_spacer()
             .with_child(
                 // 播放按钮
                 Button::new(LocalizedString::new("Play"))
-                    .lens(AppState::current_play_list)
-                    .on_click(|_ctx, data, _env| {
-                        *data.app_status.lock().unwrap() = Status::Stop;
-                        *data.app_status.lock().unwrap() = Status::Play;
-                        if data.sink.lock().unwrap().is_paused() {
-                            data.sink.lock().unwrap().play();
-                        } else {
-                            if data.sink.lock().unwrap().empty() {
-                                *data.sink.lock().unwrap() =
-                                    rodio::Sink::try_new(&data.stream).unwrap();
-                                data.sink.lock().unwrap().set_volume(data.volume as f32);
-
-                                let stream = data.stream.clone();
-                                let play_sink = Arc::clone(&data.sink);
-                                let mut play_list = data.current_play_list.clone();
-                                let current_song = Arc::clone(&mut data.current_song);
-                                let app_status = Arc::clone(&mut data.app_status);
-
-                                // 启动单独进程进行进行播放列表内歌曲按顺序播放
-                                spawn(move || {
-                                    // count 已播放歌曲计数
-                                    let mut count = 0;
-                                    // 以歌曲数总数为最大数，有限循环播放。play_sink.len最大为1，播放完一首，当len==0时，再加入下一首歌曲。
-                                    // 上一首，下一首，切歌操作，以app_status 的状态切换来控制。
-                                    while count < play_list.len()
-                                        && !app_status.lock().unwrap().same(&Status::Stop)
-                                    {
-                                        if play_sink.lock().unwrap().empty() {
-                                            // 当前播放歌曲为空时，播放第一首歌曲，进入下一次循环。
-                                            if current_song.lock().unwrap().title.is_empty() {
-                                                let mut cur = play_list.get_mut(0).unwrap();
-                                                cur.playing = true;
-                                                *current_song.lock().unwrap() = cur.to_owned();
-                                                add_paly_song(
-                                                    &current_song.lock().unwrap().file,
-                                                    &play_sink.lock().unwrap(),
-                                                );
-                                                continue;
-                                            }
-                                            let mut status = app_status.lock().unwrap();
-                                            match *status {
-                                                Status::Play => {
-                                                    count += 1;
-                                                    if let Some(mut cur) =
-                                                        next_song(Modes::Order, &mut play_list)
-                                                    {
-                                                        cur.playing = true;
-                                                        *current_song.lock().expect("lock error") =
-                                                            cur;
-                                                        add_paly_song(
-                                                            &current_song.lock().unwrap().file,
-                                                            &play_sink.lock().unwrap(),
-                                                        );
-                                                    }
-                                                }
-
-                                                Status::Stop => break,
-                                                Status::Suspend => (),
-                                                Status::Next => {
-                                                    count += 1;
-                                                    if let Some(mut cur) =
-                                                        next_song(Modes::Order, &mut play_list)
-                                                    {
-                                                        cur.playing = true;
-                                                        *current_song.lock().expect("lock error") =
-                                                            cur;
-                                                        add_paly_song(
-                                                            &current_song.lock().unwrap().file,
-                                                            &play_sink.lock().unwrap(),
-                                                        );
-                                                    }
-                                                    *status = Status::Play;
-                                                }
-                                                Status::Prev => {
-                                                    count -= 1;
-                                                    if let Some(mut cur) =
-                                                        prev_song(Modes::Order, &mut play_list)
-                                                    {
-                                                        cur.playing = true;
-                                                        *current_song.lock().expect("lock error") =
-                                                            cur;
-                                                        add_paly_song(
-                                                            &current_song.lock().unwrap().file,
-                                                            &play_sink.lock().unwrap(),
-                                                        );
-                                                    }
-                                                    *status = Status::Play;
-                                                }
-                                            }
-                                        }
-                                        if play_sink.lock().unwrap().len() == 1 {
-                                            let status = app_status.lock().unwrap();
-                                            match *status {
-                                                Status::Play => (),
-
-                                                Status::Stop => break,
-                                                Status::Suspend => (),
-                                                Status::Next => {
-                                                    // rodio::sink stop后就无法重新播放，只能重新初始化rodio::Sink::try_new(&stream)。
-                                                    play_sink.lock().unwrap().stop();
-                                                    *play_sink.lock().unwrap() =
-                                                        rodio::Sink::try_new(&stream).unwrap();
-                                                }
-                                                Status::Prev => {
-                                                    // rodio::sink stop后就无法重新播放，只能重新初始化rodio::Sink::try_new(&stream)。
-                                                    play_sink.lock().unwrap().stop();
-                                                    *play_sink.lock().unwrap() =
-                                                        rodio::Sink::try_new(&stream).unwrap();
-                                                }
-                                            }
-                                        }
-                                        sleep(std::time::Duration::from_secs(1));
-                                    }
-                                });
-                            }
-                        }
-                    }),
+                    .on_click(|_ctx, data: &mut AppState, _env| play(data)),
             )
             .with_default_spacer()
             .with_child(
                 // 暂停按钮
-                Button::new(LocalizedString::new("Pause"))
-                    .lens(AppState::current_play_list)
-                    .on_click(|_ctx, data, _env| {
+                Button::new(LocalizedString::new("Pause")).on_click(
+                    |_ctx, data: &mut AppState, _env| {
                         data.sink.lock().unwrap().pause();
                         *data.app_status.lock().unwrap() = Status::Suspend;
-                    }),
+                    },
+                ),
             )
             .with_default_spacer()
             .with_child(
                 // 停止按钮
-                Button::new(LocalizedString::new("Stop"))
-                    .lens(AppState::current_play_list)
-                    .on_click(|_ctx, data, _env| {
+                Button::new(LocalizedString::new("Stop")).on_click(
+                    |_ctx, data: &mut AppState, _env| {
                         data.sink.lock().unwrap().stop();
                         *data.app_status.lock().unwrap() = Status::Stop;
-                    }),
+                    },
+                ),
             )
             .with_default_spacer()
             .with_child(
                 // 下一首按钮
-                Button::new(">>|")
-                    .lens(AppState::current_play_list)
-                    .on_click(|_ctx, data, _env| {
-                        // 通过点击事件,主动更新当前歌曲状态和播放列表标记。
-                        if let Some(current) =
-                            next_song(data.play_mode.to_owned(), &mut data.current_play_list)
-                        {
-                            *data.current_song.lock().unwrap() = current;
-                            data.sink.lock().unwrap().set_volume(data.volume as f32);
-                            *data.app_status.lock().unwrap() = Status::Next;
-                        }
-                    }),
+                Button::new(">>|").on_click(|_ctx, data: &mut AppState, _env| {
+                    data.sink.lock().unwrap().set_volume(data.volume as f32);
+                    *data.app_status.lock().unwrap() = Status::Next;
+                }),
             ),
     )
     .align_left();
@@ -470,6 +962,35 @@ fn ui_builder() -> impl Widget<AppState> {
                 .with_child(volume),
         )
         .with_default_spacer()
+        .with_child(device_picker)
+        .with_child(
+            Flex::row()
+                .with_child(
+                    Flex::row()
+                        .with_child(
+                            Checkbox::new(LocalizedString::new("Bit-perfect"))
+                                .lens(AppState::bit_perfect),
+                        )
+                        .with_default_spacer()
+                        .with_child(Label::new(LocalizedString::new("Resampler")))
+                        .with_child(
+                            Flex::row()
+                                .with_child(Radio::new("Off", ResampleQuality::Off))
+                                .with_child(Radio::new("Fast", ResampleQuality::Fast))
+                                .with_child(Radio::new("Medium", ResampleQuality::Medium))
+                                .with_child(Radio::new("High", ResampleQuality::High))
+                                .lens(AppState::resample_quality),
+                        )
+                        .controller(PlaybackSettingsController),
+                )
+                .with_default_spacer()
+                .with_child(
+                    Label::dynamic(|d: &AppState, _| d.format_info.lock().unwrap().to_owned())
+                        .with_text_size(12.0),
+                ),
+        )
+        // 提示信息
+        .with_child(Label::dynamic(|d: &AppState, _| d.message.to_owned()).with_text_size(12.0))
         .cross_axis_alignment(CrossAxisAlignment::Center);
 
     let play_list_header = vector![
@@ -481,14 +1002,150 @@ fn ui_builder() -> impl Widget<AppState> {
         LocalizedString::new("Date"),
     ];
 
-    let mut header: Flex<AppState> = Flex::row()
+    // 搜索框，输入时实时过滤列表
+    let search_bar = Flex::row()
+        .with_default_spacer()
+        .with_child(Label::new(LocalizedString::new("Search")))
+        .with_default_spacer()
+        .with_flex_child(
+            TextBox::new()
+                .with_placeholder("artist:miles year:1959")
+                .lens(AppState::search_text)
+                .expand_width(),
+            1.0,
+        )
+        .with_default_spacer()
+        .with_child(Checkbox::new(LocalizedString::new("Broken only")).lens(AppState::show_broken))
+        .with_child(
+            Checkbox::new(LocalizedString::new("Damaged only")).lens(AppState::show_damaged),
+        )
+        .with_default_spacer()
+        .with_child(Button::new(LocalizedString::new("Select all")).on_click(
+            |_ctx, data: &mut AppState, _env| {
+                data.set_selected(None, true);
+            },
+        ))
+        .with_child(
+            Button::new(LocalizedString::new("Clear selection")).on_click(
+                |_ctx, data: &mut AppState, _env| {
+                    data.clear_selection();
+                },
+            ),
+        )
+        .with_child(Button::new(LocalizedString::new("Edit tags")).on_click(
+            |ctx, data: &mut AppState, _env| {
+                if data.open_tag_editor() {
+                    ctx.new_window(
+                        WindowDesc::new(tag_editor)
+                            .title(LocalizedString::new("Edit tags"))
+                            .window_size((520.0, 400.0)),
+                    );
+                }
+            },
+        ))
+        .with_child(Button::new(LocalizedString::new("Rename files")).on_click(
+            |ctx, data: &mut AppState, _env| {
+                if data.preview_rename() {
+                    ctx.new_window(
+                        WindowDesc::new(rename_window)
+                            .title(LocalizedString::new("Rename files"))
+                            .window_size((760.0, 520.0)),
+                    );
+                }
+            },
+        ))
+        .with_child(Button::new(LocalizedString::new("Save as UTF-8")).on_click(
+            |_ctx, data: &mut AppState, _env| {
+                data.rewrite_utf8();
+            },
+        ))
+        .with_child(
+            Button::new(LocalizedString::new("Find duplicates")).on_click(
+                |ctx, _data: &mut AppState, _env| {
+                    ctx.new_window(
+                        WindowDesc::new(duplicates_window)
+                            .title(LocalizedString::new("Find duplicates"))
+                            .window_size((800.0, 560.0)),
+                    );
+                },
+            ),
+        )
+        .with_child(
+            Button::new(LocalizedString::new("Verify library")).on_click(
+                |ctx, _data: &mut AppState, _env| {
+                    ctx.new_window(
+                        WindowDesc::new(integrity_window)
+                            .title(LocalizedString::new("Verify library"))
+                            .window_size((800.0, 560.0)),
+                    );
+                },
+            ),
+        )
+        .with_child(Button::new(LocalizedString::new("Sync")).on_click(
+            |ctx, _data: &mut AppState, _env| {
+                ctx.new_window(
+                    WindowDesc::new(sync_window)
+                        .title(LocalizedString::new("Sync"))
+                        .window_size((640.0, 480.0)),
+                );
+            },
+        ))
+        .with_child(Button::new(LocalizedString::new("Export")).on_click(
+            |ctx, _data: &mut AppState, _env| {
+                ctx.new_window(
+                    WindowDesc::new(export_window)
+                        .title(LocalizedString::new("Export"))
+                        .window_size((640.0, 320.0)),
+                );
+            },
+        ))
+        .with_child(
+            Button::new(LocalizedString::new("Match album"))
+                .on_click(|ctx, data: &mut AppState, _env| {
+                    if data.start_match() {
+                        ctx.new_window(
+                            WindowDesc::new(match_window)
+                                .title(LocalizedString::new("Match album"))
+                                .window_size((800.0, 600.0)),
+                        );
+                    }
+                })
+                .disabled_if(|d: &AppState, _| d.matching),
+        )
+        .with_child(Button::new(LocalizedString::new("Infer tags")).on_click(
+            |ctx, data: &mut AppState, _env| {
+                if data.preview_infer() {
+                    ctx.new_window(
+                        WindowDesc::new(infer_window)
+                            .title(LocalizedString::new("Infer tags"))
+                            .window_size((760.0, 520.0)),
+                    );
+                }
+            },
+        ))
         .with_default_spacer()
-        .with_child(Label::new(LocalizedString::new("Playing")))
-        .with_spacer(70.0);
+        .controller(SearchController);
 
-    for label in play_list_header.iter().skip(1) {
-        header.add_child(Label::new(label.to_owned()));
-        header.add_spacer(180.0);
+    let mut header: Flex<AppState> = Flex::row()
+        .with_spacer(35.0)
+        .with_child(Label::new(LocalizedString::new("Playing")).fix_width(80.0))
+        .with_spacer(50.0);
+
+    for (index, (label, width)) in play_list_header
+        .iter()
+        .skip(1)
+        .zip(COLUMN_WIDTHS.iter())
+        .enumerate()
+    {
+        header.add_child(Label::new(label.to_owned()).fix_width(width.to_owned()));
+        // 拖动列右侧的分隔线调整列宽
+        header.add_child(
+            Label::new("|")
+                .with_text_color(Color::grey(0.5))
+                .fix_width(8.0)
+                .controller(ColumnResizeController { index, drag: None }),
+        );
+        header.add_spacer(92.0);
     }
 
     // 播放列表
@@ -497,7 +1154,7 @@ fn ui_builder() -> impl Widget<AppState> {
             .with_default_spacer()
             .cross_axis_alignment(CrossAxisAlignment::Start)
             .with_flex_child(
-                Scroll::new(List::new(make_item).lens(AppState::current_play_list)).vertical(),
+                Scroll::new(List::new(make_item).lens(AppState::show_list)).vertical(),
                 1.0,
             )
             .expand_width(),
@@ -508,7 +1165,22 @@ fn ui_builder() -> impl Widget<AppState> {
     Container::new(
         Split::rows(
             play_panel.padding(10.),
-            Split::rows(header, play_list).split_point(0.05),
+            Split::columns(
+                browse_panel(),
+                Split::columns(
+                    Split::rows(
+                        Flex::column()
+                            .with_child(search_bar)
+                            .with_default_spacer()
+                            .with_child(header),
+                        play_list,
+                    )
+                    .split_point(0.1),
+                    side_panel(),
+                )
+                .split_point(0.75),
+            )
+            .split_point(0.2),
         )
         .split_point(0.1),
     )
@@ -521,96 +1193,2625 @@ fn ui_builder() -> impl Widget<AppState> {
                 v.playing = false;
             }
         }
+        data.refresh_show_list();
+        data.sync_queue_view();
         ctx.children_changed();
     })
+    .controller(SessionController {
+        timer: TimerToken::INVALID,
+    })
+    .controller(PodcastController {
+        timer: TimerToken::INVALID,
+    })
+    .controller(LyricsController {
+        timer: TimerToken::INVALID,
+    })
+    .controller(KeyController)
+    // 配置文件中的主题，修改后立即生效；列宽随拖动变化
+    .env_scope(|env, data: &AppState| {
+        apply_theme(env, &data.config.theme);
+        for (key, width) in COLUMN_WIDTHS.iter().zip(data.column_widths.iter()) {
+            env.set(key.to_owned(), *width);
+        }
+    })
 }
 
 #[derive(Data, Lens, Clone)]
 struct AppState {
     music_dir: String,
+    // 已导入的音乐目录
+    music_dirs: Vector<String>,
+    browse_mode: BrowseMode,
+    browse_path: Vector<String>,
+    browse_nodes: Vector<BrowseNode>,
+    // 播放队列，播放线程和界面共享
+    queue: Arc<Mutex<PlayQueue>>,
+    queue_view: Vector<QueueItem>,
+    // 当前歌曲播放进度(毫秒)，由播放线程更新
+    position: Arc<AtomicU64>,
+    window_size: Size,
+    column_widths: Vector<f64>,
+    auto_resume: bool,
+    config: Arc<Config>,
+    // 提示信息
+    message: String,
     app_status: Arc<Mutex<Status>>,
     play_lists: Vector<PlayList>,
     current_song: Arc<Mutex<Song>>,
     sink: Arc<Mutex<rodio::Sink>>,
     progress_rate: f64,
     current_play_list: Vector<Song>,
+    // 搜索过滤后用于显示的列表，播放仍然使用 current_play_list
+    show_list: Vector<Song>,
+    search_text: String,
+    // 只显示无法播放的歌曲
+    show_broken: bool,
+    // 只显示完整性校验发现问题的歌曲
+    show_damaged: bool,
     volume: f64,
     play_mode: Modes,
-    stream: Arc<OutputStreamHandle>,
+    // 当前输出设备，没有可用设备时为 None
+    stream: Arc<Mutex<Option<OutputStreamHandle>>>,
+    output: Arc<Mutex<Option<AudioOutput>>>,
+    device: String,
+    devices: Vector<String>,
+    // bit-perfect 输出模式和重采样质量，修改后同步到 playback_settings 供播放线程读取
+    bit_perfect: bool,
+    resample_quality: ResampleQuality,
+    playback_settings: Arc<Mutex<PlaybackSettings>>,
+    // 源格式和输出格式
+    format_info: Arc<Mutex<String>>,
+    events: Arc<Option<ExtEventSink>>,
+    // 网络电台列表和添加电台的输入框
+    stations: Vector<Station>,
+    station_name: String,
+    station_url: String,
+    // 电台 ICY 元数据中的当前曲目
+    stream_url: String,
+    stream_title: String,
+    side_panel: SidePanel,
+    // 播客订阅，播放进度由 sync_podcasts 定时同步
+    podcasts: Vector<Podcast>,
+    podcast_auto_delete: bool,
+    feed_url: String,
+    // 播客单集的继续播放位置，播放线程读取
+    bookmarks: Arc<Mutex<HashMap<String, u64>>>,
+    // 当前歌曲的歌词，歌曲变化时重新读取
+    lyrics: Arc<Lyrics>,
+    lyrics_file: String,
+    lyrics_view: Vector<LyricRow>,
+    // 歌词编辑器，editor_text 为粘贴的纯文本，editor_cursor 为下一次打点的行
+    lyrics_editing: bool,
+    editor_file: String,
+    editor_text: String,
+    editor_lines: Vector<EditorLine>,
+    editor_cursor: usize,
+    // 标签编辑窗口中正在编辑的文件和字段，字段顺序同 TAG_FIELDS
+    tag_files: Vector<String>,
+    tag_fields: Vector<TagField>,
+    // 按规则重命名的预览和上一次重命名，用于撤销
+    rename_pattern: String,
+    rename_rows: Vector<RenameRow>,
+    last_renames: Arc<Vec<(String, String)>>,
+    // 按文件名推断标签的规则和预览
+    infer_pattern: String,
+    infer_rows: Vector<InferRow>,
+    // 按声纹查找的重复歌曲
+    duplicates: Vector<DuplicateGroup>,
+    duplicate_scanning: bool,
+    duplicate_progress: String,
+    duplicate_cancel: Arc<AtomicBool>,
+    // 在本地 MusicBrainz 数据包中匹配到的发行
+    match_candidates: Vector<ReleaseCandidate>,
+    match_status: String,
+    matching: bool,
+    // 导出(转换格式)的设置和进度
+    export_dir: String,
+    export_format: ExportFormat,
+    export_rate: u32,
+    export_bits: u16,
+    export_progress: String,
+    exporting: bool,
+    export_cancel: Arc<AtomicBool>,
+    // 同步播放列表到设备
+    new_playlist_name: String,
+    sync_dir: String,
+    sync_transcode: SyncTranscode,
+    sync_progress: String,
+    syncing: bool,
+    sync_cancel: Arc<AtomicBool>,
+    // 属性窗口中显示的文件信息
+    inspection: Inspection,
+    // 完整性校验发现问题的歌曲
+    integrity_report: Vector<Song>,
+    integrity_checking: bool,
+    integrity_progress: String,
+    integrity_cancel: Arc<AtomicBool>,
 }
 
-#[derive(Clone, Data, PartialEq, Debug)]
-enum Status {
-    Play,
-    Suspend,
-    Stop,
-    Next,
-    Prev,
+// 右侧面板显示的内容
+#[derive(Clone, Copy, Data, PartialEq, Debug)]
+enum SidePanel {
+    Queue,
+    Radio,
+    Podcasts,
+    Lyrics,
 }
 
-#[derive(Data, Lens, Default, Clone)]
-struct PlayList {
-    name: String,
-    songs: Vector<Song>,
-}
+impl AppState {
+    // 根据浏览面板选中的节点和搜索框内容重新生成显示列表
+    fn refresh_show_list(&mut self) {
+        let selected = browse::songs_at(
+            &self.current_play_list,
+            &self.browse_mode,
+            &self.browse_path,
+        );
+        self.show_list = search::filter(&selected, &self.search_text);
+        if self.show_broken {
+            self.show_list.retain(|s| !s.broken.is_empty());
+        }
+        if self.show_damaged {
+            self.show_list.retain(|s| !s.damaged.is_empty());
+        }
+    }
 
-#[derive(Data, Lens, Default, Clone)]
-struct Current {
-    name: String,
-    cover_image: String,
-}
+    // 重新生成浏览面板节点，已经到最后一级时保留同级节点
+    fn refresh_browse(&mut self) {
+        let roots = &self.music_dirs;
+        self.browse_nodes = browse::nodes_at(
+            &self.current_play_list,
+            &self.browse_mode,
+            &self.browse_path,
+            roots,
+        );
+        if self.browse_nodes.is_empty() && !self.browse_path.is_empty() {
+            let mut parent = self.browse_path.clone();
+            parent.pop_back();
+            self.browse_nodes =
+                browse::nodes_at(&self.current_play_list, &self.browse_mode, &parent, roots);
+        }
+    }
 
-#[derive(Clone, Data, PartialEq)]
-enum Modes {
-    Order,
-    Random,
-    Repet,
-}
+    // 恢复资料库和播放队列
+    fn restore_session(&mut self, session: &session::Session) {
+        for dir in session.music_dirs.iter() {
+            if Path::new(dir).is_dir() {
+                self.current_play_list.extend(load_files(dir, &self.config));
+            }
+        }
+        self.current_play_list
+            .sort_by(|left, right| left.album.cmp(&right.album));
+        let songs = session
+            .queue
+            .iter()
+            .map(|f| {
+                if radio::is_stream(f) {
+                    // 电台不在资料库中，启动时也不去连接
+                    return self
+                        .stations
+                        .iter()
+                        .find(|s| s.url == *f)
+                        .cloned()
+                        .unwrap_or_else(|| Station {
+                            name: f.to_owned(),
+                            url: f.to_owned(),
+                        })
+                        .to_song();
+                }
+                self.current_play_list
+                    .iter()
+                    .find(|s| s.file == *f)
+                    .cloned()
+                    .unwrap_or_else(|| read_song(f, &self.config))
+            })
+            .collect();
+        self.queue.lock().unwrap().restore(songs, session.current);
+        self.play_lists = session
+            .playlists
+            .iter()
+            .map(|p| PlayList {
+                name: p.name.to_owned(),
+                songs: p
+                    .files
+                    .iter()
+                    .map(|f| {
+                        self.current_play_list
+                            .iter()
+                            .find(|s| s.file == *f)
+                            .cloned()
+                            .unwrap_or_else(|| read_song(f, &self.config))
+                    })
+                    .collect(),
+                sync: p.sync,
+            })
+            .collect();
+        self.import_roots();
+        // 上次校验发现问题的文件
+        let damaged = integrity::load_damaged();
+        self.apply_integrity(|file| damaged.get(file).cloned());
+        self.refresh_browse();
+        self.refresh_show_list();
+        self.sync_queue_view();
+    }
 
-#[derive(Data, Lens, Default, Clone, Debug)]
-struct Song {
-    title: String,
-    artist: String,
-    album: String,
-    duration: f64,
-    playing: bool,
-    date: String,
-    file: String,
-}
+    // 导入配置文件中还没有导入的音乐目录
+    fn import_roots(&mut self) {
+        let roots = self.config.library.roots.to_owned();
+        for dir in roots {
+            if self.music_dirs.contains(&dir) || !Path::new(&dir).is_dir() {
+                continue;
+            }
+            self.current_play_list
+                .extend(load_files(&dir, &self.config));
+            self.music_dirs.push_back(dir);
+        }
+        self.current_play_list
+            .sort_by(|left, right| left.album.cmp(&right.album));
+        self.refresh_browse();
+        self.refresh_show_list();
+    }
 
-fn make_item() -> impl Widget<Song> {
-    Padding::new(
-        5.0,
-        Flex::row()
-            .with_child(
-                Label::dynamic(|d: &Song, _| {
-                    if d.playing {
-                        "|>".to_string()
-                    } else {
-                        "".to_owned()
-                    }
+    // 需要保存的状态
+    fn to_session(&self) -> session::Session {
+        let queue = self.queue.lock().unwrap();
+        session::Session {
+            volume: Some(self.volume),
+            play_mode: self.play_mode.to_owned(),
+            music_dirs: self.music_dirs.iter().cloned().collect(),
+            queue: queue.files(),
+            current: queue.current(),
+            position_ms: self.position.load(Ordering::Relaxed),
+            window_width: Some(self.window_size.width),
+            window_height: Some(self.window_size.height),
+            column_widths: self.column_widths.iter().cloned().collect(),
+            auto_resume: self.auto_resume,
+            device: Some(self.device.to_owned()),
+            bit_perfect: self.bit_perfect,
+            resample_quality: self.resample_quality,
+            stations: self.stations.iter().cloned().collect(),
+            playlists: self
+                .play_lists
+                .iter()
+                .map(|p| session::SavedPlaylist {
+                    name: p.name.to_owned(),
+                    files: p.songs.iter().map(|s| s.file.to_owned()).collect(),
+                    sync: p.sync,
                 })
-                .fix_width(80.0),
-            )
-            .with_spacer(50.0)
-            .with_child(Label::dynamic(|d: &Song, _| d.title.to_owned()).fix_width(120.0))
-            .with_spacer(100.0)
-            .with_child(Label::dynamic(|d: &Song, _| d.album.to_owned()).fix_width(120.0))
-            .with_spacer(100.0)
-            .with_child(Label::dynamic(|d: &Song, _| d.artist.to_owned()).fix_width(120.0))
-            .with_spacer(100.0)
-            .with_child(Label::dynamic(|d: &Song, _| d.duration.to_string()).fix_width(120.0))
-            .with_spacer(100.0)
-            .with_child(Label::dynamic(|d: &Song, _| d.date.to_owned()).fix_width(120.0))
-            .with_spacer(100.0),
+                .collect(),
+        }
+    }
+
+    // 播放线程使用的共享状态
+    fn player(&self) -> Player {
+        Player {
+            stream: Arc::clone(&self.stream),
+            output: Arc::clone(&self.output),
+            sink: Arc::clone(&self.sink),
+            queue: Arc::clone(&self.queue),
+            current_song: Arc::clone(&self.current_song),
+            status: Arc::clone(&self.app_status),
+            position: Arc::clone(&self.position),
+            play_mode: self.play_mode.to_owned(),
+            settings: Arc::clone(&self.playback_settings),
+            format_info: Arc::clone(&self.format_info),
+            events: (*self.events).clone(),
+            bookmarks: Arc::clone(&self.bookmarks),
+        }
+    }
+
+    // 同步播放队列到队列面板
+    fn sync_queue_view(&mut self) {
+        self.queue_view = self.queue.lock().unwrap().items();
+    }
+
+    fn to_podcast_store(&self) -> podcast::PodcastStore {
+        podcast::PodcastStore {
+            auto_delete: self.podcast_auto_delete,
+            podcasts: self.podcasts.iter().cloned().collect(),
+        }
+    }
+
+    fn episode_mut(&mut self, podcast: &str, id: &str) -> Option<&mut Episode> {
+        self.podcasts
+            .iter_mut()
+            .find(|p| p.url == podcast)?
+            .episodes
+            .iter_mut()
+            .find(|e| e.id == id)
+    }
+
+    // 记录正在播放的单集进度，播放到结尾时标记为已播放，并更新播放线程使用的继续播放位置
+    fn sync_podcasts(&mut self) {
+        let current = self.current_song.lock().unwrap().to_owned();
+        let position = self.position.load(Ordering::Relaxed);
+        let auto_delete = self.podcast_auto_delete;
+        let mut bookmarks = HashMap::new();
+        for podcast in self.podcasts.iter_mut() {
+            for episode in podcast.episodes.iter_mut() {
+                if episode.file.is_empty() {
+                    continue;
+                }
+                let playing = current.playing && episode.file == current.file;
+                if playing && !episode.played {
+                    episode.position_ms = position;
+                    let duration_ms = (episode.duration * 1000.0) as u64;
+                    if duration_ms > 0 && position + podcast::PLAYED_MARGIN_MS >= duration_ms {
+                        episode.played = true;
+                        episode.position_ms = 0;
+                    }
+                }
+                if auto_delete && episode.played && !playing {
+                    podcast::delete_download(episode);
+                    continue;
+                }
+                if !episode.played && episode.position_ms > 0 {
+                    bookmarks.insert(episode.file.to_owned(), episode.position_ms);
+                }
+            }
+        }
+        *self.bookmarks.lock().unwrap() = bookmarks;
+    }
+
+    // 按播放进度刷新歌词面板
+    fn refresh_lyrics(&mut self) {
+        let current = self.current_song.lock().unwrap().to_owned();
+        if current.file != self.lyrics_file {
+            self.lyrics = Arc::new(lyrics::load(&current.file, &current.lyrics));
+            self.lyrics_file = current.file;
+        }
+        let rows = self.lyrics.rows(self.position.load(Ordering::Relaxed));
+        if rows != self.lyrics_view {
+            self.lyrics_view = rows;
+        }
+    }
+
+    // 开始编辑当前歌曲的歌词，继续编辑同一首歌时保留未保存的修改
+    fn start_lyrics_edit(&mut self) {
+        let current = self.current_song.lock().unwrap().to_owned();
+        if current.file.is_empty() || radio::is_stream(&current.file) {
+            self.message = "请先播放要编辑歌词的歌曲".to_owned();
+            return;
+        }
+        self.lyrics_editing = true;
+        if current.file == self.editor_file && !self.editor_lines.is_empty() {
+            return;
+        }
+        self.refresh_lyrics();
+        self.editor_file = current.file;
+        self.editor_lines = lyrics::editor_lines_from_lyrics(&self.lyrics);
+        self.editor_text = self
+            .editor_lines
+            .iter()
+            .map(|l| l.text.to_owned())
+            .collect::<Vec<String>>()
+            .join("\n");
+        let first = self
+            .editor_lines
+            .iter()
+            .position(|l| l.time_ms.is_none())
+            .unwrap_or(0);
+        self.select_editor_line(first);
+    }
+
+    // 用输入框中的纯文本重新开始打点
+    fn load_editor_text(&mut self) {
+        self.editor_lines = lyrics::editor_lines_from_text(&self.editor_text);
+        self.select_editor_line(0);
+    }
+
+    fn select_editor_line(&mut self, index: usize) {
+        self.editor_cursor = index;
+        for line in self.editor_lines.iter_mut() {
+            line.next = line.index == index;
+        }
+    }
+
+    // 打点：当前播放位置作为下一行的时间
+    fn tap_sync(&mut self) {
+        if self.current_song.lock().unwrap().file != self.editor_file {
+            self.message = "正在播放的不是编辑中的歌曲".to_owned();
+            return;
+        }
+        let position = self.position.load(Ordering::Relaxed);
+        let cursor = self.editor_cursor;
+        match self.editor_lines.get_mut(cursor) {
+            Some(line) => line.time_ms = Some(position),
+            None => return,
+        }
+        self.select_editor_line(cursor + 1);
+    }
+
+    // 还没有时间的行，保存后会被忽略
+    fn untimed_warning(&self) -> String {
+        let untimed = self
+            .editor_lines
+            .iter()
+            .filter(|l| l.time_ms.is_none())
+            .count();
+        if untimed > 0 && untimed < self.editor_lines.len() {
+            format!("，有 {} 行还没有时间", untimed)
+        } else {
+            "".to_owned()
+        }
+    }
+
+    // 预览：歌词面板按编辑中的时间显示
+    fn preview_lyrics(&mut self) {
+        self.lyrics = Arc::new(lyrics::parse_lrc(&lyrics::to_lrc(&self.editor_lines)));
+        self.lyrics_file = self.editor_file.to_owned();
+        self.lyrics_editing = false;
+    }
+
+    fn save_lrc(&mut self) {
+        match lyrics::save_lrc(&self.editor_file, &lyrics::to_lrc(&self.editor_lines)) {
+            Ok(path) => {
+                self.message = format!("已保存 {}{}", path.display(), self.untimed_warning());
+                // 重新读取保存后的歌词
+                self.lyrics_file.clear();
+            }
+            Err(e) => self.message = format!("保存歌词失败: {}", e),
+        }
+    }
+
+    // 写入歌曲文件的 LYRICS 标签
+    fn embed_lyrics(&mut self) {
+        let events = match self.events.as_ref() {
+            Some(events) => events.to_owned(),
+            None => return,
+        };
+        tagging::write_tags_async(
+            vec![(
+                self.editor_file.to_owned(),
+                vec![("lyrics".to_owned(), lyrics::to_lrc(&self.editor_lines))],
+            )],
+            events,
+        );
+        self.message = format!("正在写入歌词{}", self.untimed_warning());
+    }
+
+    // 设置显示列表中歌曲的选中状态，file 为 None 时设置全部
+    fn set_selected(&mut self, file: Option<&str>, selected: bool) {
+        let files: HashSet<String> = self
+            .show_list
+            .iter()
+            .filter(|s| file.map_or(true, |f| s.file == f))
+            .map(|s| s.file.to_owned())
+            .collect();
+        for song in self
+            .current_play_list
+            .iter_mut()
+            .filter(|s| files.contains(&s.file))
+        {
+            song.selected = selected;
+        }
+        self.refresh_show_list();
+    }
+
+    fn clear_selection(&mut self) {
+        for song in self.current_play_list.iter_mut() {
+            song.selected = false;
+        }
+        self.refresh_show_list();
+    }
+
+    // 用选中的歌曲初始化标签编辑器，没有选中歌曲时返回 false
+    fn open_tag_editor(&mut self) -> bool {
+        let songs: Vec<&Song> = self
+            .current_play_list
+            .iter()
+            .filter(|s| s.selected && !radio::is_stream(&s.file))
+            .collect();
+        if songs.is_empty() {
+            self.message = "请先勾选要编辑的歌曲".to_owned();
+            return false;
+        }
+        self.tag_files = songs.iter().map(|s| s.file.to_owned()).collect();
+        self.tag_fields = TAG_FIELDS
+            .iter()
+            .map(|(key, _)| {
+                let values: Vec<&str> = songs.iter().map(|s| s.tag(key)).collect();
+                TagField::new(key, &values)
+            })
+            .collect();
+        true
+    }
+
+    // 后台写入修改过的字段
+    fn save_tags(&mut self) {
+        let tags: Vec<(String, String)> = self
+            .tag_fields
+            .iter()
+            .filter(|f| f.changed())
+            .map(|f| (f.key.to_owned(), f.value.trim().to_owned()))
+            .collect();
+        if tags.is_empty() {
+            self.message = "标签没有修改".to_owned();
+            return;
+        }
+        let events = match self.events.as_ref() {
+            Some(events) => events.to_owned(),
+            None => return,
+        };
+        let jobs = self
+            .tag_files
+            .iter()
+            .map(|f| (f.to_owned(), tags.to_owned()))
+            .collect();
+        tagging::write_tags_async(jobs, events);
+        self.message = format!("正在写入 {} 个文件的标签", self.tag_files.len());
+    }
+
+    // 预览选中歌曲按规则重命名的结果，没有选中歌曲时返回 false
+    fn preview_rename(&mut self) -> bool {
+        let songs: Vec<Song> = self
+            .current_play_list
+            .iter()
+            .filter(|s| s.selected && !radio::is_stream(&s.file))
+            .cloned()
+            .collect();
+        if songs.is_empty() {
+            self.message = "请先勾选要重命名的歌曲".to_owned();
+            return false;
+        }
+        self.rename_rows = organize::plan(&songs, self.rename_pattern.trim(), &self.music_dirs);
+        true
+    }
+
+    fn apply_rename(&mut self) {
+        if self.rename_rows.iter().any(|r| !r.conflict.is_empty()) {
+            self.message = "有冲突的文件，请修改规则后重新预览".to_owned();
+            return;
+        }
+        let moves: Vec<(String, String)> = self
+            .rename_rows
+            .iter()
+            .filter(|r| !r.unchanged())
+            .map(|r| (r.from.to_owned(), r.to.to_owned()))
+            .collect();
+        if moves.is_empty() {
+            self.message = "文件名没有变化".to_owned();
+            return;
+        }
+        let (done, errors) = organize::apply(&moves, &self.music_dirs);
+        self.update_paths(&done);
+        self.message = rename_message(done.len(), &errors);
+        self.last_renames = Arc::new(done);
+        self.rename_rows.clear();
+    }
+
+    // 撤销上一次重命名，按相反的顺序移回原位置
+    fn undo_rename(&mut self) {
+        let moves: Vec<(String, String)> = self
+            .last_renames
+            .iter()
+            .rev()
+            .map(|(from, to)| (to.to_owned(), from.to_owned()))
+            .collect();
+        if moves.is_empty() {
+            self.message = "没有可以撤销的重命名".to_owned();
+            return;
+        }
+        let (done, errors) = organize::apply(&moves, &self.music_dirs);
+        self.update_paths(&done);
+        self.message = rename_message(done.len(), &errors);
+        // 没有移回去的文件保留，可以再次撤销
+        let remaining: Vec<(String, String)> = self
+            .last_renames
+            .iter()
+            .filter(|(_, to)| !done.iter().any(|(from, _)| from == to))
+            .cloned()
+            .collect();
+        self.last_renames = Arc::new(remaining);
+    }
+
+    // 文件移动后更新资料库，播放队列和当前歌曲中的路径
+    fn update_paths(&mut self, moves: &[(String, String)]) {
+        {
+            let mut queue = self.queue.lock().unwrap();
+            let mut current = self.current_song.lock().unwrap();
+            for (from, to) in moves {
+                for song in self
+                    .current_play_list
+                    .iter_mut()
+                    .filter(|s| s.file == *from)
+                {
+                    song.file = to.to_owned();
+                }
+                queue.rename_file(from, to);
+                if current.file == *from {
+                    current.file = to.to_owned();
+                }
+                if self.editor_file == *from {
+                    self.editor_file = to.to_owned();
+                }
+            }
+        }
+        self.lyrics_file.clear();
+        self.refresh_browse();
+        self.refresh_show_list();
+        self.sync_queue_view();
+    }
+
+    // 预览选中歌曲按规则推断的标签，没有选中歌曲时返回 false
+    fn preview_infer(&mut self) -> bool {
+        let pattern = self.infer_pattern.trim().to_owned();
+        if let Err(e) = infer::validate(&pattern) {
+            self.message = e;
+            return false;
+        }
+        self.infer_rows = self
+            .current_play_list
+            .iter()
+            .filter(|s| s.selected && !radio::is_stream(&s.file))
+            .map(|s| InferRow {
+                file: s.file.to_owned(),
+                fields: infer::infer(&pattern, &s.file)
+                    .map_or("".to_owned(), |f| infer::describe(&f)),
+            })
+            .collect();
+        if self.infer_rows.is_empty() {
+            self.message = "请先勾选要推断标签的歌曲".to_owned();
+            return false;
+        }
+        true
+    }
+
+    // 用推断的值填充选中歌曲缺少的字段，write 为 true 时同时写入文件
+    fn apply_infer(&mut self, write: bool) {
+        let pattern = self.infer_pattern.trim().to_owned();
+        if let Err(e) = infer::validate(&pattern) {
+            self.message = e;
+            return;
+        }
+        let mut filled = 0;
+        let mut jobs = vec![];
+        for song in self
+            .current_play_list
+            .iter_mut()
+            .filter(|s| s.selected && !radio::is_stream(&s.file))
+        {
+            let fields = match infer::infer(&pattern, &song.file) {
+                Some(f) => f,
+                None => continue,
+            };
+            if !infer::fill_missing(song, &pattern).is_empty() {
+                filled += 1;
+            }
+            if write {
+                // 已有标签的字段写回原来的值
+                let tags = fields
+                    .iter()
+                    .map(|(name, _)| {
+                        let key = infer::tag_key(name);
+                        (key.to_owned(), song.tag(key).to_owned())
+                    })
+                    .collect();
+                jobs.push((song.file.to_owned(), tags));
+            }
+        }
+        self.refresh_browse();
+        self.refresh_show_list();
+        self.message = format!("已推断 {} 首歌曲的标签", filled);
+        if jobs.is_empty() {
+            return;
+        }
+        if let Some(events) = self.events.as_ref() {
+            self.message = format!("正在写入 {} 个文件的标签", jobs.len());
+            tagging::write_tags_async(jobs, events.to_owned());
+        }
+    }
+
+    // 把选中歌曲中还原过编码的标签按 UTF-8 重新写入文件
+    fn rewrite_utf8(&mut self) {
+        let jobs: Vec<(String, Vec<(String, String)>)> = self
+            .current_play_list
+            .iter()
+            .filter(|s| s.selected && !s.encoding.is_empty())
+            .map(|s| {
+                let tags = ["title", "artist", "album", "album_artist", "genre"]
+                    .iter()
+                    .filter(|key| !s.tag(key).is_empty())
+                    .map(|key| (key.to_string(), s.tag(key).to_owned()))
+                    .collect();
+                (s.file.to_owned(), tags)
+            })
+            .collect();
+        if jobs.is_empty() {
+            self.message = "选中的歌曲中没有需要转换编码的标签".to_owned();
+            return;
+        }
+        if let Some(events) = self.events.as_ref() {
+            self.message = format!("正在把 {} 个文件的标签转换为 UTF-8", jobs.len());
+            tagging::write_tags_async(jobs, events.to_owned());
+        }
+    }
+
+    // 把选中的歌曲(没有选中时为当前列表)保存为播放列表，同名的播放列表被替换
+    fn save_playlist(&mut self) {
+        let name = self.new_playlist_name.trim().to_owned();
+        if name.is_empty() {
+            self.sync_progress = "请先填写播放列表名称".to_owned();
+            return;
+        }
+        let mut songs: Vector<Song> = self
+            .current_play_list
+            .iter()
+            .filter(|s| s.selected && !radio::is_stream(&s.file))
+            .cloned()
+            .collect();
+        if songs.is_empty() {
+            songs = self
+                .show_list
+                .iter()
+                .filter(|s| !radio::is_stream(&s.file))
+                .cloned()
+                .collect();
+        }
+        self.sync_progress = format!("播放列表 {} 共 {} 首", name, songs.len());
+        let playlist = PlayList {
+            name: name.to_owned(),
+            songs,
+            sync: true,
+        };
+        match self.play_lists.iter().position(|p| p.name == name) {
+            Some(index) => self.play_lists[index] = playlist,
+            None => self.play_lists.push_back(playlist),
+        }
+        self.new_playlist_name.clear();
+    }
+
+    // 把勾选的播放列表同步到设备目录
+    fn start_sync(&mut self) {
+        let dest = self.sync_dir.trim().to_owned();
+        if dest.is_empty() {
+            self.sync_progress = "请先填写同步目录".to_owned();
+            return;
+        }
+        let playlists: Vec<SyncPlaylist> = self
+            .play_lists
+            .iter()
+            .filter(|p| p.sync)
+            .map(|p| SyncPlaylist {
+                name: p.name.to_owned(),
+                songs: p.songs.to_owned(),
+            })
+            .collect();
+        if playlists.is_empty() {
+            self.sync_progress = "请先勾选要同步的播放列表".to_owned();
+            return;
+        }
+        let events = match self.events.as_ref() {
+            Some(events) => events.to_owned(),
+            None => return,
+        };
+        let settings = ExportSettings {
+            format: self.export_format,
+            sample_rate: self.export_rate,
+            bit_depth: self.export_bits,
+        };
+        self.sync_cancel = Arc::new(AtomicBool::new(false));
+        self.syncing = true;
+        self.sync_progress = "正在同步".to_owned();
+        sync::sync_async(
+            playlists,
+            dest,
+            self.music_dirs.to_owned(),
+            self.sync_transcode,
+            settings,
+            Arc::clone(&self.sync_cancel),
+            events,
+        );
+    }
+
+    // 导出选中的歌曲，没有选中时导出当前列表中的歌曲
+    fn start_export(&mut self) {
+        let dest = self.export_dir.trim().to_owned();
+        if dest.is_empty() {
+            self.export_progress = "请先填写导出目录".to_owned();
+            return;
+        }
+        let events = match self.events.as_ref() {
+            Some(events) => events.to_owned(),
+            None => return,
+        };
+        let selected: Vec<&Song> = self
+            .current_play_list
+            .iter()
+            .filter(|s| s.selected)
+            .collect();
+        let songs = if selected.is_empty() {
+            self.show_list.iter().collect()
+        } else {
+            selected
+        };
+        let jobs: Vec<(String, PathBuf)> = songs
+            .iter()
+            .filter(|s| !radio::is_stream(&s.file) && s.broken.is_empty())
+            .map(|s| {
+                let relative = organize::relative_path(&s.file, &self.music_dirs);
+                let target =
+                    transcode::target_path(&relative, Path::new(&dest), self.export_format);
+                (s.file.to_owned(), target)
+            })
+            .collect();
+        // 工作线程从末尾取任务，倒序后按列表顺序转换
+        let jobs: Vec<(String, PathBuf)> =
+            transcode::unique_targets(jobs).into_iter().rev().collect();
+        if jobs.is_empty() {
+            self.export_progress = "没有可以导出的歌曲".to_owned();
+            return;
+        }
+        let settings = ExportSettings {
+            format: self.export_format,
+            sample_rate: self.export_rate,
+            bit_depth: self.export_bits,
+        };
+        self.export_cancel = Arc::new(AtomicBool::new(false));
+        self.exporting = true;
+        self.export_progress = format!("0/{}", jobs.len());
+        transcode::export_async(
+            jobs,
+            settings,
+            transcode::worker_count(self.config.export.workers),
+            Arc::clone(&self.export_cancel),
+            events,
+        );
+    }
+
+    // 把选中的歌曲作为一张专辑，在本地数据包中查找候选发行，没有选中歌曲时返回 false
+    fn start_match(&mut self) -> bool {
+        let dump = self.config.library.musicbrainz_dump.trim().to_owned();
+        if dump.is_empty() {
+            self.message = "请先在配置文件中设置 library.musicbrainz_dump".to_owned();
+            return false;
+        }
+        let songs: Vec<Song> = self
+            .current_play_list
+            .iter()
+            .filter(|s| s.selected && !radio::is_stream(&s.file))
+            .cloned()
+            .collect();
+        if songs.is_empty() {
+            self.message = "请先勾选同一张专辑的所有歌曲".to_owned();
+            return false;
+        }
+        let events = match self.events.as_ref() {
+            Some(events) => events.to_owned(),
+            None => return false,
+        };
+        self.matching = true;
+        self.match_candidates.clear();
+        self.match_status = format!("正在匹配 {} 首歌曲", songs.len());
+        musicbrainz::search_async(dump, songs, events);
+        true
+    }
+
+    // 写入发行的标签，有封面时同时保存到专辑目录并替换内嵌封面
+    fn apply_release(&mut self, release: &ReleaseCandidate) {
+        let events = match self.events.as_ref() {
+            Some(events) => events.to_owned(),
+            None => return,
+        };
+        let cover = if release.cover.is_empty() {
+            None
+        } else {
+            Some(PathBuf::from(&release.cover))
+        };
+        if let Some(cover) = cover.as_ref() {
+            let ext = cover
+                .extension()
+                .map_or("jpg".to_owned(), |e| e.to_string_lossy().to_string());
+            let dirs: HashSet<PathBuf> = release
+                .tags
+                .iter()
+                .filter_map(|(file, _)| Path::new(file).parent().map(Path::to_path_buf))
+                .collect();
+            for dir in dirs {
+                let target = dir.join(format!("cover.{}", ext));
+                if let Err(e) = fs::copy(cover, &target) {
+                    println!("保存封面失败 {}: {}", target.display(), e);
+                }
+            }
+            self.refresh_browse();
+        }
+        self.match_status = format!("正在写入 {} 个文件", release.tags.len());
+        tagging::write_album_async(release.tags.to_vec(), cover, events);
+    }
+
+    // 在后台计算资料库中所有歌曲的声纹并查找重复
+    fn start_duplicate_scan(&mut self) {
+        let events = match self.events.as_ref() {
+            Some(events) => events.to_owned(),
+            None => return,
+        };
+        let songs: Vec<Song> = self
+            .current_play_list
+            .iter()
+            .filter(|s| !radio::is_stream(&s.file) && s.broken.is_empty())
+            .cloned()
+            .collect();
+        self.duplicate_cancel = Arc::new(AtomicBool::new(false));
+        self.duplicate_scanning = true;
+        self.duplicate_progress = format!("0/{}", songs.len());
+        self.duplicates.clear();
+        fingerprint::scan_async(songs, Arc::clone(&self.duplicate_cancel), events);
+    }
+
+    // 在后台解码资料库中的所有文件，检查 MD5、解码错误和截断
+    fn start_integrity_check(&mut self) {
+        let events = match self.events.as_ref() {
+            Some(events) => events.to_owned(),
+            None => return,
+        };
+        let mut files: Vec<String> = self
+            .current_play_list
+            .iter()
+            .filter(|s| !radio::is_stream(&s.file))
+            .map(|s| s.file.to_owned())
+            .collect();
+        files.sort();
+        files.dedup();
+        self.integrity_cancel = Arc::new(AtomicBool::new(false));
+        self.integrity_checking = true;
+        self.integrity_progress = format!("0/{}", files.len());
+        integrity::check_async(files, Arc::clone(&self.integrity_cancel), events);
+    }
+
+    // 更新歌曲的校验结果，problem 返回 None 的歌曲保持不变
+    fn apply_integrity(&mut self, problem: impl Fn(&String) -> Option<String>) {
+        for song in self.current_play_list.iter_mut() {
+            if let Some(p) = problem(&song.file) {
+                song.damaged = p;
+            }
+        }
+        self.integrity_report = self
+            .current_play_list
+            .iter()
+            .filter(|s| !s.damaged.is_empty())
+            .cloned()
+            .collect();
+        self.refresh_show_list();
+    }
+
+    // 保留勾选的版本，其余的移出资料库
+    fn keep_duplicates(&mut self, group: &DuplicateGroup) {
+        if !group.items.iter().any(|i| i.keep) {
+            self.message = "请至少勾选一个要保留的版本".to_owned();
+            return;
+        }
+        let mut errors = vec![];
+        let mut moved = vec![];
+        for item in group.items.iter().filter(|i| !i.keep) {
+            match fingerprint::set_aside(&item.file) {
+                Ok(target) => moved.push((item.file.to_owned(), target)),
+                Err(e) => errors.push(format!("{}: {}", item.file, e)),
+            }
+        }
+        self.current_play_list
+            .retain(|s| !moved.iter().any(|(file, _)| *file == s.file));
+        {
+            let mut queue = self.queue.lock().unwrap();
+            let files = queue.files();
+            for (index, file) in files.iter().enumerate().rev() {
+                if moved.iter().any(|(f, _)| f == file) {
+                    queue.remove(index);
+                }
+            }
+        }
+        self.sync_queue_view();
+        self.duplicates.retain(|g| g != group);
+        self.refresh_browse();
+        self.refresh_show_list();
+        self.message = match (errors.first(), moved.first()) {
+            (Some(e), _) => format!("移动失败 {}", e),
+            (None, Some((_, target))) => format!(
+                "已把 {} 个文件移到 {}",
+                moved.len(),
+                target.parent().unwrap_or(target).display()
+            ),
+            (None, None) => "".to_owned(),
+        };
+    }
+
+    // 写入标签后重新读取歌曲信息，保留选中和无法播放的状态
+    fn reload_song(&mut self, file: &str) {
+        let fresh = read_song(file, &self.config);
+        for song in self.current_play_list.iter_mut().filter(|s| s.file == file) {
+            *song = Song {
+                playing: song.playing,
+                broken: song.broken.to_owned(),
+                damaged: song.damaged.to_owned(),
+                selected: song.selected,
+                ..fresh.to_owned()
+            };
+        }
+        {
+            let mut current = self.current_song.lock().unwrap();
+            if current.file == file {
+                *current = Song {
+                    playing: current.playing,
+                    ..fresh
+                };
+            }
+        }
+        // 重新读取内嵌的歌词
+        self.lyrics_file.clear();
+        self.refresh_browse();
+        self.refresh_show_list();
+    }
+
+    // 添加输入框中的电台，名称为空时使用地址
+    fn add_station(&mut self) {
+        let url = self.station_url.trim().to_owned();
+        if !radio::is_stream(&url) {
+            self.message = "电台地址必须以 http:// 或 https:// 开头".to_owned();
+            return;
+        }
+        if self.stations.iter().any(|s| s.url == url) {
+            self.message = "电台已存在".to_owned();
+            return;
+        }
+        let name = match self.station_name.trim() {
+            "" => url.to_owned(),
+            name => name.to_owned(),
+        };
+        self.stations.push_back(Station { name, url });
+        self.station_name.clear();
+        self.station_url.clear();
+    }
+
+    fn set_browse_mode(&mut self, mode: BrowseMode) {
+        self.browse_mode = mode;
+        self.browse_path.clear();
+        self.refresh_browse();
+        self.refresh_show_list();
+    }
+}
+
+// 拖动表头分隔线调整列宽，宽度保存在会话中
+struct ColumnResizeController {
+    index: usize,
+    // 开始拖动时的 (鼠标位置, 列宽)
+    drag: Option<(f64, f64)>,
+}
+
+impl<W: Widget<AppState>> Controller<AppState, W> for ColumnResizeController {
+    fn event(
+        &mut self,
+        child: &mut W,
+        ctx: &mut EventCtx,
+        event: &Event,
+        data: &mut AppState,
+        env: &Env,
+    ) {
+        match event {
+            Event::MouseDown(mouse) => {
+                if let Some(width) = data.column_widths.get(self.index) {
+                    self.drag = Some((mouse.window_pos.x, *width));
+                    ctx.set_active(true);
+                    ctx.set_handled();
+                }
+            }
+            Event::MouseMove(mouse) => {
+                if ctx.is_hot() || ctx.is_active() {
+                    ctx.set_cursor(&Cursor::ResizeLeftRight);
+                }
+                if let (true, Some((start, width))) = (ctx.is_active(), self.drag) {
+                    let width = (width + mouse.window_pos.x - start).max(MIN_COLUMN_WIDTH);
+                    data.column_widths[self.index] = width.round();
+                    // 列表中每一行都要按新的宽度重新布局
+                    ctx.request_layout();
+                }
+            }
+            Event::MouseUp(_) if ctx.is_active() => {
+                self.drag = None;
+                ctx.set_active(false);
+                ctx.set_handled();
+            }
+            _ => {}
+        }
+        child.event(ctx, event, data, env)
+    }
+}
+
+// 定时保存会话，防止异常退出丢失状态
+struct SessionController {
+    timer: TimerToken,
+}
+
+impl<W: Widget<AppState>> Controller<AppState, W> for SessionController {
+    fn event(
+        &mut self,
+        child: &mut W,
+        ctx: &mut EventCtx,
+        event: &Event,
+        data: &mut AppState,
+        env: &Env,
+    ) {
+        match event {
+            Event::WindowConnected => {
+                self.timer = ctx.request_timer(SESSION_SAVE_INTERVAL);
+            }
+            Event::Timer(token) if *token == self.timer => {
+                session::save(&data.to_session());
+                podcast::save(&data.to_podcast_store());
+                self.timer = ctx.request_timer(SESSION_SAVE_INTERVAL);
+            }
+            _ => {}
+        }
+        child.event(ctx, event, data, env)
+    }
+}
+
+// 定时同步播客播放进度
+struct PodcastController {
+    timer: TimerToken,
+}
+
+impl<W: Widget<AppState>> Controller<AppState, W> for PodcastController {
+    fn event(
+        &mut self,
+        child: &mut W,
+        ctx: &mut EventCtx,
+        event: &Event,
+        data: &mut AppState,
+        env: &Env,
+    ) {
+        match event {
+            Event::WindowConnected => {
+                self.timer = ctx.request_timer(PODCAST_SYNC_INTERVAL);
+            }
+            Event::Timer(token) if *token == self.timer => {
+                data.sync_podcasts();
+                self.timer = ctx.request_timer(PODCAST_SYNC_INTERVAL);
+            }
+            _ => {}
+        }
+        child.event(ctx, event, data, env)
+    }
+}
+
+// 定时按播放进度刷新歌词
+struct LyricsController {
+    timer: TimerToken,
+}
+
+impl<W: Widget<AppState>> Controller<AppState, W> for LyricsController {
+    fn event(
+        &mut self,
+        child: &mut W,
+        ctx: &mut EventCtx,
+        event: &Event,
+        data: &mut AppState,
+        env: &Env,
+    ) {
+        match event {
+            Event::WindowConnected => {
+                self.timer = ctx.request_timer(LYRICS_REFRESH_INTERVAL);
+            }
+            Event::Timer(token) if *token == self.timer => {
+                data.refresh_lyrics();
+                self.timer = ctx.request_timer(LYRICS_REFRESH_INTERVAL);
+            }
+            _ => {}
+        }
+        child.event(ctx, event, data, env)
+    }
+}
+
+// 全局快捷键，输入框已经处理过的按键不再处理
+struct KeyController;
+
+impl<W: Widget<AppState>> Controller<AppState, W> for KeyController {
+    fn event(
+        &mut self,
+        child: &mut W,
+        ctx: &mut EventCtx,
+        event: &Event,
+        data: &mut AppState,
+        env: &Env,
+    ) {
+        if let Event::WindowConnected = event {
+            ctx.request_focus();
+        }
+        child.event(ctx, event, data, env);
+        if ctx.is_handled() {
+            return;
+        }
+        if let Event::KeyDown(key) = event {
+            let action = match data.config.keys.action_for(key) {
+                Some(action) => action,
+                None => return,
+            };
+            match action {
+                Action::PlayPause => {
+                    if *data.app_status.lock().unwrap() == Status::Play {
+                        data.sink.lock().unwrap().pause();
+                        *data.app_status.lock().unwrap() = Status::Suspend;
+                    } else {
+                        play(data);
+                    }
+                }
+                Action::Stop => {
+                    data.sink.lock().unwrap().stop();
+                    *data.app_status.lock().unwrap() = Status::Stop;
+                }
+                Action::Next => *data.app_status.lock().unwrap() = Status::Next,
+                Action::Prev => *data.app_status.lock().unwrap() = Status::Prev,
+                // bit-perfect 模式下不做软件音量调节
+                Action::VolumeUp | Action::VolumeDown if !data.bit_perfect => {
+                    let step = if action == Action::VolumeUp {
+                        0.05
+                    } else {
+                        -0.05
+                    };
+                    data.volume = (data.volume + step).max(0.0).min(1.0);
+                    data.sink.lock().unwrap().set_volume(data.volume as f32);
+                }
+                Action::VolumeUp | Action::VolumeDown => {}
+                Action::TapSync => {
+                    if !data.lyrics_editing {
+                        return;
+                    }
+                    data.tap_sync();
+                }
+            }
+            ctx.set_handled();
+        }
+    }
+}
+
+// 修改播放设置，下一首歌曲生效。bit-perfect 模式开启时音量固定为 1.0
+struct PlaybackSettingsController;
+
+impl<W: Widget<AppState>> Controller<AppState, W> for PlaybackSettingsController {
+    fn event(
+        &mut self,
+        child: &mut W,
+        ctx: &mut EventCtx,
+        event: &Event,
+        data: &mut AppState,
+        env: &Env,
+    ) {
+        let old = (data.bit_perfect, data.resample_quality);
+        child.event(ctx, event, data, env);
+        if old != (data.bit_perfect, data.resample_quality) {
+            *data.playback_settings.lock().unwrap() = PlaybackSettings {
+                bit_perfect: data.bit_perfect,
+                resample: data.resample_quality,
+            };
+            let volume = if data.bit_perfect {
+                1.0
+            } else {
+                data.volume as f32
+            };
+            data.sink.lock().unwrap().set_volume(volume);
+        }
+    }
+}
+
+// 搜索框内容或过滤条件变化时刷新显示列表
+struct SearchController;
+
+impl<W: Widget<AppState>> Controller<AppState, W> for SearchController {
+    fn event(
+        &mut self,
+        child: &mut W,
+        ctx: &mut EventCtx,
+        event: &Event,
+        data: &mut AppState,
+        env: &Env,
+    ) {
+        let old_text = data.search_text.clone();
+        let old_broken = data.show_broken;
+        let old_damaged = data.show_damaged;
+        child.event(ctx, event, data, env);
+        if old_text != data.search_text
+            || old_broken != data.show_broken
+            || old_damaged != data.show_damaged
+        {
+            data.refresh_show_list();
+        }
+    }
+}
+
+#[derive(Clone, Data, PartialEq, Debug)]
+enum Status {
+    Play,
+    Suspend,
+    Stop,
+    Next,
+    Prev,
+}
+
+#[derive(Data, Lens, Default, Clone)]
+struct PlayList {
+    name: String,
+    songs: Vector<Song>,
+    // 勾选后同步到设备
+    sync: bool,
+}
+
+#[derive(Data, Lens, Default, Clone)]
+struct Current {
+    name: String,
+    cover_image: String,
+}
+
+#[derive(Clone, Data, PartialEq, Debug, Serialize, Deserialize)]
+enum Modes {
+    Order,
+    Random,
+    Repet,
+}
+
+#[derive(Data, Lens, Default, Clone, Debug)]
+struct Song {
+    title: String,
+    artist: String,
+    album: String,
+    duration: f64,
+    playing: bool,
+    date: String,
+    genre: String,
+    file: String,
+    // 无法播放的原因，为空表示正常
+    broken: String,
+    // 完整性校验发现的问题，为空表示正常或还没有校验
+    damaged: String,
+    // 内嵌的歌词标签
+    lyrics: String,
+    album_artist: String,
+    track: String,
+    disc: String,
+    // 在列表中勾选，用于编辑标签等批量操作
+    selected: bool,
+    // 没有标题标签，标题取自文件名
+    title_from_file: bool,
+    // 乱码标签还原时使用的编码，为空表示不需要还原
+    encoding: String,
+}
+
+// 标签编辑器中的字段，(ffmpeg 标签名, 显示名称)
+const TAG_FIELDS: [(&str, &str); 8] = [
+    ("title", "Title"),
+    ("artist", "Artist"),
+    ("album", "Album"),
+    ("album_artist", "Album artist"),
+    ("date", "Date"),
+    ("genre", "Genre"),
+    ("track", "Track"),
+    ("disc", "Disc"),
+];
+
+impl Song {
+    // TAG_FIELDS 中标签对应的值
+    fn tag(&self, key: &str) -> &str {
+        match key {
+            "title" => &self.title,
+            "artist" => &self.artist,
+            "album" => &self.album,
+            "album_artist" => &self.album_artist,
+            "date" => &self.date,
+            "genre" => &self.genre,
+            "track" => &self.track,
+            "disc" => &self.disc,
+            _ => "",
+        }
+    }
+}
+
+// 勾选歌曲时同步到 current_play_list
+struct SelectController;
+
+impl<W: Widget<Song>> Controller<Song, W> for SelectController {
+    fn event(
+        &mut self,
+        child: &mut W,
+        ctx: &mut EventCtx,
+        event: &Event,
+        data: &mut Song,
+        env: &Env,
+    ) {
+        let old = data.selected;
+        child.event(ctx, event, data, env);
+        if data.selected != old {
+            ctx.submit_command(SELECT_SONG.with((data.file.to_owned(), data.selected)));
+        }
+    }
+}
+
+fn make_item() -> impl Widget<Song> {
+    Padding::new(
+        5.0,
+        Flex::row()
+            .with_child(Checkbox::new("").lens(Song::selected).fix_width(30.0))
+            .with_child(
+                Label::dynamic(|d: &Song, _| {
+                    if d.playing {
+                        "|>".to_string()
+                    } else if !d.broken.is_empty() {
+                        "x".to_owned()
+                    } else {
+                        "".to_owned()
+                    }
+                })
+                .fix_width(80.0),
+            )
+            .with_spacer(50.0)
+            .with_child(
+                Label::dynamic(|d: &Song, _| d.title.to_owned())
+                    .fix_width(COLUMN_WIDTHS[0].to_owned()),
+            )
+            .with_spacer(100.0)
+            .with_child(
+                Label::dynamic(|d: &Song, _| d.album.to_owned())
+                    .fix_width(COLUMN_WIDTHS[1].to_owned()),
+            )
+            .with_spacer(100.0)
+            .with_child(
+                Label::dynamic(|d: &Song, _| d.artist.to_owned())
+                    .fix_width(COLUMN_WIDTHS[2].to_owned()),
+            )
+            .with_spacer(100.0)
+            .with_child(
+                Label::dynamic(|d: &Song, _| d.duration.to_string())
+                    .fix_width(COLUMN_WIDTHS[3].to_owned()),
+            )
+            .with_spacer(100.0)
+            .with_child(
+                Label::dynamic(|d: &Song, _| d.date.to_owned())
+                    .fix_width(COLUMN_WIDTHS[4].to_owned()),
+            )
+            .with_spacer(100.0)
+            // 无法播放的原因
+            .with_child(
+                Label::dynamic(|d: &Song, _| {
+                    if d.broken.is_empty() {
+                        d.damaged.to_owned()
+                    } else {
+                        d.broken.to_owned()
+                    }
+                })
+                .with_text_color(Color::rgb8(0xcc, 0x33, 0x33))
+                .with_text_size(12.0),
+            )
+            .with_default_spacer()
+            .with_child(Button::new("+").on_click(|ctx, d: &mut Song, _env| {
+                ctx.submit_command(QUEUE_APPEND.with(d.to_owned()));
+            }))
+            .with_default_spacer()
+            .with_child(Button::new(LocalizedString::new("Play Next")).on_click(
+                |ctx, d: &mut Song, _env| {
+                    ctx.submit_command(QUEUE_PLAY_NEXT.with(d.to_owned()));
+                },
+            ))
+            .with_default_spacer()
+            .with_child(Button::new(LocalizedString::new("Properties")).on_click(
+                |ctx, d: &mut Song, _env| {
+                    ctx.submit_command(INSPECT_SONG.with(d.file.to_owned()));
+                    ctx.new_window(
+                        WindowDesc::new(inspector_window)
+                            .title(LocalizedString::new("Properties"))
+                            .window_size((720.0, 640.0)),
+                    );
+                },
+            )),
+    )
+    .controller(SelectController)
+}
+
+// 修改混合值字段时取消"保留原值"
+struct TagFieldController;
+
+impl<W: Widget<TagField>> Controller<TagField, W> for TagFieldController {
+    fn event(
+        &mut self,
+        child: &mut W,
+        ctx: &mut EventCtx,
+        event: &Event,
+        data: &mut TagField,
+        env: &Env,
+    ) {
+        let old = data.value.to_owned();
+        child.event(ctx, event, data, env);
+        if data.mixed && data.value != old {
+            data.keep = false;
+        }
+    }
+}
+
+fn close_window(ctx: &mut EventCtx) {
+    ctx.submit_command(commands::CLOSE_WINDOW.to(ctx.window_id()));
+}
+
+// 标签编辑窗口，多首歌曲的值不同时默认保留各自原有的值
+fn tag_editor() -> impl Widget<AppState> {
+    let mut fields = Flex::column().cross_axis_alignment(CrossAxisAlignment::Start);
+    for (i, (_, label)) in TAG_FIELDS.iter().enumerate() {
+        fields.add_child(
+            Flex::row()
+                .with_child(Label::new(LocalizedString::new(*label)).fix_width(90.0))
+                .with_flex_child(
+                    TextBox::new()
+                        .lens(TagField::value)
+                        .expand_width()
+                        .controller(TagFieldController),
+                    1.0,
+                )
+                .with_default_spacer()
+                .with_child(
+                    Checkbox::new(LocalizedString::new("Keep existing"))
+                        .lens(TagField::keep)
+                        .disabled_if(|d: &TagField, _| !d.mixed),
+                )
+                .lens(AppState::tag_fields.index(i))
+                .padding(2.0),
+        );
+    }
+    Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(Label::dynamic(|d: &AppState, _| {
+            if d.tag_files.len() == 1 {
+                d.tag_files[0].to_owned()
+            } else {
+                format!("{} 首歌曲", d.tag_files.len())
+            }
+        }))
+        .with_default_spacer()
+        .with_child(fields)
+        .with_default_spacer()
+        .with_child(
+            Flex::row()
+                .with_child(Button::new(LocalizedString::new("Save")).on_click(
+                    |ctx, data: &mut AppState, _env| {
+                        data.save_tags();
+                        close_window(ctx);
+                    },
+                ))
+                .with_child(
+                    Button::new(LocalizedString::new("Cancel"))
+                        .on_click(|ctx, _data: &mut AppState, _env| close_window(ctx)),
+                ),
+        )
+        .padding(10.0)
+}
+
+// 重命名预览中的一行，有冲突时显示原因
+fn make_rename_row() -> impl Widget<RenameRow> {
+    Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(Label::dynamic(|d: &RenameRow, _| d.from.to_owned()).with_text_size(12.0))
+        .with_child(Label::dynamic(|d: &RenameRow, _| {
+            if d.unchanged() {
+                "  (不变)".to_owned()
+            } else {
+                format!("→ {}", d.to)
+            }
+        }))
+        .with_child(
+            Label::dynamic(|d: &RenameRow, _| d.conflict.to_owned())
+                .with_text_color(Color::rgb8(0xcc, 0x33, 0x33))
+                .with_text_size(12.0),
+        )
+        .padding(3.0)
+}
+
+// 按规则重命名窗口：修改规则，预览，执行和撤销
+fn rename_window() -> impl Widget<AppState> {
+    Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(
+            Flex::row()
+                .with_child(Label::new(LocalizedString::new("Pattern")))
+                .with_default_spacer()
+                .with_flex_child(
+                    TextBox::new()
+                        .lens(AppState::rename_pattern)
+                        .expand_width(),
+                    1.0,
+                )
+                .with_child(Button::new(LocalizedString::new("Preview")).on_click(
+                    |_ctx, data: &mut AppState, _env| {
+                        data.preview_rename();
+                    },
+                )),
+        )
+        .with_child(
+            Label::new("{title} {artist} {album} {albumartist} {year} {date} {genre} {track:02} {disc} {ext}")
+                .with_text_size(12.0),
+        )
+        .with_default_spacer()
+        .with_flex_child(
+            Scroll::new(List::new(make_rename_row).lens(AppState::rename_rows)).vertical(),
+            1.0,
+        )
+        .with_default_spacer()
+        .with_child(
+            Flex::row()
+                .with_child(
+                    Button::new(LocalizedString::new("Apply"))
+                        .on_click(|_ctx, data: &mut AppState, _env| {
+                            data.apply_rename();
+                        })
+                        .disabled_if(|d: &AppState, _| {
+                            d.rename_rows.is_empty()
+                                || d.rename_rows.iter().any(|r| !r.conflict.is_empty())
+                        }),
+                )
+                .with_child(
+                    Button::new(LocalizedString::new("Undo"))
+                        .on_click(|_ctx, data: &mut AppState, _env| {
+                            data.undo_rename();
+                        })
+                        .disabled_if(|d: &AppState, _| d.last_renames.is_empty()),
+                )
+                .with_child(
+                    Button::new(LocalizedString::new("Close"))
+                        .on_click(|ctx, _data: &mut AppState, _env| close_window(ctx)),
+                )
+                .with_default_spacer()
+                .with_flex_child(
+                    Label::dynamic(|d: &AppState, _| d.message.to_owned())
+                        .with_text_size(12.0)
+                        .expand_width(),
+                    1.0,
+                ),
+        )
+        .padding(10.0)
+}
+
+// 推断预览中的一行，不符合规则时显示提示
+fn make_infer_row() -> impl Widget<InferRow> {
+    Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(Label::dynamic(|d: &InferRow, _| d.file.to_owned()).with_text_size(12.0))
+        .with_child(Label::dynamic(|d: &InferRow, _| {
+            if d.fields.is_empty() {
+                "  (不符合规则)".to_owned()
+            } else {
+                format!("  {}", d.fields)
+            }
+        }))
+        .padding(3.0)
+}
+
+// 按文件名推断标签窗口
+fn infer_window() -> impl Widget<AppState> {
+    Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(
+            Flex::row()
+                .with_child(Label::new(LocalizedString::new("Pattern")))
+                .with_default_spacer()
+                .with_flex_child(
+                    TextBox::new().lens(AppState::infer_pattern).expand_width(),
+                    1.0,
+                )
+                .with_child(Button::new(LocalizedString::new("Preview")).on_click(
+                    |_ctx, data: &mut AppState, _env| {
+                        data.preview_infer();
+                    },
+                )),
+        )
+        .with_child(
+            Label::new("%title% %artist% %album% %albumartist% %year% %genre% %track% %disc%")
+                .with_text_size(12.0),
+        )
+        .with_default_spacer()
+        .with_flex_child(
+            Scroll::new(List::new(make_infer_row).lens(AppState::infer_rows)).vertical(),
+            1.0,
+        )
+        .with_default_spacer()
+        .with_child(
+            Flex::row()
+                .with_child(Button::new(LocalizedString::new("Apply")).on_click(
+                    |_ctx, data: &mut AppState, _env| {
+                        data.apply_infer(false);
+                    },
+                ))
+                .with_child(Button::new(LocalizedString::new("Write tags")).on_click(
+                    |_ctx, data: &mut AppState, _env| {
+                        data.apply_infer(true);
+                    },
+                ))
+                .with_child(
+                    Button::new(LocalizedString::new("Close"))
+                        .on_click(|ctx, _data: &mut AppState, _env| close_window(ctx)),
+                )
+                .with_default_spacer()
+                .with_flex_child(
+                    Label::dynamic(|d: &AppState, _| d.message.to_owned())
+                        .with_text_size(12.0)
+                        .expand_width(),
+                    1.0,
+                ),
+        )
+        .padding(10.0)
+}
+
+// 重复分组中的一个版本
+fn make_duplicate_item() -> impl Widget<DuplicateItem> {
+    Flex::row()
+        .with_child(Checkbox::new(LocalizedString::new("Keep")).lens(DuplicateItem::keep))
+        .with_default_spacer()
+        .with_flex_child(
+            Flex::column()
+                .cross_axis_alignment(CrossAxisAlignment::Start)
+                .with_child(Label::dynamic(|d: &DuplicateItem, _| {
+                    format!("{} - {}    {}", d.title, d.artist, d.quality)
+                }))
+                .with_child(
+                    Label::dynamic(|d: &DuplicateItem, _| d.file.to_owned()).with_text_size(12.0),
+                )
+                .expand_width(),
+            1.0,
+        )
+        .padding(2.0)
+}
+
+fn make_duplicate_group() -> impl Widget<DuplicateGroup> {
+    Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(List::new(make_duplicate_item).lens(DuplicateGroup::items))
+        .with_child(Button::new(LocalizedString::new("Keep checked")).on_click(
+            |ctx, d: &mut DuplicateGroup, _env| {
+                ctx.submit_command(KEEP_DUPLICATES.with(d.to_owned()));
+            },
+        ))
+        .padding(5.0)
+        .border(Color::grey(0.6), 1.0)
+}
+
+// 属性窗口，用于检查抓轨文件的格式，标签和完整性
+fn inspector_window() -> impl Widget<AppState> {
+    let section = |title: &'static str| {
+        Label::new(LocalizedString::new(title))
+            .with_text_size(15.0)
+            .padding((0.0, 8.0, 0.0, 2.0))
+    };
+    let text = |f: fn(&Inspection) -> String| {
+        Label::dynamic(move |d: &Inspection, _| f(d))
+            .with_text_size(12.0)
+            .with_line_break_mode(LineBreaking::WordWrap)
+    };
+    Scroll::new(
+        Flex::column()
+            .cross_axis_alignment(CrossAxisAlignment::Start)
+            .with_child(text(|d| d.properties.to_owned()))
+            .with_child(section("Tags"))
+            .with_child(text(|d| d.tags.to_owned()))
+            .with_child(section("Pictures"))
+            .with_child(text(|d| d.pictures.to_owned()))
+            .with_child(section("MD5"))
+            .with_child(text(|d| d.md5.to_owned()))
+            .with_child(
+                Flex::row()
+                    .with_child(
+                        Button::new(LocalizedString::new("Verify MD5"))
+                            .on_click(|ctx, d: &mut Inspection, _env| {
+                                d.verify = "正在解码校验".to_owned();
+                                ctx.submit_command(VERIFY_MD5.with(d.file.to_owned()));
+                            })
+                            .disabled_if(|d: &Inspection, _| d.md5.is_empty()),
+                    )
+                    .with_default_spacer()
+                    .with_flex_child(text(|d| d.verify.to_owned()).expand_width(), 1.0),
+            )
+            .padding(10.0),
     )
+    .vertical()
+    .lens(AppState::inspection)
 }
 
-// 加入歌曲到音轨sink
-fn add_paly_song<'a>(f: &'a str, sink: &'a rodio::Sink) {
-    let file = std::fs::File::open(f).unwrap();
-    let source = rodio::Decoder::new(BufReader::new(file)).unwrap();
-    sink.append(source);
+fn make_playlist_item() -> impl Widget<PlayList> {
+    Flex::row()
+        .with_child(Checkbox::new("").lens(PlayList::sync))
+        .with_flex_child(
+            Label::dynamic(|d: &PlayList, _| format!("{}  ({})", d.name, d.songs.len()))
+                .expand_width(),
+            1.0,
+        )
+        .with_child(Button::new(LocalizedString::new("Delete")).on_click(
+            |ctx, d: &mut PlayList, _env| {
+                ctx.submit_command(REMOVE_PLAYLIST.with(d.name.to_owned()));
+            },
+        ))
+        .padding(2.0)
+}
+
+// 同步窗口，管理播放列表并同步到设备目录。转换格式使用导出设置
+fn sync_window() -> impl Widget<AppState> {
+    Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(
+            Flex::row()
+                .with_child(Label::new(LocalizedString::new("Playlist name")))
+                .with_default_spacer()
+                .with_flex_child(
+                    TextBox::new()
+                        .lens(AppState::new_playlist_name)
+                        .expand_width(),
+                    1.0,
+                )
+                .with_child(
+                    Button::new(LocalizedString::new("Save as playlist")).on_click(
+                        |_ctx, data: &mut AppState, _env| {
+                            data.save_playlist();
+                        },
+                    ),
+                ),
+        )
+        .with_default_spacer()
+        .with_flex_child(
+            Scroll::new(List::new(make_playlist_item).lens(AppState::play_lists)).vertical(),
+            1.0,
+        )
+        .with_default_spacer()
+        .with_child(
+            Flex::row()
+                .with_child(Label::new(LocalizedString::new("Sync to")))
+                .with_default_spacer()
+                .with_flex_child(TextBox::new().lens(AppState::sync_dir).expand_width(), 1.0),
+        )
+        .with_child(
+            Flex::row()
+                .with_child(Radio::new(
+                    LocalizedString::new("Copy all"),
+                    SyncTranscode::Never,
+                ))
+                .with_child(Radio::new(
+                    LocalizedString::new("Convert lossless"),
+                    SyncTranscode::Lossless,
+                ))
+                .with_child(Radio::new(
+                    LocalizedString::new("Convert all"),
+                    SyncTranscode::Always,
+                ))
+                .lens(AppState::sync_transcode),
+        )
+        .with_child(Label::new(LocalizedString::new("Sync convert hint")).with_text_size(12.0))
+        .with_default_spacer()
+        .with_child(
+            Flex::row()
+                .with_child(
+                    Button::new(LocalizedString::new("Sync"))
+                        .on_click(|_ctx, data: &mut AppState, _env| {
+                            data.start_sync();
+                        })
+                        .disabled_if(|d: &AppState, _| d.syncing),
+                )
+                .with_child(
+                    Button::new(LocalizedString::new("Cancel"))
+                        .on_click(|_ctx, data: &mut AppState, _env| {
+                            data.sync_cancel.store(true, Ordering::Relaxed);
+                        })
+                        .disabled_if(|d: &AppState, _| !d.syncing),
+                )
+                .with_default_spacer()
+                .with_flex_child(
+                    Label::dynamic(|d: &AppState, _| d.sync_progress.to_owned())
+                        .with_line_break_mode(LineBreaking::WordWrap)
+                        .expand_width(),
+                    1.0,
+                ),
+        )
+        .padding(10.0)
+}
+
+// 导出窗口，选择格式，采样率和位深
+fn export_window() -> impl Widget<AppState> {
+    Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(
+            Flex::row()
+                .with_child(Label::new(LocalizedString::new("Export to")))
+                .with_default_spacer()
+                .with_flex_child(
+                    TextBox::new().lens(AppState::export_dir).expand_width(),
+                    1.0,
+                ),
+        )
+        .with_default_spacer()
+        .with_child(
+            Flex::row()
+                .with_child(Label::new(LocalizedString::new("Format")))
+                .with_child(Radio::new("MP3 V0", ExportFormat::Mp3V0))
+                .with_child(Radio::new("MP3 320k", ExportFormat::Mp3Cbr320))
+                .with_child(Radio::new("AAC 256k", ExportFormat::Aac))
+                .with_child(Radio::new("Opus 160k", ExportFormat::Opus))
+                .with_child(Radio::new("FLAC", ExportFormat::Flac))
+                .lens(AppState::export_format),
+        )
+        .with_child(
+            Flex::row()
+                .with_child(Label::new(LocalizedString::new("Sample rate")))
+                .with_child(Radio::new(LocalizedString::new("Same as source"), 0))
+                .with_child(Radio::new("44.1kHz", 44100))
+                .with_child(Radio::new("48kHz", 48000))
+                .with_child(Radio::new("96kHz", 96000))
+                .lens(AppState::export_rate),
+        )
+        .with_child(
+            Flex::row()
+                .with_child(Label::new(LocalizedString::new("Bit depth")))
+                .with_child(Radio::new(LocalizedString::new("Same as source"), 0))
+                .with_child(Radio::new("16 bit", 16))
+                .with_child(Radio::new("24 bit", 24))
+                .lens(AppState::export_bits)
+                .disabled_if(|d: &AppState, _| d.export_format != ExportFormat::Flac),
+        )
+        .with_default_spacer()
+        .with_child(
+            Flex::row()
+                .with_child(
+                    Button::new(LocalizedString::new("Export"))
+                        .on_click(|_ctx, data: &mut AppState, _env| {
+                            data.start_export();
+                        })
+                        .disabled_if(|d: &AppState, _| d.exporting),
+                )
+                .with_child(
+                    Button::new(LocalizedString::new("Cancel"))
+                        .on_click(|_ctx, data: &mut AppState, _env| {
+                            data.export_cancel.store(true, Ordering::Relaxed);
+                        })
+                        .disabled_if(|d: &AppState, _| !d.exporting),
+                )
+                .with_default_spacer()
+                .with_flex_child(
+                    Label::dynamic(|d: &AppState, _| d.export_progress.to_owned())
+                        .with_line_break_mode(LineBreaking::WordWrap)
+                        .expand_width(),
+                    1.0,
+                ),
+        )
+        .with_default_spacer()
+        .with_child(
+            Label::new(LocalizedString::new("Export hint"))
+                .with_text_size(12.0)
+                .with_line_break_mode(LineBreaking::WordWrap),
+        )
+        .padding(10.0)
+}
+
+// 候选发行，显示和现有标签的差异
+fn make_release_candidate() -> impl Widget<ReleaseCandidate> {
+    Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(
+            Flex::row()
+                .with_flex_child(
+                    Label::dynamic(|d: &ReleaseCandidate, _| d.summary.to_owned()).expand_width(),
+                    1.0,
+                )
+                .with_child(Button::new(LocalizedString::new("Apply")).on_click(
+                    |ctx, d: &mut ReleaseCandidate, _env| {
+                        ctx.submit_command(APPLY_RELEASE.with(d.to_owned()));
+                    },
+                )),
+        )
+        .with_child(
+            Label::dynamic(|d: &ReleaseCandidate, _| d.diff.to_owned())
+                .with_text_size(12.0)
+                .with_line_break_mode(LineBreaking::WordWrap),
+        )
+        .padding(5.0)
+        .border(Color::grey(0.6), 1.0)
+}
+
+// 匹配专辑窗口
+fn match_window() -> impl Widget<AppState> {
+    Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(Label::dynamic(|d: &AppState, _| d.match_status.to_owned()))
+        .with_default_spacer()
+        .with_flex_child(
+            Scroll::new(
+                List::new(make_release_candidate)
+                    .with_spacing(5.0)
+                    .lens(AppState::match_candidates),
+            )
+            .vertical(),
+            1.0,
+        )
+        .padding(10.0)
+}
+
+// 重复歌曲窗口，默认勾选音质最好的版本
+fn duplicates_window() -> impl Widget<AppState> {
+    Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(
+            Flex::row()
+                .with_child(
+                    Button::new(LocalizedString::new("Scan"))
+                        .on_click(|_ctx, data: &mut AppState, _env| {
+                            data.start_duplicate_scan();
+                        })
+                        .disabled_if(|d: &AppState, _| d.duplicate_scanning),
+                )
+                .with_child(
+                    Button::new(LocalizedString::new("Cancel"))
+                        .on_click(|_ctx, data: &mut AppState, _env| {
+                            data.duplicate_cancel.store(true, Ordering::Relaxed);
+                        })
+                        .disabled_if(|d: &AppState, _| !d.duplicate_scanning),
+                )
+                .with_default_spacer()
+                .with_child(Label::dynamic(|d: &AppState, _| {
+                    d.duplicate_progress.to_owned()
+                }))
+                .with_default_spacer()
+                .with_flex_child(
+                    Label::dynamic(|d: &AppState, _| d.message.to_owned())
+                        .with_text_size(12.0)
+                        .expand_width(),
+                    1.0,
+                ),
+        )
+        .with_default_spacer()
+        .with_flex_child(
+            Scroll::new(
+                List::new(make_duplicate_group)
+                    .with_spacing(5.0)
+                    .lens(AppState::duplicates),
+            )
+            .vertical(),
+            1.0,
+        )
+        .padding(10.0)
+}
+
+// 校验报告中的一首歌曲
+fn make_damaged_item() -> impl Widget<Song> {
+    Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(Label::dynamic(|d: &Song, _| {
+            format!("{} - {}    {}", d.title, d.artist, d.damaged)
+        }))
+        .with_child(Label::dynamic(|d: &Song, _| d.file.to_owned()).with_text_size(12.0))
+        .padding(2.0)
+}
+
+// 资料库完整性校验窗口，结果保存在数据目录中，再次校验时跳过没有修改过的文件
+fn integrity_window() -> impl Widget<AppState> {
+    Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(
+            Flex::row()
+                .with_child(
+                    Button::new(LocalizedString::new("Verify"))
+                        .on_click(|_ctx, data: &mut AppState, _env| {
+                            data.start_integrity_check();
+                        })
+                        .disabled_if(|d: &AppState, _| d.integrity_checking),
+                )
+                .with_child(
+                    Button::new(LocalizedString::new("Cancel"))
+                        .on_click(|_ctx, data: &mut AppState, _env| {
+                            data.integrity_cancel.store(true, Ordering::Relaxed);
+                        })
+                        .disabled_if(|d: &AppState, _| !d.integrity_checking),
+                )
+                .with_child(
+                    Button::new(LocalizedString::new("Show damaged"))
+                        .on_click(|_ctx, data: &mut AppState, _env| {
+                            data.show_damaged = true;
+                            data.refresh_show_list();
+                        })
+                        .disabled_if(|d: &AppState, _| d.integrity_report.is_empty()),
+                )
+                .with_default_spacer()
+                .with_child(Label::dynamic(|d: &AppState, _| {
+                    d.integrity_progress.to_owned()
+                }))
+                .with_default_spacer()
+                .with_flex_child(
+                    Label::dynamic(|d: &AppState, _| d.message.to_owned())
+                        .with_text_size(12.0)
+                        .expand_width(),
+                    1.0,
+                ),
+        )
+        .with_default_spacer()
+        .with_flex_child(
+            Scroll::new(List::new(make_damaged_item).lens(AppState::integrity_report)).vertical(),
+            1.0,
+        )
+        .padding(10.0)
+}
+
+// 播放队列条目
+fn make_queue_item() -> impl Widget<QueueItem> {
+    Flex::row()
+        .with_child(
+            Label::dynamic(|d: &QueueItem, _| if d.current { "|>" } else { "" }.to_owned())
+                .fix_width(20.0),
+        )
+        .with_flex_child(
+            Label::dynamic(|d: &QueueItem, _| format!("{} - {}", d.song.title, d.song.artist))
+                .expand_width(),
+            1.0,
+        )
+        .with_child(Button::new("↑").on_click(|ctx, d: &mut QueueItem, _env| {
+            if d.index > 0 {
+                ctx.submit_command(QUEUE_MOVE.with((d.index, d.index - 1)));
+            }
+        }))
+        .with_child(Button::new("↓").on_click(|ctx, d: &mut QueueItem, _env| {
+            ctx.submit_command(QUEUE_MOVE.with((d.index, d.index + 1)));
+        }))
+        .with_child(Button::new("x").on_click(|ctx, d: &mut QueueItem, _env| {
+            ctx.submit_command(QUEUE_REMOVE.with(d.index));
+        }))
+        .padding(3.0)
+}
+
+// 播放队列面板
+fn queue_panel() -> impl Widget<AppState> {
+    Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(
+            Flex::row()
+                .with_child(Label::new(LocalizedString::new("Queue")))
+                .with_default_spacer()
+                .with_child(Button::new(LocalizedString::new("Clear")).on_click(
+                    |_ctx, data: &mut AppState, _env| {
+                        data.queue.lock().unwrap().clear();
+                        data.sync_queue_view();
+                    },
+                )),
+        )
+        .with_default_spacer()
+        .with_flex_child(
+            Scroll::new(List::new(make_queue_item).lens(AppState::queue_view)).vertical(),
+            1.0,
+        )
+        .padding(5.0)
+}
+
+// 电台条目
+fn make_station() -> impl Widget<Station> {
+    Flex::row()
+        .with_flex_child(
+            Label::dynamic(|d: &Station, _| d.name.to_owned()).expand_width(),
+            1.0,
+        )
+        .with_child(Button::new(LocalizedString::new("Play")).on_click(
+            |ctx, d: &mut Station, _env| {
+                ctx.submit_command(PLAY_STATION.with(d.to_owned()));
+            },
+        ))
+        .with_child(Button::new("+").on_click(|ctx, d: &mut Station, _env| {
+            ctx.submit_command(QUEUE_APPEND.with(d.to_song()));
+        }))
+        .with_child(Button::new("x").on_click(|ctx, d: &mut Station, _env| {
+            ctx.submit_command(REMOVE_STATION.with(d.url.to_owned()));
+        }))
+        .padding(3.0)
+}
+
+// 网络电台面板
+fn stations_panel() -> impl Widget<AppState> {
+    Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(Label::new(LocalizedString::new("Radio")))
+        .with_default_spacer()
+        .with_child(
+            Flex::row()
+                .with_flex_child(
+                    TextBox::new()
+                        .with_placeholder("名称")
+                        .lens(AppState::station_name)
+                        .expand_width(),
+                    1.0,
+                )
+                .with_flex_child(
+                    TextBox::new()
+                        .with_placeholder("http://")
+                        .lens(AppState::station_url)
+                        .expand_width(),
+                    2.0,
+                )
+                .with_child(
+                    Button::new(LocalizedString::new("Add"))
+                        .on_click(|_ctx, data: &mut AppState, _env| data.add_station()),
+                ),
+        )
+        .with_default_spacer()
+        .with_flex_child(
+            Scroll::new(List::new(make_station).lens(AppState::stations)).vertical(),
+            1.0,
+        )
+        .padding(5.0)
+}
+
+// 播客单集
+fn make_episode() -> impl Widget<Episode> {
+    Flex::row()
+        .with_flex_child(
+            Label::dynamic(|d: &Episode, _| d.title.to_owned()).expand_width(),
+            1.0,
+        )
+        .with_child(Label::dynamic(|d: &Episode, _| d.published.to_owned()).with_text_size(12.0))
+        .with_default_spacer()
+        .with_child(
+            Label::dynamic(|d: &Episode, _| {
+                if d.downloading {
+                    "下载中".to_owned()
+                } else if d.position_ms > 0 && !d.played {
+                    let secs = d.position_ms / 1000;
+                    format!("{}:{:02}", secs / 60, secs % 60)
+                } else {
+                    "".to_owned()
+                }
+            })
+            .with_text_size(12.0),
+        )
+        .with_child(
+            Button::new(LocalizedString::new("Download"))
+                .on_click(|ctx, d: &mut Episode, _env| {
+                    ctx.submit_command(
+                        DOWNLOAD_EPISODE.with((d.podcast.to_owned(), d.id.to_owned())),
+                    );
+                })
+                .disabled_if(|d: &Episode, _| !d.file.is_empty() || d.downloading),
+        )
+        .with_child(
+            Button::new(LocalizedString::new("Play"))
+                .on_click(|ctx, d: &mut Episode, _env| {
+                    ctx.submit_command(PLAY_EPISODE.with((d.podcast.to_owned(), d.id.to_owned())));
+                })
+                .disabled_if(|d: &Episode, _| d.file.is_empty()),
+        )
+        .with_child(Checkbox::new(LocalizedString::new("Played")).lens(Episode::played))
+        .padding(3.0)
+}
+
+// 播客订阅及其单集
+fn make_podcast() -> impl Widget<Podcast> {
+    Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(
+            Flex::row()
+                .with_flex_child(
+                    Label::dynamic(|d: &Podcast, _| d.title.to_owned()).expand_width(),
+                    1.0,
+                )
+                .with_child(Button::new(LocalizedString::new("Refresh")).on_click(
+                    |ctx, d: &mut Podcast, _env| {
+                        ctx.submit_command(REFRESH_FEED.with(d.url.to_owned()));
+                    },
+                ))
+                .with_child(Button::new("x").on_click(|ctx, d: &mut Podcast, _env| {
+                    ctx.submit_command(UNSUBSCRIBE.with(d.url.to_owned()));
+                })),
+        )
+        .with_child(List::new(make_episode).lens(Podcast::episodes))
+        .padding(3.0)
+}
+
+// 播客面板
+fn podcasts_panel() -> impl Widget<AppState> {
+    Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(
+            Flex::row()
+                .with_flex_child(
+                    TextBox::new()
+                        .with_placeholder("https://example.com/feed.xml")
+                        .lens(AppState::feed_url)
+                        .expand_width(),
+                    1.0,
+                )
+                .with_child(Button::new(LocalizedString::new("Subscribe")).on_click(
+                    |ctx, data: &mut AppState, _env| {
+                        let url = data.feed_url.trim().to_owned();
+                        if url.is_empty() {
+                            return;
+                        }
+                        podcast::fetch_async(url, ctx.get_external_handle());
+                        data.message = "正在读取订阅".to_owned();
+                        data.feed_url.clear();
+                    },
+                )),
+        )
+        .with_child(
+            Checkbox::new(LocalizedString::new("Auto-delete played"))
+                .lens(AppState::podcast_auto_delete),
+        )
+        .with_default_spacer()
+        .with_flex_child(
+            Scroll::new(List::new(make_podcast).lens(AppState::podcasts)).vertical(),
+            1.0,
+        )
+        .padding(5.0)
+}
+
+// 歌词行，当前行已唱的部分高亮
+fn make_lyric_row() -> impl Widget<LyricRow> {
+    Flex::row()
+        .with_child(
+            Label::dynamic(|d: &LyricRow, _| d.sung.to_owned())
+                .with_text_color(Color::rgb8(0x33, 0x99, 0xff))
+                .with_text_size(16.0),
+        )
+        .with_child(Label::dynamic(|d: &LyricRow, _| d.rest.to_owned()))
+        .padding(2.0)
+}
+
+// 歌词面板
+fn lyrics_viewer() -> impl Widget<AppState> {
+    Flex::column()
+        .with_child(
+            Flex::row()
+                .with_flex_child(
+                    Label::dynamic(|d: &AppState, _| {
+                        if d.lyrics.is_empty() {
+                            "没有歌词".to_owned()
+                        } else {
+                            "".to_owned()
+                        }
+                    })
+                    .expand_width(),
+                    1.0,
+                )
+                .with_child(Button::new(LocalizedString::new("Edit lyrics")).on_click(
+                    |_ctx, data: &mut AppState, _env| {
+                        data.start_lyrics_edit();
+                    },
+                )),
+        )
+        .with_flex_child(
+            Scroll::new(List::new(make_lyric_row).lens(AppState::lyrics_view)).vertical(),
+            1.0,
+        )
+        .padding(5.0)
+}
+
+// 歌词编辑器中的一行：时间，微调按钮和歌词，点击歌词选择下一次打点的行
+fn make_editor_line() -> impl Widget<EditorLine> {
+    let nudge = |label: &'static str, delta: i64| {
+        Button::new(label)
+            .on_click(move |_ctx, d: &mut EditorLine, _env| {
+                if let Some(t) = d.time_ms {
+                    d.time_ms = Some((t as i64 + delta).max(0) as u64);
+                }
+            })
+            .disabled_if(|d: &EditorLine, _| d.time_ms.is_none())
+    };
+    Flex::row()
+        .with_child(
+            Label::dynamic(|d: &EditorLine, _| {
+                d.time_ms.map_or("--:--.--".to_owned(), lyrics::format_time)
+            })
+            .with_text_size(12.0),
+        )
+        .with_child(nudge("-", -100))
+        .with_child(nudge("+", 100))
+        .with_flex_child(
+            Label::dynamic(|d: &EditorLine, _| {
+                if d.next {
+                    format!("▶ {}", d.text)
+                } else {
+                    d.text.to_owned()
+                }
+            })
+            .expand_width()
+            .on_click(|ctx, d: &mut EditorLine, _env| {
+                ctx.submit_command(EDITOR_SELECT.with(d.index));
+            }),
+            1.0,
+        )
+        .padding(2.0)
+}
+
+// 歌词编辑器：粘贴纯文本，播放时按打点键记录每行的时间
+fn lyrics_editor() -> impl Widget<AppState> {
+    Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(Label::dynamic(|d: &AppState, _| {
+            format!(
+                "{} ({})",
+                Path::new(&d.editor_file)
+                    .file_name()
+                    .map_or("".to_owned(), |f| f.to_string_lossy().to_string()),
+                d.config.keys.tap_sync
+            )
+        }))
+        .with_child(
+            TextBox::multiline()
+                .with_placeholder("每行一句歌词")
+                .lens(AppState::editor_text)
+                .expand_width()
+                .fix_height(100.0),
+        )
+        .with_child(
+            Flex::row()
+                .with_child(Button::new(LocalizedString::new("Load text")).on_click(
+                    |_ctx, data: &mut AppState, _env| {
+                        data.load_editor_text();
+                    },
+                ))
+                .with_child(
+                    Button::new(LocalizedString::new("Tap"))
+                        .on_click(|_ctx, data: &mut AppState, _env| {
+                            data.tap_sync();
+                        })
+                        .disabled_if(|d: &AppState, _| d.editor_cursor >= d.editor_lines.len()),
+                ),
+        )
+        .with_child(
+            Flex::row()
+                .with_child(Button::new(LocalizedString::new("Preview")).on_click(
+                    |_ctx, data: &mut AppState, _env| {
+                        data.preview_lyrics();
+                    },
+                ))
+                .with_child(Button::new(LocalizedString::new("Save LRC")).on_click(
+                    |_ctx, data: &mut AppState, _env| {
+                        data.save_lrc();
+                    },
+                ))
+                .with_child(Button::new(LocalizedString::new("Embed")).on_click(
+                    |_ctx, data: &mut AppState, _env| {
+                        data.embed_lyrics();
+                    },
+                ))
+                .with_child(Button::new(LocalizedString::new("Done")).on_click(
+                    |_ctx, data: &mut AppState, _env| {
+                        data.lyrics_editing = false;
+                    },
+                )),
+        )
+        .with_default_spacer()
+        .with_flex_child(
+            Scroll::new(List::new(make_editor_line).lens(AppState::editor_lines)).vertical(),
+            1.0,
+        )
+        .padding(5.0)
+}
+
+fn lyrics_panel() -> impl Widget<AppState> {
+    ViewSwitcher::new(
+        |d: &AppState, _| d.lyrics_editing,
+        |editing, _, _| {
+            if *editing {
+                lyrics_editor().boxed()
+            } else {
+                lyrics_viewer().boxed()
+            }
+        },
+    )
+}
+
+// 右侧面板：播放队列，网络电台，播客，歌词
+fn side_panel() -> impl Widget<AppState> {
+    let tab = |name: &'static str, panel: SidePanel| {
+        Button::new(LocalizedString::new(name)).on_click(move |_ctx, data: &mut AppState, _env| {
+            data.side_panel = panel;
+        })
+    };
+    Flex::column()
+        .with_child(
+            Flex::row()
+                .with_child(tab("Queue", SidePanel::Queue))
+                .with_child(tab("Radio", SidePanel::Radio))
+                .with_child(tab("Podcasts", SidePanel::Podcasts))
+                .with_child(tab("Lyrics", SidePanel::Lyrics)),
+        )
+        .with_flex_child(
+            ViewSwitcher::new(
+                |d: &AppState, _| d.side_panel,
+                |panel, _, _| match panel {
+                    SidePanel::Queue => queue_panel().boxed(),
+                    SidePanel::Radio => stations_panel().boxed(),
+                    SidePanel::Podcasts => podcasts_panel().boxed(),
+                    SidePanel::Lyrics => lyrics_panel().boxed(),
+                },
+            ),
+            1.0,
+        )
+}
+
+// 浏览面板节点
+fn make_node() -> impl Widget<BrowseNode> {
+    Label::dynamic(|d: &BrowseNode, _| format!("{} ({})", d.label, d.count))
+        .padding(3.0)
+        .expand_width()
+        .on_click(|ctx, d, _env| {
+            ctx.submit_command(BROWSE_SELECT.with((d.level, d.key.to_owned())));
+        })
+}
+
+// 专辑封面缩略图
+fn make_album_cell() -> impl Widget<BrowseNode> {
+    Flex::column()
+        .with_child(ViewSwitcher::new(
+            |d: &BrowseNode, _| d.cover.to_owned(),
+            |cover, _, _| match ImageBuf::from_file(cover) {
+                Ok(buf) => Image::new(buf).fix_size(96.0, 96.0).boxed(),
+                Err(_) => Label::new("♪").center().fix_size(96.0, 96.0).boxed(),
+            },
+        ))
+        .with_child(Label::dynamic(|d: &BrowseNode, _| d.label.to_owned()).fix_width(96.0))
+        .padding(5.0)
+        .on_click(|ctx, d, _env| {
+            ctx.submit_command(BROWSE_SELECT.with((d.level, d.key.to_owned())));
+        })
+}
+
+// 资料库浏览面板：艺人/专辑/流派/年代/文件夹
+fn browse_panel() -> impl Widget<AppState> {
+    let mode_button = |name: &'static str, mode: BrowseMode| {
+        Button::new(LocalizedString::new(name)).on_click(move |_ctx, data: &mut AppState, _env| {
+            data.set_browse_mode(mode.to_owned());
+        })
+    };
+    let modes = Flex::row()
+        .with_child(mode_button("Artist", BrowseMode::Artist))
+        .with_child(mode_button("Album", BrowseMode::Album))
+        .with_child(mode_button("Genre", BrowseMode::Genre))
+        .with_child(mode_button("Year", BrowseMode::Year))
+        .with_child(mode_button("Folder", BrowseMode::Folder));
+
+    let back = Flex::row()
+        .with_child(
+            Button::new("<").on_click(|_ctx, data: &mut AppState, _env| {
+                data.browse_path.pop_back();
+                data.refresh_browse();
+                data.refresh_show_list();
+            }),
+        )
+        .with_default_spacer()
+        .with_flex_child(
+            Label::dynamic(|d: &AppState, _| {
+                d.browse_path
+                    .iter()
+                    .cloned()
+                    .collect::<Vec<String>>()
+                    .join(" / ")
+            })
+            .expand_width(),
+            1.0,
+        );
+
+    // 专辑模式第一级显示封面网格，其他显示列表
+    let nodes = ViewSwitcher::new(
+        |d: &AppState, _| d.browse_mode == BrowseMode::Album && d.browse_path.is_empty(),
+        |is_grid, _, _| {
+            if *is_grid {
+                Box::new(List::new(|| List::new(make_album_cell).horizontal()).lens(
+                    lens::Map::new(
+                        |d: &AppState| {
+                            let mut rows: Vector<Vector<BrowseNode>> = vector![];
+                            for (i, node) in d.browse_nodes.iter().enumerate() {
+                                if i % 3 == 0 {
+                                    rows.push_back(vector![]);
+                                }
+                                rows.back_mut().unwrap().push_back(node.to_owned());
+                            }
+                            rows
+                        },
+                        |_d: &mut AppState, _rows| {},
+                    ),
+                ))
+            } else {
+                Box::new(List::new(make_node).lens(AppState::browse_nodes))
+            }
+        },
+    );
+
+    Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(modes)
+        .with_default_spacer()
+        .with_child(back)
+        .with_default_spacer()
+        .with_flex_child(Scroll::new(nodes).vertical(), 1.0)
+        .padding(5.0)
 }
 
 fn paly_song<'a>(f: &'a str, output: &'a Arc<OutputStreamHandle>) {
@@ -619,58 +3820,89 @@ fn paly_song<'a>(f: &'a str, output: &'a Arc<OutputStreamHandle>) {
     output.play_raw(source.convert_samples()).unwrap();
 }
 
-// 获取上一首歌
-fn prev_song(play_mode: Modes, play_list: &mut Vector<Song>) -> Option<Song> {
-    match play_mode {
-        _ => {
-            let mut this_index: usize = 0;
-            let mut prev_index: usize = 0;
-            let max = play_list.len() - 1;
-            for (k, v) in play_list.iter_mut().enumerate() {
-                if v.playing == true {
-                    this_index = k;
-                    v.playing = false;
-                }
-            }
-            if this_index == 0 {
-                // prev_index = max;
-                println!("已经是第一首歌曲!");
-                return None;
-                // play_list[prev_index].playing = true;
-                // return play_list[max].to_owned();
-            } else {
-                prev_index = this_index - 1;
-                play_list[prev_index].playing = true;
-                return Some(play_list[prev_index].to_owned());
-            }
-        }
+fn rename_message(moved: usize, errors: &[String]) -> String {
+    match errors.first() {
+        None => format!("已移动 {} 个文件", moved),
+        Some(e) => format!("已移动 {} 个文件，{} 个失败: {}", moved, errors.len(), e),
     }
 }
 
-// 获取下一首歌曲
-fn next_song(play_mode: Modes, play_list: &mut Vector<Song>) -> Option<Song> {
-    match play_mode {
-        _ => {
-            let mut this_index: usize = 0;
-            let mut next_index: usize = 0;
-            let max = play_list.len() - 1;
-            for (k, v) in play_list.iter_mut().enumerate() {
-                if v.playing == true {
-                    this_index = k;
-                    v.playing = false;
-                }
+// 插入到当前歌曲之后并立即播放
+fn play_now(data: &mut AppState, song: Song) {
+    data.queue.lock().unwrap().play_next(song);
+    data.sync_queue_view();
+    if *data.app_status.lock().unwrap() == Status::Stop {
+        play(data);
+    } else {
+        *data.app_status.lock().unwrap() = Status::Next;
+    }
+}
+
+// 切换输出设备，正在播放的歌曲从当前进度继续播放
+fn switch_device(data: &mut AppState, device: &str) {
+    let (new_output, handle) = match output::open(device) {
+        Ok(opened) => opened,
+        Err(e) => {
+            data.message = e;
+            return;
+        }
+    };
+    let playing = !data.sink.lock().unwrap().empty();
+    let paused = data.sink.lock().unwrap().is_paused();
+    let volume = data.sink.lock().unwrap().volume();
+    let position = Duration::from_millis(data.position.load(Ordering::Relaxed));
+    data.sink.lock().unwrap().stop();
+    // 新设备打开后再关闭旧设备
+    *data.stream.lock().unwrap() = Some(handle);
+    *data.output.lock().unwrap() = Some(new_output);
+    *data.sink.lock().unwrap() = output::new_sink(&data.stream);
+    data.sink.lock().unwrap().set_volume(volume);
+    if playing {
+        let current = data.current_song.lock().unwrap().file.to_owned();
+        let player = data.player();
+        // 电台需要连接和缓冲，不能阻塞界面
+        spawn(move || {
+            if let Err(e) = player.add_song(&current, position) {
+                println!("切换设备后无法继续播放 {}: {}", current, e);
+                return;
             }
-            if this_index == max {
-                // next_index = 0;
-                println!("已经是最后一首歌曲!");
-                // play_list[next_index].playing = true;
-                // return play_list[next_index].to_owned();
-                return None;
-            } else {
-                next_index = this_index + 1;
-                play_list[next_index].playing = true;
-                return Some(play_list[next_index].to_owned());
+            if paused {
+                player.sink.lock().unwrap().pause();
+            }
+        });
+    }
+    data.device = device.to_owned();
+    data.message = "".to_owned();
+}
+
+// 开始播放或继续播放
+fn play(data: &mut AppState) {
+    *data.app_status.lock().unwrap() = Status::Stop;
+    *data.app_status.lock().unwrap() = Status::Play;
+    if data.sink.lock().unwrap().is_paused() {
+        data.sink.lock().unwrap().play();
+    } else {
+        if data.sink.lock().unwrap().empty() {
+            *data.sink.lock().unwrap() = output::new_sink(&data.stream);
+            data.sink.lock().unwrap().set_volume(data.volume as f32);
+            // 队列为空时，用当前列表视图中的歌曲生成播放队列
+            if data.queue.lock().unwrap().is_empty() {
+                data.queue
+                    .lock()
+                    .unwrap()
+                    .replace(data.show_list.iter().cloned().collect());
             }
+            data.sync_queue_view();
+            // 本次启动还没播放过，并且恢复了上次的队列时，从上次的进度继续播放
+            let resume = data
+                .queue
+                .lock()
+                .unwrap()
+                .current()
+                .filter(|_| data.current_song.lock().unwrap().file.is_empty())
+                .map(|_| Duration::from_millis(data.position.load(Ordering::Relaxed)));
+            // 启动单独进程,按播放队列播放歌曲
+            data.player().start(resume);
         }
     }
 }
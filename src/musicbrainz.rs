@@ -0,0 +1,378 @@
+use crate::chromaprint;
+use crate::fingerprint;
+use crate::Song;
+use druid::im::Vector;
+use druid::{Data, ExtEventSink, Lens, Selector, Target};
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread::spawn;
+
+// 已扫描的发行数量
+pub const MATCH_PROGRESS: Selector<usize> = Selector::new("flac-music.match-progress");
+// 匹配完成，按得分从高到低排列的候选
+pub const MATCH_DONE: Selector<Result<Vector<ReleaseCandidate>, String>> =
+    Selector::new("flac-music.match-done");
+
+// 最多显示的候选数量
+const MAX_CANDIDATES: usize = 5;
+// 时长得分低于该值的发行不作为候选
+const MIN_DURATION_SCORE: f64 = 0.6;
+// 时长相差在 2 秒内算完全一致，相差 15 秒以上不得分
+const DURATION_EXACT: f64 = 2.0;
+const DURATION_LIMIT: f64 = 15.0;
+
+// MusicBrainz JSON 数据包(mbdump/release)中每行一个发行，只读取用到的字段
+#[derive(Deserialize, Debug)]
+struct Release {
+    id: String,
+    title: String,
+    date: Option<String>,
+    #[serde(rename = "artist-credit", default)]
+    artist_credit: Vec<Credit>,
+    #[serde(default)]
+    media: Vec<Medium>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Credit {
+    name: String,
+    #[serde(default)]
+    joinphrase: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct Medium {
+    #[serde(default)]
+    position: u32,
+    #[serde(default)]
+    tracks: Vec<Track>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Track {
+    #[serde(default)]
+    position: u32,
+    title: String,
+    length: Option<u64>,
+    #[serde(rename = "artist-credit", default)]
+    artist_credit: Vec<Credit>,
+    recording: Option<Recording>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Recording {
+    id: String,
+}
+
+// 匹配到的发行。tags 为每个文件要写入的标签，diff 为和现有标签的差异
+#[derive(Clone, Data, Lens, Debug, Default)]
+pub struct ReleaseCandidate {
+    pub id: String,
+    pub summary: String,
+    pub diff: String,
+    pub cover: String,
+    pub tags: Arc<Vec<(String, Vec<(String, String)>)>>,
+}
+
+// 发行中的一条音轨，已按碟号和音轨号排序
+struct FlatTrack {
+    disc: u32,
+    number: u32,
+    title: String,
+    artist: String,
+    length: Option<u64>,
+    recording: String,
+}
+
+fn credit_name(credits: &[Credit]) -> String {
+    credits
+        .iter()
+        .map(|c| format!("{}{}", c.name, c.joinphrase))
+        .collect()
+}
+
+fn flatten(release: &Release) -> Vec<FlatTrack> {
+    let album_artist = credit_name(&release.artist_credit);
+    let mut media: Vec<&Medium> = release.media.iter().collect();
+    media.sort_by_key(|m| m.position);
+    let mut tracks = vec![];
+    for medium in media {
+        let mut medium_tracks: Vec<&Track> = medium.tracks.iter().collect();
+        medium_tracks.sort_by_key(|t| t.position);
+        for track in medium_tracks {
+            let artist = credit_name(&track.artist_credit);
+            tracks.push(FlatTrack {
+                disc: medium.position.max(1),
+                number: track.position,
+                title: track.title.to_owned(),
+                artist: if artist.is_empty() {
+                    album_artist.to_owned()
+                } else {
+                    artist
+                },
+                length: track.length,
+                recording: track
+                    .recording
+                    .as_ref()
+                    .map_or("".to_owned(), |r| r.id.to_owned()),
+            });
+        }
+    }
+    tracks
+}
+
+// "3/12" 这样的编号只取前面的数字
+fn number(s: &str) -> u32 {
+    s.split('/')
+        .next()
+        .and_then(|n| n.trim().parse().ok())
+        .unwrap_or(0)
+}
+
+// 专辑中的歌曲按碟号，音轨号和文件名排序，和发行的音轨一一对应
+pub fn album_order(songs: &mut [Song]) {
+    songs.sort_by(|a, b| {
+        (number(&a.disc).max(1), number(&a.track), &a.file).cmp(&(
+            number(&b.disc).max(1),
+            number(&b.track),
+            &b.file,
+        ))
+    });
+}
+
+fn duration_score(song: &Song, length: Option<u64>) -> f64 {
+    let length = match length {
+        Some(l) => l as f64 / 1000.0,
+        // 数据中没有时长的音轨不扣分也不加分
+        None => return 0.5,
+    };
+    let diff = (song.duration - length).abs();
+    if diff <= DURATION_EXACT {
+        1.0
+    } else if diff >= DURATION_LIMIT {
+        0.0
+    } else {
+        1.0 - (diff - DURATION_EXACT) / (DURATION_LIMIT - DURATION_EXACT)
+    }
+}
+
+// 音轨数相同时按时长打分
+fn match_durations(songs: &[Song], tracks: &[FlatTrack]) -> Option<f64> {
+    if songs.len() != tracks.len() || songs.is_empty() {
+        return None;
+    }
+    let total: f64 = songs
+        .iter()
+        .zip(tracks.iter())
+        .map(|(s, t)| duration_score(s, t.length))
+        .sum();
+    Some(total / songs.len() as f64)
+}
+
+// 从 fingerprints.txt 读取需要的录音的指纹，每行: 录音 MBID\t指纹。
+// 指纹为 fpcalc 输出的 FINGERPRINT，和 AcoustID 中保存的格式相同
+fn load_recording_prints(dump: &Path, wanted: &HashSet<String>) -> HashMap<String, Vec<u32>> {
+    let file = match File::open(dump.join("fingerprints.txt")) {
+        Ok(f) => f,
+        Err(_) => return HashMap::new(),
+    };
+    BufReader::new(file)
+        .lines()
+        .map_while(Result::ok)
+        .filter_map(|line| {
+            let (id, print) = line.split_once('\t')?;
+            if !wanted.contains(id) {
+                return None;
+            }
+            Some((id.to_owned(), chromaprint::decode(print)?))
+        })
+        .collect()
+}
+
+// 取得专辑中每个文件的 Chromaprint 指纹，计算失败的文件不返回
+fn song_prints(songs: &[Song]) -> HashMap<String, Vec<u32>> {
+    let mut prints = HashMap::new();
+    for song in songs {
+        match chromaprint::compute(&song.file) {
+            Ok(print) => {
+                prints.insert(song.file.to_owned(), print);
+            }
+            Err(e) => println!("计算指纹失败 {}: {}", song.file, e),
+        }
+    }
+    prints
+}
+
+// 有指纹时按声纹相似度打分，随机的两首歌相似度约为 0.5
+fn fingerprint_score(
+    songs: &[Song],
+    tracks: &[FlatTrack],
+    song_prints: &HashMap<String, Vec<u32>>,
+    recording_prints: &HashMap<String, Vec<u32>>,
+) -> Option<f64> {
+    let scores: Vec<f64> = songs
+        .iter()
+        .zip(tracks.iter())
+        .filter_map(|(s, t)| {
+            let a = song_prints.get(&s.file)?;
+            let b = recording_prints.get(&t.recording)?;
+            Some(((fingerprint::similarity(a, b) - 0.5) * 2.0).clamp(0.0, 1.0))
+        })
+        .collect();
+    if scores.is_empty() {
+        None
+    } else {
+        Some(scores.iter().sum::<f64>() / scores.len() as f64)
+    }
+}
+
+fn release_file(dump: &Path) -> Option<PathBuf> {
+    [dump.join("mbdump").join("release"), dump.join("release")]
+        .into_iter()
+        .find(|p| p.is_file())
+}
+
+// 数据包 covers 目录中的封面，文件名为发行的 MBID
+fn cover_file(dump: &Path, id: &str) -> String {
+    ["jpg", "png"]
+        .iter()
+        .map(|ext| dump.join("covers").join(format!("{}.{}", id, ext)))
+        .find(|p| p.is_file())
+        .map_or("".to_owned(), |p| p.display().to_string())
+}
+
+// 发行对应的标签和差异说明
+fn candidate(
+    release: &Release,
+    tracks: &[FlatTrack],
+    songs: &[Song],
+    score: f64,
+    dump: &Path,
+) -> ReleaseCandidate {
+    let album_artist = credit_name(&release.artist_credit);
+    let date = release.date.to_owned().unwrap_or_default();
+    let mut diff = vec![];
+    let mut tags = vec![];
+    for (song, track) in songs.iter().zip(tracks.iter()) {
+        let values = [
+            ("title", song.title.as_str(), track.title.to_owned()),
+            ("artist", song.artist.as_str(), track.artist.to_owned()),
+            ("album", song.album.as_str(), release.title.to_owned()),
+            (
+                "album_artist",
+                song.album_artist.as_str(),
+                album_artist.to_owned(),
+            ),
+            ("date", song.date.as_str(), date.to_owned()),
+            ("track", song.track.as_str(), track.number.to_string()),
+            ("disc", song.disc.as_str(), track.disc.to_string()),
+        ];
+        let name = Path::new(&song.file)
+            .file_name()
+            .map_or(song.file.to_owned(), |n| n.to_string_lossy().to_string());
+        let mut file_tags = vec![];
+        for (key, old, new) in values {
+            if new.is_empty() {
+                continue;
+            }
+            if old != new {
+                diff.push(format!("{}  {}: \"{}\" → \"{}\"", name, key, old, new));
+            }
+            file_tags.push((key.to_owned(), new));
+        }
+        file_tags.push(("MUSICBRAINZ_ALBUMID".to_owned(), release.id.to_owned()));
+        if !track.recording.is_empty() {
+            file_tags.push(("MUSICBRAINZ_TRACKID".to_owned(), track.recording.to_owned()));
+        }
+        tags.push((song.file.to_owned(), file_tags));
+    }
+    let cover = cover_file(dump, &release.id);
+    ReleaseCandidate {
+        id: release.id.to_owned(),
+        summary: format!(
+            "{} - {} ({})  匹配度 {:.0}%{}",
+            album_artist,
+            release.title,
+            if date.is_empty() { "?" } else { &date },
+            score * 100.0,
+            if cover.is_empty() { "" } else { "  有封面" }
+        ),
+        diff: if diff.is_empty() {
+            "标签没有变化".to_owned()
+        } else {
+            diff.join("\n")
+        },
+        cover,
+        tags: Arc::new(tags),
+    }
+}
+
+// 在本地数据包中查找和专辑匹配的发行。先按音轨数和时长筛选，
+// 数据包目录中有录音的 Chromaprint 指纹时再用声纹确认
+fn search(
+    dump: &Path,
+    mut songs: Vec<Song>,
+    events: &ExtEventSink,
+) -> Result<Vector<ReleaseCandidate>, String> {
+    album_order(&mut songs);
+    let path = release_file(dump)
+        .ok_or_else(|| format!("{} 中没有找到 mbdump/release 文件", dump.display()))?;
+    let file = File::open(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let mut found: Vec<(f64, Release, Vec<FlatTrack>)> = vec![];
+    for (i, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.map_err(|e| e.to_string())?;
+        if i % 10000 == 0 {
+            let _ = events.submit_command(MATCH_PROGRESS, i, Target::Auto);
+        }
+        let release: Release = match serde_json::from_str(&line) {
+            Ok(r) => r,
+            Err(_) => continue,
+        };
+        // 先比较音轨数，避免展开每个发行
+        let count: usize = release.media.iter().map(|m| m.tracks.len()).sum();
+        if count != songs.len() {
+            continue;
+        }
+        let tracks = flatten(&release);
+        if let Some(score) = match_durations(&songs, &tracks) {
+            if score >= MIN_DURATION_SCORE {
+                found.push((score, release, tracks));
+            }
+        }
+    }
+    found.sort_by(|a, b| b.0.total_cmp(&a.0));
+    // 声纹比较较慢，只比较时长得分最高的一部分
+    found.truncate(MAX_CANDIDATES * 4);
+    let wanted: HashSet<String> = found
+        .iter()
+        .flat_map(|(_, _, tracks)| tracks.iter().map(|t| t.recording.to_owned()))
+        .filter(|id| !id.is_empty())
+        .collect();
+    let recording_prints = load_recording_prints(dump, &wanted);
+    if !recording_prints.is_empty() {
+        let song_prints = song_prints(&songs);
+        for (score, _, tracks) in found.iter_mut() {
+            if let Some(fp) = fingerprint_score(&songs, tracks, &song_prints, &recording_prints) {
+                *score = *score * 0.4 + fp * 0.6;
+            }
+        }
+        found.sort_by(|a, b| b.0.total_cmp(&a.0));
+    }
+    Ok(found
+        .iter()
+        .take(MAX_CANDIDATES)
+        .map(|(score, release, tracks)| candidate(release, tracks, &songs, *score, dump))
+        .collect())
+}
+
+// 后台查找候选发行，songs 为同一张专辑的所有歌曲
+pub fn search_async(dump: String, songs: Vec<Song>, events: ExtEventSink) {
+    spawn(move || {
+        let result = search(Path::new(&dump), songs, &events);
+        let _ = events.submit_command(MATCH_DONE, result, Target::Auto);
+    });
+}
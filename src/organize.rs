@@ -0,0 +1,332 @@
+use crate::lyrics;
+use crate::Song;
+use druid::im::Vector;
+use druid::{Data, Lens};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// 预览表中的一行，conflict 不为空时不能执行
+#[derive(Clone, Data, Lens, Debug, Default, PartialEq)]
+pub struct RenameRow {
+    pub from: String,
+    pub to: String,
+    pub conflict: String,
+}
+
+impl RenameRow {
+    pub fn unchanged(&self) -> bool {
+        self.from == self.to
+    }
+}
+
+// 文件名中不能使用的字符替换为 _，去掉首尾的空格和点
+pub fn sanitize(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| {
+            if "/\\:*?\"<>|".contains(c) || c.is_control() {
+                '_'
+            } else {
+                c
+            }
+        })
+        .collect();
+    name.trim().trim_matches('.').trim().to_owned()
+}
+
+// "3/12" 这样的音轨号只取前面的数字
+fn number(s: &str) -> Option<u64> {
+    s.split('/').next()?.trim().parse().ok()
+}
+
+fn year(date: &str) -> String {
+    date.chars().take(4).collect()
+}
+
+// 规则中变量对应的值
+fn field(song: &Song, name: &str) -> Option<String> {
+    let value = match name {
+        "title" => song.title.to_owned(),
+        "artist" => song.artist.to_owned(),
+        "album" => song.album.to_owned(),
+        // 没有专辑艺人时使用艺人
+        "albumartist" if song.album_artist.is_empty() => song.artist.to_owned(),
+        "albumartist" => song.album_artist.to_owned(),
+        "year" => year(&song.date),
+        "date" => song.date.to_owned(),
+        "genre" => song.genre.to_owned(),
+        "track" => number(&song.track).map_or("".to_owned(), |n| n.to_string()),
+        "disc" => number(&song.disc).unwrap_or(1).to_string(),
+        "ext" => Path::new(&song.file)
+            .extension()
+            .map_or("".to_owned(), |e| e.to_string_lossy().to_lowercase()),
+        _ => return None,
+    };
+    Some(value)
+}
+
+// 按规则生成相对路径，例如 {artist}/{album}/{track:02} {title}.{ext}。
+// 变量的值中不能出现路径分隔符，规则中的 / 表示目录。
+pub fn render(pattern: &str, song: &Song) -> Result<String, String> {
+    let mut result = String::new();
+    let mut rest = pattern;
+    while let Some(start) = rest.find('{') {
+        result.push_str(&rest[..start]);
+        let end = rest[start..]
+            .find('}')
+            .ok_or_else(|| format!("规则 \"{}\" 中的 {{ 没有对应的 }}", pattern))?
+            + start;
+        let var = &rest[start + 1..end];
+        let (name, width) = match var.split_once(':') {
+            Some((name, width)) => (name, width.parse::<usize>().ok()),
+            None => (var, None),
+        };
+        let value = field(song, &name.to_lowercase())
+            .ok_or_else(|| format!("规则中的变量 {{{}}} 无效", name))?;
+        let value = match width {
+            Some(w) if value.chars().all(|c| c.is_ascii_digit()) && !value.is_empty() => {
+                format!("{:0>w$}", value, w = w)
+            }
+            _ => value,
+        };
+        let value = sanitize(&value);
+        result.push_str(if value.is_empty() { "Unknown" } else { &value });
+        rest = &rest[end + 1..];
+    }
+    result.push_str(rest);
+    // 去掉空的目录名，每一级再处理一次首尾空格和点
+    let parts: Vec<String> = result
+        .split('/')
+        .map(sanitize)
+        .filter(|p| !p.is_empty())
+        .collect();
+    if parts.is_empty() {
+        return Err("规则生成的文件名为空".to_owned());
+    }
+    Ok(parts.join("/"))
+}
+
+// 文件所在的资料库目录，不在资料库中时使用文件所在目录
+fn root_of(file: &str, roots: &Vector<String>) -> PathBuf {
+    roots
+        .iter()
+        .filter(|r| Path::new(file).starts_with(r))
+        .max_by_key(|r| r.len())
+        .map(PathBuf::from)
+        .unwrap_or_else(|| {
+            Path::new(file)
+                .parent()
+                .unwrap_or(Path::new(""))
+                .to_path_buf()
+        })
+}
+
+// 文件相对于所在资料库目录的路径，导出时保持目录结构
+pub fn relative_path(file: &str, roots: &Vector<String>) -> PathBuf {
+    let root = root_of(file, roots);
+    Path::new(file)
+        .strip_prefix(&root)
+        .map(Path::to_path_buf)
+        .unwrap_or_else(|_| PathBuf::from(Path::new(file).file_name().unwrap_or_default()))
+}
+
+// 不区分大小写比较，避免在 macOS 和 Windows 上覆盖文件
+pub fn collision_key(path: &str) -> String {
+    path.to_lowercase()
+}
+
+// 生成预览，检查目标文件已存在和多首歌曲生成相同路径的情况
+pub fn plan(songs: &[Song], pattern: &str, roots: &Vector<String>) -> Vector<RenameRow> {
+    let mut rows: Vec<RenameRow> = songs
+        .iter()
+        .map(|song| match render(pattern, song) {
+            Ok(relative) => RenameRow {
+                from: song.file.to_owned(),
+                to: root_of(&song.file, roots)
+                    .join(relative)
+                    .display()
+                    .to_string(),
+                conflict: "".to_owned(),
+            },
+            Err(e) => RenameRow {
+                from: song.file.to_owned(),
+                to: song.file.to_owned(),
+                conflict: e,
+            },
+        })
+        .collect();
+    let mut targets: HashMap<String, usize> = HashMap::new();
+    for row in rows.iter() {
+        *targets.entry(collision_key(&row.to)).or_insert(0) += 1;
+    }
+    for row in rows.iter_mut().filter(|r| r.conflict.is_empty()) {
+        if targets[&collision_key(&row.to)] > 1 {
+            row.conflict = "多个文件的目标相同".to_owned();
+        } else if !row.unchanged()
+            && collision_key(&row.from) != collision_key(&row.to)
+            && Path::new(&row.to).exists()
+        {
+            row.conflict = "目标文件已存在".to_owned();
+        }
+    }
+    rows.into_iter().collect()
+}
+
+// 移动文件，跨磁盘时复制后删除原文件
+fn move_file(from: &Path, to: &Path) -> Result<(), String> {
+    if let Some(dir) = to.parent() {
+        fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+    }
+    if fs::rename(from, to).is_ok() {
+        return Ok(());
+    }
+    fs::copy(from, to).map_err(|e| e.to_string())?;
+    fs::remove_file(from).map_err(|e| {
+        let _ = fs::remove_file(to);
+        e.to_string()
+    })
+}
+
+// 移动后删除变空的目录，资料库目录本身保留
+fn remove_empty_dirs(from: &Path, roots: &Vector<String>) {
+    let mut dir = from.parent();
+    while let Some(d) = dir {
+        if roots.iter().any(|r| Path::new(r) == d) || fs::remove_dir(d).is_err() {
+            break;
+        }
+        dir = d.parent();
+    }
+}
+
+// 依次移动文件，同名的 .lrc 歌词一起移动。返回成功移动的 (原路径, 新路径) 和错误信息
+pub fn apply(
+    moves: &[(String, String)],
+    roots: &Vector<String>,
+) -> (Vec<(String, String)>, Vec<String>) {
+    let mut done = vec![];
+    let mut errors = vec![];
+    for (from, to) in moves {
+        // 预览之后才出现的同名文件不覆盖
+        if collision_key(from) != collision_key(to) && Path::new(to).exists() {
+            errors.push(format!("{}: 目标文件已存在", to));
+            continue;
+        }
+        if let Err(e) = move_file(Path::new(from), Path::new(to)) {
+            errors.push(format!("{}: {}", from, e));
+            continue;
+        }
+        let lrc = lyrics::sidecar_path(from);
+        if lrc.is_file() {
+            let _ = move_file(&lrc, &lyrics::sidecar_path(to));
+        }
+        remove_empty_dirs(Path::new(from), roots);
+        done.push((from.to_owned(), to.to_owned()));
+    }
+    (done, errors)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn song(file: &str) -> Song {
+        Song {
+            file: file.to_owned(),
+            title: "Title".to_owned(),
+            artist: "Artist".to_owned(),
+            album: "Album".to_owned(),
+            date: "1959-08-17".to_owned(),
+            track: "3/12".to_owned(),
+            ..Song::default()
+        }
+    }
+
+    #[test]
+    fn render_fields() {
+        let s = song("/music/a.FLAC");
+        assert_eq!(
+            render(
+                "{albumartist}/{year} - {album}/{disc}-{track:02} {title}.{ext}",
+                &s
+            ),
+            Ok("Artist/1959 - Album/1-03 Title.flac".to_owned())
+        );
+        assert_eq!(render("{date} {track}", &s), Ok("1959-08-17 3".to_owned()));
+        // 变量名不区分大小写
+        assert_eq!(render("{Title}", &s), Ok("Title".to_owned()));
+    }
+
+    #[test]
+    fn render_sanitizes_values() {
+        let mut s = song("/music/a.flac");
+        s.artist = "AC/DC".to_owned();
+        s.title = "What? <Live>".to_owned();
+        s.album = "...".to_owned();
+        assert_eq!(
+            render("{artist}/{album}/{title}", &s),
+            Ok("AC_DC/Unknown/What_ _Live_".to_owned())
+        );
+    }
+
+    #[test]
+    fn render_empty_values() {
+        let mut s = song("/music/a.flac");
+        s.track = "".to_owned();
+        s.genre = "".to_owned();
+        // 空值用 Unknown 代替，补零只用于数字
+        assert_eq!(
+            render("{genre}/{track:02} {title}", &s),
+            Ok("Unknown/Unknown Title".to_owned())
+        );
+        // 空的目录名被去掉
+        assert_eq!(render("//{title}/", &s), Ok("Title".to_owned()));
+    }
+
+    #[test]
+    fn render_errors() {
+        let s = song("/music/a.flac");
+        assert!(render("{nope}", &s).is_err());
+        assert!(render("{title", &s).is_err());
+        assert!(render("/ /", &s).is_err());
+    }
+
+    #[test]
+    fn plan_targets() {
+        let roots: Vector<String> = vec!["/nonexistent/music".to_owned()].into();
+        let songs = vec![
+            song("/nonexistent/music/x/a.flac"),
+            // 不在资料库中的文件相对于所在目录
+            song("/nonexistent/other/b.mp3"),
+        ];
+        let rows = plan(&songs, "{artist}/{title}.{ext}", &roots);
+        assert_eq!(rows[0].to, "/nonexistent/music/Artist/Title.flac");
+        assert_eq!(rows[1].to, "/nonexistent/other/Artist/Title.mp3");
+        assert!(rows.iter().all(|r| r.conflict.is_empty()));
+    }
+
+    #[test]
+    fn plan_conflicts() {
+        let roots: Vector<String> = vec!["/nonexistent/music".to_owned()].into();
+        let mut other = song("/nonexistent/music/b.flac");
+        other.title = "TITLE".to_owned();
+        let songs = vec![song("/nonexistent/music/a.flac"), other];
+        // 只是大小写不同也算相同的目标
+        let rows = plan(&songs, "{title}.{ext}", &roots);
+        assert!(rows.iter().all(|r| !r.conflict.is_empty()));
+        let rows = plan(&songs, "{nope}", &roots);
+        assert!(rows.iter().all(|r| r.unchanged() && !r.conflict.is_empty()));
+    }
+
+    #[test]
+    fn plan_existing_target() {
+        let dir = std::env::temp_dir().join(format!("flac_music_plan_{}", std::process::id()));
+        fs::create_dir_all(&dir).expect("create test dir");
+        fs::write(dir.join("Title.flac"), b"").expect("write test file");
+        let roots: Vector<String> = vec![dir.display().to_string()].into();
+        let songs = vec![song(&dir.join("a.flac").display().to_string())];
+        let rows = plan(&songs, "{title}.{ext}", &roots);
+        let _ = fs::remove_dir_all(&dir);
+        assert_eq!(rows[0].conflict, "目标文件已存在");
+    }
+}
@@ -0,0 +1,210 @@
+use druid::{ExtEventSink, Selector, Target};
+use rodio::cpal::traits::{DeviceTrait, HostTrait};
+use rodio::cpal::{SampleFormat, SampleRate};
+use rodio::{OutputStream, OutputStreamHandle, SupportedStreamConfig};
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread::{sleep, spawn};
+use std::time::Duration;
+
+// 输出设备断开
+pub const DEVICE_LOST: Selector<String> = Selector::new("flac-music.device-lost");
+
+// 当前打开的输出设备。rodio::OutputStream 不能跨线程传递，
+// 所以放在单独的线程里持有，AudioOutput 被 drop 时线程退出并关闭设备。
+pub struct AudioOutput {
+    // 设备名称，为空表示系统默认设备
+    pub device: String,
+    // 按指定格式打开时的格式，None 为设备默认格式
+    pub format: Option<OutputFormat>,
+    // 实际打开的采样格式
+    pub sample_format: SampleFormat,
+    // 实际输出采样率
+    pub rate: u32,
+    // 实际输出格式，例如 96000Hz 2ch F32
+    description: String,
+    _keep_alive: mpsc::Sender<()>,
+}
+
+// bit-perfect 模式下要求的输出格式
+#[derive(Clone, Debug, PartialEq)]
+pub struct OutputFormat {
+    pub rate: u32,
+    pub channels: u16,
+    pub bits: u16,
+}
+
+impl AudioOutput {
+    pub fn describe(&self) -> String {
+        self.description.to_owned()
+    }
+}
+
+// 列出所有输出设备名称
+pub fn device_names() -> Vec<String> {
+    let host = rodio::cpal::default_host();
+    match host.output_devices() {
+        Ok(devices) => devices.filter_map(|d| d.name().ok()).collect(),
+        Err(e) => {
+            println!("获取输出设备列表失败: {}", e);
+            vec![]
+        }
+    }
+}
+
+fn find_device(name: &str) -> Result<rodio::Device, String> {
+    let host = rodio::cpal::default_host();
+    if name.is_empty() {
+        return host
+            .default_output_device()
+            .ok_or_else(|| "没有可用的音频输出设备".to_owned());
+    }
+    host.output_devices()
+        .map_err(|e| e.to_string())?
+        .find(|d| d.name().map_or(false, |n| n == name))
+        .ok_or_else(|| format!("找不到输出设备 {}", name))
+}
+
+// 和源位深对应的输出采样格式。
+// 超过 16 位的源使用 F32，24 位整数在 F32 中可以无损表示。
+pub fn sample_format_for(bits: u16) -> SampleFormat {
+    if bits > 16 {
+        SampleFormat::F32
+    } else {
+        SampleFormat::I16
+    }
+}
+
+// 在设备支持的格式中查找和源格式一致的配置，不支持时返回错误，不退回其他采样格式
+fn find_config(
+    device: &rodio::Device,
+    format: &OutputFormat,
+) -> Result<SupportedStreamConfig, String> {
+    let preferred = sample_format_for(format.bits);
+    device
+        .supported_output_configs()
+        .map_err(|e| e.to_string())?
+        .find(|r| {
+            r.channels() == format.channels
+                && r.sample_format() == preferred
+                && r.min_sample_rate().0 <= format.rate
+                && format.rate <= r.max_sample_rate().0
+        })
+        .map(|r| r.with_sample_rate(SampleRate(format.rate)))
+        .ok_or_else(|| {
+            format!(
+                "设备不支持 {}Hz {}ch {:?} 输出",
+                format.rate, format.channels, preferred
+            )
+        })
+}
+
+// 打开输出设备，返回用于创建 Sink 的 handle
+pub fn open(name: &str) -> Result<(AudioOutput, OutputStreamHandle), String> {
+    open_config(name, None)
+}
+
+// 按指定格式打开输出设备，format 为 None 时使用设备默认格式
+pub fn open_config(
+    name: &str,
+    format: Option<&OutputFormat>,
+) -> Result<(AudioOutput, OutputStreamHandle), String> {
+    let (keep_tx, keep_rx) = mpsc::channel::<()>();
+    let (handle_tx, handle_rx) = mpsc::channel();
+    let device_name = name.to_owned();
+    let wanted = format.cloned();
+    spawn(move || {
+        let opened = find_device(&device_name).and_then(|device| {
+            let config = match &wanted {
+                Some(f) => find_config(&device, f)?,
+                None => device.default_output_config().map_err(|e| e.to_string())?,
+            };
+            let rate = config.sample_rate().0;
+            let sample_format = config.sample_format();
+            let description = format!(
+                "{}Hz {}ch {:?}",
+                config.sample_rate().0,
+                config.channels(),
+                config.sample_format()
+            );
+            OutputStream::try_from_device_config(&device, config)
+                .map(|(stream, handle)| (stream, handle, rate, sample_format, description))
+                .map_err(|e| e.to_string())
+        });
+        match opened {
+            Ok((stream, handle, rate, sample_format, description)) => {
+                let _ = handle_tx.send(Ok((handle, rate, sample_format, description)));
+                // 一直等到 AudioOutput 被 drop
+                let _ = keep_rx.recv();
+                drop(stream);
+            }
+            Err(e) => {
+                let _ = handle_tx.send(Err(e));
+            }
+        }
+    });
+    let (handle, rate, sample_format, description) = handle_rx
+        .recv()
+        .map_err(|e| e.to_string())
+        .and_then(|r| r)?;
+    Ok((
+        AudioOutput {
+            device: name.to_owned(),
+            format: format.cloned(),
+            sample_format,
+            rate,
+            description,
+            _keep_alive: keep_tx,
+        },
+        handle,
+    ))
+}
+
+// 打开指定设备，失败时退回默认设备
+pub fn open_or_default(name: &str) -> Result<(AudioOutput, OutputStreamHandle), String> {
+    match open(name) {
+        Ok(opened) => Ok(opened),
+        Err(e) if !name.is_empty() => {
+            println!("{}，使用默认设备", e);
+            open("")
+        }
+        Err(e) => Err(e),
+    }
+}
+
+// 用当前设备创建 Sink，没有设备时创建一个不输出的 Sink
+pub fn new_sink(stream: &Mutex<Option<OutputStreamHandle>>) -> rodio::Sink {
+    match stream.lock().unwrap().as_ref() {
+        Some(handle) => rodio::Sink::try_new(handle).unwrap_or_else(|_| rodio::Sink::new_idle().0),
+        None => rodio::Sink::new_idle().0,
+    }
+}
+
+// 后台检查当前设备是否还存在，设备拔出时通知界面
+pub fn watch(output: Arc<Mutex<Option<AudioOutput>>>, sink: ExtEventSink) {
+    spawn(move || {
+        let mut lost = false;
+        loop {
+            sleep(Duration::from_secs(2));
+            let device = match output.lock().unwrap().as_ref() {
+                Some(o) => o.device.to_owned(),
+                None => continue,
+            };
+            let exists = find_device(&device).is_ok();
+            if !exists && !lost {
+                let name = if device.is_empty() {
+                    "默认设备".to_owned()
+                } else {
+                    device
+                };
+                if sink
+                    .submit_command(DEVICE_LOST, name, Target::Auto)
+                    .is_err()
+                {
+                    break;
+                }
+            }
+            lost = !exists;
+        }
+    });
+}
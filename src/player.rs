@@ -0,0 +1,392 @@
+use crate::ffmpeg_source::FfmpegSource;
+use crate::output::{self, AudioOutput, OutputFormat};
+use crate::queue::PlayQueue;
+use crate::radio;
+use crate::resample::{ResampleQuality, Resampler};
+use crate::{Modes, Song, Status};
+use druid::{ExtEventSink, Selector, Target};
+use ffmpeg_next as ffmpeg;
+use rodio::{OutputStreamHandle, Sample, Source};
+use std::collections::HashMap;
+use std::io::BufReader;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{sleep, spawn};
+use std::time::Duration;
+
+// 歌曲无法播放，(文件, 原因)
+pub const TRACK_BROKEN: Selector<(String, String)> = Selector::new("flac-music.track-broken");
+
+// 记录播放进度的 Source 包装，进度以毫秒写入 position，界面和会话保存从这里读取。
+pub struct Tracked<S> {
+    inner: S,
+    position: Arc<AtomicU64>,
+    start_ms: u64,
+    samples: u64,
+}
+
+impl<S> Tracked<S>
+where
+    S: Source,
+    S::Item: Sample,
+{
+    pub fn new(inner: S, position: Arc<AtomicU64>, start: Duration) -> Tracked<S> {
+        let start_ms = start.as_millis() as u64;
+        position.store(start_ms, Ordering::Relaxed);
+        Tracked {
+            inner,
+            position,
+            start_ms,
+            samples: 0,
+        }
+    }
+}
+
+impl<S> Iterator for Tracked<S>
+where
+    S: Source,
+    S::Item: Sample,
+{
+    type Item = S::Item;
+
+    fn next(&mut self) -> Option<S::Item> {
+        let sample = self.inner.next()?;
+        self.samples += 1;
+        // 每 4096 个采样更新一次，避免频繁写原子变量
+        if self.samples % 4096 == 0 {
+            let per_sec = self.inner.sample_rate() as u64 * self.inner.channels() as u64;
+            if per_sec > 0 {
+                self.position.store(
+                    self.start_ms + self.samples * 1000 / per_sec,
+                    Ordering::Relaxed,
+                );
+            }
+        }
+        Some(sample)
+    }
+}
+
+impl<S> Source for Tracked<S>
+where
+    S: Source,
+    S::Item: Sample,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        self.inner.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.inner.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.inner.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.inner.total_duration()
+    }
+}
+
+// 源文件的音频格式
+#[derive(Clone, Debug, PartialEq)]
+pub struct SourceFormat {
+    pub codec: String,
+    pub rate: u32,
+    pub channels: u16,
+    // 位深，未知时为 0
+    pub bits: u16,
+}
+
+impl SourceFormat {
+    pub fn describe(&self) -> String {
+        let bits = if self.bits > 0 {
+            format!("{}bit/", self.bits)
+        } else {
+            "".to_owned()
+        };
+        format!(
+            "{} {}{}Hz {}ch",
+            self.codec.to_uppercase(),
+            bits,
+            self.rate,
+            self.channels
+        )
+    }
+}
+
+// 用 ffmpeg 读取文件中第一个音频流的格式
+pub fn probe_format(f: &str) -> Option<SourceFormat> {
+    ffmpeg::init().ok()?;
+    let context = ffmpeg::format::input(&Path::new(f)).ok()?;
+    let stream = context.streams().best(ffmpeg::media::Type::Audio)?;
+    let codec = ffmpeg::codec::context::Context::from_parameters(stream.parameters()).ok()?;
+    let codec_name = codec.id().name().to_owned();
+    let decoder = codec.decoder().audio().ok()?;
+    let raw_bits = unsafe {
+        let par = stream.parameters().as_ptr();
+        if (*par).bits_per_raw_sample > 0 {
+            (*par).bits_per_raw_sample
+        } else {
+            (*par).bits_per_coded_sample
+        }
+    };
+    // DSD 记为 1 位；有损格式没有固定位深，按解码后的采样格式计算
+    let bits = if codec_name.starts_with("dsd_") {
+        1
+    } else if raw_bits > 0 {
+        raw_bits as u16
+    } else {
+        (decoder.format().bytes() * 8) as u16
+    };
+    Some(SourceFormat {
+        codec: codec_name,
+        rate: decoder.rate(),
+        channels: decoder.channels(),
+        bits,
+    })
+}
+
+type BoxedSource = Box<dyn Source<Item = f32> + Send>;
+
+fn open_rodio(f: &str) -> Result<BoxedSource, String> {
+    let file = std::fs::File::open(f).map_err(|e| e.to_string())?;
+    let decoder = rodio::Decoder::new(BufReader::new(file)).map_err(|e| e.to_string())?;
+    Ok(Box::new(decoder.convert_samples::<f32>()))
+}
+
+fn open_ffmpeg(f: &str) -> Result<BoxedSource, String> {
+    let source = FfmpegSource::new(f).map_err(|e| e.to_string())?;
+    Ok(Box::new(source))
+}
+
+// 打开音频文件，先用 rodio 解码，不支持的格式退回 ffmpeg
+pub fn open_source(f: &str, prefer_ffmpeg: bool) -> Result<BoxedSource, String> {
+    if prefer_ffmpeg {
+        return open_ffmpeg(f).or_else(|e| {
+            println!("ffmpeg 解码失败 {}: {}", f, e);
+            open_rodio(f)
+        });
+    }
+    open_rodio(f).or_else(|e| {
+        println!("rodio 不支持 {}: {}，使用 ffmpeg 解码", f, e);
+        open_ffmpeg(f)
+    })
+}
+
+// 播放设置，界面修改后下一首歌曲生效
+#[derive(Clone, Debug, Default)]
+pub struct PlaybackSettings {
+    // 按源文件原始采样率输出，不做音量调节和重采样
+    pub bit_perfect: bool,
+    // 源文件和输出设备采样率不一致时使用的重采样器
+    pub resample: ResampleQuality,
+}
+
+// 播放线程需要的共享状态
+#[derive(Clone)]
+pub struct Player {
+    pub stream: Arc<Mutex<Option<OutputStreamHandle>>>,
+    pub output: Arc<Mutex<Option<AudioOutput>>>,
+    pub sink: Arc<Mutex<rodio::Sink>>,
+    pub queue: Arc<Mutex<PlayQueue>>,
+    pub current_song: Arc<Mutex<Song>>,
+    pub status: Arc<Mutex<Status>>,
+    pub position: Arc<AtomicU64>,
+    pub play_mode: Modes,
+    pub settings: Arc<Mutex<PlaybackSettings>>,
+    // 源格式和实际输出格式，界面显示用
+    pub format_info: Arc<Mutex<String>>,
+    // 通知界面歌曲无法播放
+    pub events: Option<ExtEventSink>,
+    // 从头播放时需要从上次位置继续的文件(毫秒)，例如播客单集
+    pub bookmarks: Arc<Mutex<HashMap<String, u64>>>,
+}
+
+impl Player {
+    // 加入歌曲到音轨sink
+    // start 为开始播放的位置，position 记录播放进度
+    pub fn add_song(&self, f: &str, start: Duration) -> Result<(), String> {
+        let settings = self.settings.lock().unwrap().to_owned();
+        let (source, start): (BoxedSource, Duration) = if radio::is_stream(f) {
+            // 网络电台连接后才知道格式，直播流也不能从中间开始
+            let (source, source_format) = radio::open(f, self.events.clone())?;
+            self.prepare_output(Some(&source_format), settings.bit_perfect);
+            (Box::new(source), Duration::ZERO)
+        } else {
+            if !Path::new(f).is_file() {
+                return Err("文件不存在".to_owned());
+            }
+            let source_format = probe_format(f);
+            self.prepare_output(source_format.as_ref(), settings.bit_perfect);
+            // rodio 只能输出 16 位采样，bit-perfect 播放高位深文件时直接用 ffmpeg 解码
+            let prefer_ffmpeg =
+                settings.bit_perfect && source_format.as_ref().map_or(false, |s| s.bits > 16);
+            (open_source(f, prefer_ffmpeg)?, start)
+        };
+        let source = source.skip_duration(start);
+        let output_rate = self.output.lock().unwrap().as_ref().map(|o| o.rate);
+        let position = Arc::clone(&self.position);
+        match output_rate {
+            // 采样率不一致时使用加窗 sinc 重采样，避免 rodio 线性插值的失真
+            Some(rate)
+                if rate != source.sample_rate()
+                    && settings.resample != ResampleQuality::Off
+                    && !settings.bit_perfect =>
+            {
+                self.format_info.lock().unwrap().push_str(&format!(
+                    " [{:?} 重采样 {}Hz -> {}Hz]",
+                    settings.resample,
+                    source.sample_rate(),
+                    rate
+                ));
+                let source = Resampler::new(source, rate, settings.resample);
+                self.sink
+                    .lock()
+                    .unwrap()
+                    .append(Tracked::new(source, position, start));
+            }
+            _ => {
+                self.sink
+                    .lock()
+                    .unwrap()
+                    .append(Tracked::new(source, position, start));
+            }
+        }
+        Ok(())
+    }
+
+    // 播放队列中取出的歌曲，无法播放时标记并通知界面，播放线程会继续取下一首
+    fn play_song(&self, mut song: Song, start: Duration) {
+        let start = if start.is_zero() {
+            let bookmark = self.bookmarks.lock().unwrap().get(&song.file).copied();
+            bookmark.map_or(start, Duration::from_millis)
+        } else {
+            start
+        };
+        if let Err(e) = self.add_song(&song.file, start) {
+            println!("无法播放 {}: {}", song.file, e);
+            self.queue.lock().unwrap().mark_broken(&song.file, &e);
+            if let Some(events) = &self.events {
+                let _ = events.submit_command(
+                    TRACK_BROKEN,
+                    (song.file.to_owned(), e.to_owned()),
+                    Target::Auto,
+                );
+            }
+            song.broken = e;
+            song.playing = false;
+        } else {
+            song.playing = true;
+        }
+        *self.current_song.lock().expect("lock error") = song;
+    }
+
+    // bit-perfect 模式下，源格式和设备当前格式不一致时按源格式重新打开设备；
+    // 关闭该模式后恢复设备默认格式。
+    fn prepare_output(&self, source: Option<&SourceFormat>, bit_perfect: bool) {
+        let mut output = self.output.lock().unwrap();
+        let (device, current) = match output.as_ref() {
+            Some(o) => (o.device.to_owned(), o.format.to_owned()),
+            None => return,
+        };
+        let wanted = match (bit_perfect, source) {
+            // DSD 由 ffmpeg 转成 32 位浮点 PCM 输出
+            (true, Some(s)) => Some(OutputFormat {
+                rate: s.rate,
+                channels: s.channels,
+                bits: if s.bits == 1 { 32 } else { s.bits },
+            }),
+            _ => None,
+        };
+        if wanted != current {
+            match output::open_config(&device, wanted.as_ref()) {
+                Ok((new_output, handle)) => {
+                    *self.stream.lock().unwrap() = Some(handle);
+                    let mut sink = self.sink.lock().unwrap();
+                    let volume = if bit_perfect { 1.0 } else { sink.volume() };
+                    sink.stop();
+                    *sink = output::new_sink(&self.stream);
+                    sink.set_volume(volume);
+                    *output = Some(new_output);
+                }
+                Err(e) => println!("无法按源格式打开输出设备: {}", e),
+            }
+        } else if bit_perfect {
+            self.sink.lock().unwrap().set_volume(1.0);
+        }
+        let output_desc = output.as_ref().map_or("".to_owned(), |o| o.describe());
+        let source_desc = source.map_or("".to_owned(), |s| s.describe());
+        // 只有设备实际按源格式打开时才是 bit-perfect
+        let exact = match (wanted.as_ref(), output.as_ref()) {
+            (Some(w), Some(o)) => {
+                o.format.as_ref() == Some(w) && o.sample_format == output::sample_format_for(w.bits)
+            }
+            _ => false,
+        };
+        let mode = if bit_perfect && exact {
+            " (bit-perfect)"
+        } else {
+            ""
+        };
+        *self.format_info.lock().unwrap() = format!("{} -> {}{}", source_desc, output_desc, mode);
+    }
+
+    // 播放线程：每次都从播放队列中取下一首歌，队列的修改会立即生效。
+    // 上一首，下一首，切歌操作，以status 的状态切换来控制。
+    // resume 不为空时，先从该位置继续播放队列中的当前歌曲
+    pub fn start(self, resume: Option<Duration>) {
+        spawn(move || {
+            if let Some(start) = resume {
+                let song = self.queue.lock().unwrap().current_song();
+                if let Some(cur) = song {
+                    self.play_song(cur, start);
+                }
+            }
+            self.run()
+        });
+    }
+
+    fn run(&self) {
+        loop {
+            let status = self.status.lock().unwrap().to_owned();
+            match status {
+                Status::Stop => break,
+                Status::Suspend => (),
+                Status::Next | Status::Prev => {
+                    let song = if status == Status::Next {
+                        self.queue.lock().unwrap().next(&Modes::Order)
+                    } else {
+                        self.queue.lock().unwrap().prev()
+                    };
+                    // 已经是第一首或最后一首时，继续播放当前歌曲
+                    if let Some(cur) = song {
+                        // rodio::sink stop后就无法重新播放，只能重新初始化rodio::Sink::try_new(&stream)。
+                        let volume = self.sink.lock().unwrap().volume();
+                        self.sink.lock().unwrap().stop();
+                        *self.sink.lock().unwrap() = output::new_sink(&self.stream);
+                        self.sink.lock().unwrap().set_volume(volume);
+                        self.play_song(cur, Duration::ZERO);
+                    }
+                    *self.status.lock().unwrap() = Status::Play;
+                }
+                Status::Play => {
+                    // sink.len最大为1，播放完一首，当sink为空时，再加入下一首歌曲。
+                    if self.sink.lock().unwrap().empty() {
+                        let song = self.queue.lock().unwrap().next(&self.play_mode);
+                        match song {
+                            Some(cur) => self.play_song(cur, Duration::ZERO),
+                            None => {
+                                self.current_song.lock().unwrap().playing = false;
+                                *self.status.lock().unwrap() = Status::Stop;
+                                break;
+                            }
+                        }
+                    }
+                }
+            }
+            sleep(Duration::from_millis(200));
+        }
+    }
+}
@@ -0,0 +1,457 @@
+use crate::radio;
+use druid::im::Vector;
+use druid::{Data, ExtEventSink, Lens, Selector, Target};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::thread::spawn;
+
+// 订阅或刷新完成，Ok 为解析后的节目
+pub const FEED_LOADED: Selector<Result<Podcast, String>> = Selector::new("flac-music.feed-loaded");
+// 下载完成，(订阅地址, 单集 id, 本地文件或错误)
+pub const EPISODE_DOWNLOADED: Selector<(String, String, Result<String, String>)> =
+    Selector::new("flac-music.episode-downloaded");
+
+// 播放到距离结尾多少毫秒内算作已播放
+pub const PLAYED_MARGIN_MS: u64 = 10_000;
+
+// 播客单集
+#[derive(Clone, Data, Lens, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Episode {
+    // 所属订阅地址
+    pub podcast: String,
+    pub id: String,
+    pub title: String,
+    // 发布时间，例如 2023-05-01 08:00
+    pub published: String,
+    // 音频地址
+    pub enclosure: String,
+    // 下载后的本地文件，未下载时为空
+    pub file: String,
+    // 时长(秒)，未知时为 0
+    pub duration: f64,
+    // 上次播放到的位置(毫秒)
+    pub position_ms: u64,
+    pub played: bool,
+    #[serde(skip)]
+    pub downloading: bool,
+}
+
+// 订阅的播客
+#[derive(Clone, Data, Lens, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Podcast {
+    pub title: String,
+    // 订阅地址，可以是 http(s) 地址或本地文件
+    pub url: String,
+    #[serde(with = "vector_serde")]
+    pub episodes: Vector<Episode>,
+}
+
+mod vector_serde {
+    use super::Episode;
+    use druid::im::Vector;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(v: &Vector<Episode>, s: S) -> Result<S::Ok, S::Error> {
+        s.collect_seq(v.iter())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Vector<Episode>, D::Error> {
+        Vec::<Episode>::deserialize(d).map(Vector::from)
+    }
+}
+
+impl Podcast {
+    // 刷新后保留已有单集的下载和播放状态，已下载但不在订阅中的旧单集也保留
+    pub fn merge(&mut self, fresh: Podcast) {
+        let mut episodes: Vector<Episode> = fresh
+            .episodes
+            .into_iter()
+            .map(|mut e| {
+                if let Some(old) = self.episodes.iter().find(|o| o.id == e.id) {
+                    e.file = old.file.to_owned();
+                    e.position_ms = old.position_ms;
+                    e.played = old.played;
+                    e.downloading = old.downloading;
+                }
+                e
+            })
+            .collect();
+        for old in self.episodes.iter() {
+            if !old.file.is_empty() && !episodes.iter().any(|e| e.id == old.id) {
+                episodes.push_back(old.to_owned());
+            }
+        }
+        self.title = fresh.title;
+        self.episodes = episodes;
+    }
+}
+
+// 保存在配置目录下的播客订阅和播放状态
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct PodcastStore {
+    // 播放完的单集自动删除下载文件
+    pub auto_delete: bool,
+    pub podcasts: Vec<Podcast>,
+}
+
+fn store_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|d| d.join("flac_music").join("podcasts.toml"))
+}
+
+// 下载目录
+fn download_dir() -> PathBuf {
+    dirs::data_dir()
+        .or_else(dirs::home_dir)
+        .unwrap_or_default()
+        .join("flac_music")
+        .join("podcasts")
+}
+
+pub fn load() -> PodcastStore {
+    let path = match store_path() {
+        Some(p) => p,
+        None => return PodcastStore::default(),
+    };
+    match fs::read_to_string(&path) {
+        Ok(text) => toml::from_str(&text).unwrap_or_else(|e| {
+            println!("读取播客订阅失败 {}: {}", path.display(), e);
+            PodcastStore::default()
+        }),
+        Err(_) => PodcastStore::default(),
+    }
+}
+
+// 和会话文件一样先写临时文件再改名
+pub fn save(store: &PodcastStore) {
+    let path = match store_path() {
+        Some(p) => p,
+        None => return,
+    };
+    let text = match toml::to_string(store) {
+        Ok(t) => t,
+        Err(e) => {
+            println!("保存播客订阅失败: {}", e);
+            return;
+        }
+    };
+    if let Some(dir) = path.parent() {
+        let _ = fs::create_dir_all(dir);
+    }
+    let tmp = path.with_extension("toml.tmp");
+    if let Err(e) = fs::write(&tmp, text).and_then(|_| fs::rename(&tmp, &path)) {
+        println!("保存播客订阅失败 {}: {}", path.display(), e);
+    }
+}
+
+// 本地文件地址，支持 file:// 前缀
+fn local_path(url: &str) -> &str {
+    url.strip_prefix("file://").unwrap_or(url)
+}
+
+fn read_url(url: &str) -> Result<Vec<u8>, String> {
+    if radio::is_stream(url) {
+        let mut bytes = vec![];
+        ureq::get(url)
+            .call()
+            .map_err(|e| e.to_string())?
+            .into_reader()
+            .read_to_end(&mut bytes)
+            .map_err(|e| e.to_string())?;
+        Ok(bytes)
+    } else {
+        fs::read(local_path(url)).map_err(|e| e.to_string())
+    }
+}
+
+// 解析 RSS/Atom 订阅
+pub fn fetch(url: &str) -> Result<Podcast, String> {
+    let bytes = read_url(url)?;
+    let feed = feed_rs::parser::parse(&bytes[..]).map_err(|e| e.to_string())?;
+    // 本地订阅中的相对路径按订阅文件所在目录解析
+    let base = if radio::is_stream(url) {
+        None
+    } else {
+        Path::new(local_path(url)).parent().map(|p| p.to_path_buf())
+    };
+    let mut episodes: Vec<Episode> = feed
+        .entries
+        .iter()
+        .filter_map(|entry| {
+            let content = entry.media.iter().flat_map(|m| m.content.iter()).next();
+            let enclosure = content
+                .and_then(|c| c.url.as_ref().map(|u| u.to_string()))
+                .or_else(|| {
+                    entry
+                        .links
+                        .iter()
+                        .find(|l| l.rel.as_deref() == Some("enclosure"))
+                        .map(|l| l.href.to_owned())
+                })?;
+            let enclosure = match &base {
+                Some(dir)
+                    if !radio::is_stream(&enclosure)
+                        && Path::new(local_path(&enclosure)).is_relative() =>
+                {
+                    dir.join(local_path(&enclosure)).display().to_string()
+                }
+                _ => enclosure,
+            };
+            let duration = content
+                .and_then(|c| c.duration)
+                .or_else(|| entry.media.iter().find_map(|m| m.duration))
+                .map_or(0.0, |d| d.as_secs_f64());
+            Some(Episode {
+                podcast: url.to_owned(),
+                id: if entry.id.is_empty() {
+                    enclosure.to_owned()
+                } else {
+                    entry.id.to_owned()
+                },
+                title: entry
+                    .title
+                    .as_ref()
+                    .map_or("".to_owned(), |t| t.content.to_owned()),
+                published: entry
+                    .published
+                    .or(entry.updated)
+                    .map_or("".to_owned(), |d| d.format("%Y-%m-%d %H:%M").to_string()),
+                enclosure,
+                duration,
+                ..Episode::default()
+            })
+        })
+        .collect();
+    // 有的订阅会重复列出同一集
+    let mut seen = HashSet::new();
+    episodes.retain(|e| seen.insert(e.id.to_owned()));
+    // 新发布的在前
+    episodes.sort_by(|a, b| b.published.cmp(&a.published));
+    Ok(Podcast {
+        title: feed.title.map_or(url.to_owned(), |t| t.content.to_owned()),
+        url: url.to_owned(),
+        episodes: episodes.into_iter().collect(),
+    })
+}
+
+// 后台读取订阅，完成后通知界面
+pub fn fetch_async(url: String, events: ExtEventSink) {
+    spawn(move || {
+        let _ = events.submit_command(FEED_LOADED, fetch(&url), Target::Auto);
+    });
+}
+
+// 文件名中不能使用的字符替换为 _
+fn sanitize(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| {
+            if "/\\:*?\"<>|".contains(c) || c.is_control() {
+                '_'
+            } else {
+                c
+            }
+        })
+        .collect();
+    let name = name.trim().trim_matches('.').to_owned();
+    if name.is_empty() {
+        "untitled".to_owned()
+    } else {
+        name
+    }
+}
+
+fn download(podcast_title: &str, episode: &Episode) -> Result<String, String> {
+    let dir = download_dir().join(sanitize(podcast_title));
+    fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+    let source = local_path(&episode.enclosure);
+    // 扩展名取自地址，去掉查询参数
+    let ext = Path::new(source.split(['?', '#']).next().unwrap_or(""))
+        .extension()
+        .map_or("mp3".to_owned(), |e| e.to_string_lossy().to_string());
+    // 标题相同的单集(例如预告和重播)用 id 的哈希区分，避免互相覆盖
+    let hash = format!("{:x}", md5::compute(&episode.id));
+    let file = dir.join(format!(
+        "{} [{}].{}",
+        sanitize(&episode.title),
+        &hash[..8],
+        ext
+    ));
+    let tmp = file.with_extension(format!("{}.part", ext));
+    let result = if radio::is_stream(&episode.enclosure) {
+        ureq::get(&episode.enclosure)
+            .call()
+            .map_err(|e| e.to_string())
+            .and_then(|response| {
+                let mut out = fs::File::create(&tmp).map_err(|e| e.to_string())?;
+                std::io::copy(&mut response.into_reader(), &mut out).map_err(|e| e.to_string())
+            })
+    } else {
+        fs::copy(source, &tmp).map_err(|e| e.to_string())
+    };
+    match result.and_then(|_| fs::rename(&tmp, &file).map_err(|e| e.to_string())) {
+        Ok(()) => Ok(file.display().to_string()),
+        Err(e) => {
+            let _ = fs::remove_file(&tmp);
+            Err(e)
+        }
+    }
+}
+
+// 后台下载单集音频，完成后通知界面
+pub fn download_async(podcast_title: String, episode: Episode, events: ExtEventSink) {
+    spawn(move || {
+        let result = download(&podcast_title, &episode);
+        let _ = events.submit_command(
+            EPISODE_DOWNLOADED,
+            (episode.podcast, episode.id, result),
+            Target::Auto,
+        );
+    });
+}
+
+// 删除已下载的文件
+pub fn delete_download(episode: &mut Episode) {
+    if episode.file.is_empty() {
+        return;
+    }
+    if let Err(e) = fs::remove_file(&episode.file) {
+        println!("删除 {} 失败: {}", episode.file, e);
+    }
+    episode.file.clear();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FEED: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rss version="2.0">
+  <channel>
+    <title>测试播客</title>
+    <item>
+      <title>第一集</title>
+      <guid>ep-1</guid>
+      <pubDate>Mon, 01 May 2023 08:00:00 +0000</pubDate>
+      <enclosure url="file:///music/podcasts/ep1.mp3" type="audio/mpeg" length="1000"/>
+    </item>
+    <item>
+      <title>第二集</title>
+      <guid>ep-2</guid>
+      <pubDate>Mon, 08 May 2023 08:00:00 +0000</pubDate>
+      <enclosure url="https://example.com/ep2.mp3" type="audio/mpeg" length="1000"/>
+    </item>
+    <item>
+      <title>第二集(重复)</title>
+      <guid>ep-2</guid>
+      <pubDate>Mon, 08 May 2023 08:00:00 +0000</pubDate>
+      <enclosure url="https://example.com/ep2.mp3" type="audio/mpeg" length="1000"/>
+    </item>
+    <item>
+      <title>没有音频</title>
+      <guid>text-only</guid>
+    </item>
+  </channel>
+</rss>
+"#;
+
+    fn fetch_fixture(name: &str) -> Result<Podcast, String> {
+        let dir = std::env::temp_dir().join(format!("flac_music_{}_{}", name, std::process::id()));
+        fs::create_dir_all(&dir).expect("create test dir");
+        let path = dir.join("feed.xml");
+        fs::write(&path, FEED).expect("write test feed");
+        let url = path.display().to_string();
+        let result = fetch(&url);
+        let _ = fs::remove_dir_all(&dir);
+        let podcast = result?;
+        assert_eq!(podcast.url, url);
+        assert!(podcast.episodes.iter().all(|e| e.podcast == url));
+        Ok(podcast)
+    }
+
+    #[test]
+    fn parse_local_feed() {
+        let podcast = fetch_fixture("feed").expect("parse feed");
+        assert_eq!(podcast.title, "测试播客");
+        let summary: Vec<(&str, &str, &str, &str)> = podcast
+            .episodes
+            .iter()
+            .map(|e| {
+                (
+                    e.id.as_str(),
+                    e.title.as_str(),
+                    e.published.as_str(),
+                    e.enclosure.as_str(),
+                )
+            })
+            .collect();
+        // 新的在前，重复的只保留一次，没有音频的跳过
+        assert_eq!(
+            summary,
+            vec![
+                (
+                    "ep-2",
+                    "第二集",
+                    "2023-05-08 08:00",
+                    "https://example.com/ep2.mp3"
+                ),
+                (
+                    "ep-1",
+                    "第一集",
+                    "2023-05-01 08:00",
+                    "file:///music/podcasts/ep1.mp3"
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn missing_feed() {
+        assert!(fetch("/nonexistent/flac_music/feed.xml").is_err());
+    }
+
+    #[test]
+    fn merge_keeps_state() {
+        let old = |id: &str, file: &str| Episode {
+            podcast: "feed".to_owned(),
+            id: id.to_owned(),
+            title: format!("旧 {}", id),
+            file: file.to_owned(),
+            position_ms: 5000,
+            played: true,
+            ..Episode::default()
+        };
+        let mut podcast = Podcast {
+            title: "旧标题".to_owned(),
+            url: "feed".to_owned(),
+            episodes: vec![
+                old("ep-1", "/downloads/ep1.mp3"),
+                // 已下载但订阅中已经没有的保留
+                old("ep-old", "/downloads/old.mp3"),
+                // 没有下载并且订阅中已经没有的删除
+                old("ep-gone", ""),
+            ]
+            .into(),
+        };
+        podcast.merge(fetch_fixture("merge").expect("parse feed"));
+        assert_eq!(podcast.title, "测试播客");
+        let ids: Vec<&str> = podcast.episodes.iter().map(|e| e.id.as_str()).collect();
+        assert_eq!(ids, vec!["ep-2", "ep-1", "ep-old"]);
+        let ep1 = &podcast.episodes[1];
+        assert_eq!(ep1.title, "第一集");
+        assert_eq!(ep1.file, "/downloads/ep1.mp3");
+        assert_eq!(ep1.position_ms, 5000);
+        assert!(ep1.played);
+        let ep2 = &podcast.episodes[0];
+        assert!(ep2.file.is_empty() && !ep2.played && ep2.position_ms == 0);
+
+        // 再次刷新不会重复添加
+        podcast.merge(fetch_fixture("merge2").expect("parse feed"));
+        assert_eq!(podcast.episodes.len(), 3);
+    }
+}
@@ -0,0 +1,325 @@
+use crate::{Modes, Song};
+use druid::im::Vector;
+use druid::{Data, Lens};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// 播放队列，播放线程每次都从这里取下一首歌，和列表视图互不影响。
+#[derive(Default, Debug)]
+pub struct PlayQueue {
+    songs: Vec<Song>,
+    current: Option<usize>,
+}
+
+// 队列面板显示用的条目
+#[derive(Clone, Data, Lens, Default, Debug)]
+pub struct QueueItem {
+    pub index: usize,
+    pub current: bool,
+    pub song: Song,
+}
+
+impl PlayQueue {
+    pub fn is_empty(&self) -> bool {
+        self.songs.is_empty()
+    }
+
+    pub fn current(&self) -> Option<usize> {
+        self.current
+    }
+
+    pub fn current_song(&self) -> Option<Song> {
+        self.current.and_then(|i| self.songs.get(i).cloned())
+    }
+
+    // 用新的歌曲列表替换队列
+    pub fn replace(&mut self, songs: Vec<Song>) {
+        self.songs = songs;
+        self.current = None;
+    }
+
+    // 恢复上次保存的队列和当前位置
+    pub fn restore(&mut self, songs: Vec<Song>, current: Option<usize>) {
+        self.current = current.filter(|i| *i < songs.len());
+        self.songs = songs;
+    }
+
+    // 队列中的歌曲文件，用于保存会话
+    pub fn files(&self) -> Vec<String> {
+        self.songs.iter().map(|s| s.file.to_owned()).collect()
+    }
+
+    // 加到队列末尾
+    pub fn append(&mut self, song: Song) {
+        self.songs.push(song);
+    }
+
+    // 插入到当前歌曲之后，下一首播放
+    pub fn play_next(&mut self, song: Song) {
+        let at = self.current.map_or(0, |i| i + 1).min(self.songs.len());
+        self.songs.insert(at, song);
+    }
+
+    pub fn remove(&mut self, index: usize) {
+        if index >= self.songs.len() {
+            return;
+        }
+        self.songs.remove(index);
+        self.current = match self.current {
+            Some(i) if i == index => {
+                // 删除正在播放的歌曲，下一首从原位置继续
+                if i == 0 {
+                    None
+                } else {
+                    Some(i - 1)
+                }
+            }
+            Some(i) if i > index => Some(i - 1),
+            other => other,
+        };
+    }
+
+    // 调整顺序，把 from 位置的歌曲移动到 to 位置
+    pub fn move_item(&mut self, from: usize, to: usize) {
+        if from >= self.songs.len() || to >= self.songs.len() || from == to {
+            return;
+        }
+        let song = self.songs.remove(from);
+        self.songs.insert(to, song);
+        self.current = self.current.map(|i| {
+            if i == from {
+                to
+            } else if from < i && i <= to {
+                i - 1
+            } else if to <= i && i < from {
+                i + 1
+            } else {
+                i
+            }
+        });
+    }
+
+    pub fn clear(&mut self) {
+        self.songs.clear();
+        self.current = None;
+    }
+
+    // 按播放模式移动到下一首，跳过无法播放的歌曲，没有下一首时返回 None
+    pub fn next(&mut self, mode: &Modes) -> Option<Song> {
+        if self.songs.is_empty() {
+            return None;
+        }
+        let next = match (mode, self.current) {
+            (_, None) => self.playable_from(0, false),
+            (Modes::Order, Some(i)) => self.playable_from(i + 1, false),
+            (Modes::Random, Some(_)) => self.playable_from(random_index(self.songs.len()), true),
+            (Modes::Repet, Some(i)) if self.songs[i].broken.is_empty() => Some(i),
+            (Modes::Repet, Some(i)) => self.playable_from(i + 1, false),
+        };
+        match next {
+            Some(i) => {
+                self.current = Some(i);
+                self.current_song()
+            }
+            None => {
+                println!("已经是最后一首歌曲!");
+                None
+            }
+        }
+    }
+
+    // 从 start 开始第一首可以播放的歌曲，wrap 为 true 时到末尾后从头继续找
+    fn playable_from(&self, start: usize, wrap: bool) -> Option<usize> {
+        let len = self.songs.len();
+        let count = if wrap { len } else { len.saturating_sub(start) };
+        (0..count)
+            .map(|n| (start + n) % len)
+            .find(|i| self.songs[*i].broken.is_empty())
+    }
+
+    // 文件移动后更新路径
+    pub fn rename_file(&mut self, from: &str, to: &str) {
+        for song in self.songs.iter_mut().filter(|s| s.file == from) {
+            song.file = to.to_owned();
+        }
+    }
+
+    // 标记无法播放的歌曲
+    pub fn mark_broken(&mut self, file: &str, reason: &str) {
+        for song in self.songs.iter_mut().filter(|s| s.file == file) {
+            song.broken = reason.to_owned();
+        }
+    }
+
+    // 移动到上一首，跳过无法播放的歌曲
+    pub fn prev(&mut self) -> Option<Song> {
+        let prev = self
+            .current
+            .and_then(|i| (0..i).rev().find(|j| self.songs[*j].broken.is_empty()));
+        match prev {
+            Some(i) => {
+                self.current = Some(i);
+                self.current_song()
+            }
+            _ => {
+                println!("已经是第一首歌曲!");
+                None
+            }
+        }
+    }
+
+    pub fn items(&self) -> Vector<QueueItem> {
+        self.songs
+            .iter()
+            .enumerate()
+            .map(|(index, song)| QueueItem {
+                index,
+                current: Some(index) == self.current,
+                song: song.to_owned(),
+            })
+            .collect()
+    }
+}
+
+// 随机播放用的简单随机数
+fn random_index(len: usize) -> usize {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.subsec_nanos())
+        .unwrap_or(0);
+    nanos as usize % len
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 队列 a, b, c, d, e，current 为当前位置
+    fn queue(current: Option<usize>) -> PlayQueue {
+        let songs = ["a", "b", "c", "d", "e"]
+            .iter()
+            .map(|f| Song {
+                file: f.to_string(),
+                ..Song::default()
+            })
+            .collect();
+        let mut q = PlayQueue::default();
+        q.restore(songs, current);
+        q
+    }
+
+    fn current_file(q: &PlayQueue) -> Option<String> {
+        q.current_song().map(|s| s.file)
+    }
+
+    #[test]
+    fn restore_checks_index() {
+        assert_eq!(queue(Some(4)).current(), Some(4));
+        assert_eq!(queue(Some(5)).current(), None);
+    }
+
+    #[test]
+    fn remove_before_current() {
+        let mut q = queue(Some(2));
+        q.remove(0);
+        assert_eq!(q.current(), Some(1));
+        assert_eq!(current_file(&q).as_deref(), Some("c"));
+    }
+
+    #[test]
+    fn remove_after_current() {
+        let mut q = queue(Some(2));
+        q.remove(4);
+        assert_eq!(q.current(), Some(2));
+        assert_eq!(current_file(&q).as_deref(), Some("c"));
+        // 超出范围的不处理
+        q.remove(10);
+        assert_eq!(q.files().len(), 4);
+    }
+
+    #[test]
+    fn remove_current() {
+        // 下一首从被删除歌曲的位置继续
+        let mut q = queue(Some(2));
+        q.remove(2);
+        assert_eq!(current_file(&q).as_deref(), Some("b"));
+        assert_eq!(q.next(&Modes::Order).map(|s| s.file).as_deref(), Some("d"));
+
+        let mut q = queue(Some(0));
+        q.remove(0);
+        assert_eq!(q.current(), None);
+        assert_eq!(q.next(&Modes::Order).map(|s| s.file).as_deref(), Some("b"));
+    }
+
+    #[test]
+    fn move_across_current() {
+        // 从当前歌曲之前移到之后
+        let mut q = queue(Some(2));
+        q.move_item(0, 4);
+        assert_eq!(q.files(), vec!["b", "c", "d", "e", "a"]);
+        assert_eq!(current_file(&q).as_deref(), Some("c"));
+        // 从当前歌曲之后移到之前
+        let mut q = queue(Some(2));
+        q.move_item(4, 0);
+        assert_eq!(q.files(), vec!["e", "a", "b", "c", "d"]);
+        assert_eq!(current_file(&q).as_deref(), Some("c"));
+        // 移动当前歌曲
+        let mut q = queue(Some(2));
+        q.move_item(2, 0);
+        assert_eq!(q.current(), Some(0));
+        assert_eq!(current_file(&q).as_deref(), Some("c"));
+        // 不跨过当前歌曲时位置不变
+        let mut q = queue(Some(2));
+        q.move_item(3, 4);
+        assert_eq!(q.current(), Some(2));
+        q.move_item(1, 5);
+        assert_eq!(q.files(), vec!["a", "b", "c", "e", "d"]);
+    }
+
+    #[test]
+    fn next_and_prev_at_ends() {
+        let mut q = queue(Some(4));
+        assert!(q.next(&Modes::Order).is_none());
+        assert_eq!(q.current(), Some(4));
+        // 单曲循环停在当前歌曲
+        assert_eq!(q.next(&Modes::Repet).map(|s| s.file).as_deref(), Some("e"));
+
+        let mut q = queue(Some(0));
+        assert!(q.prev().is_none());
+        assert_eq!(q.current(), Some(0));
+        assert!(queue(None).prev().is_none());
+        assert_eq!(
+            queue(None).next(&Modes::Order).map(|s| s.file).as_deref(),
+            Some("a")
+        );
+        assert!(PlayQueue::default().next(&Modes::Random).is_none());
+    }
+
+    #[test]
+    fn skip_broken() {
+        let mut q = queue(Some(0));
+        q.mark_broken("b", "无法解码");
+        q.mark_broken("e", "无法解码");
+        assert_eq!(q.next(&Modes::Order).map(|s| s.file).as_deref(), Some("c"));
+        assert_eq!(q.next(&Modes::Order).map(|s| s.file).as_deref(), Some("d"));
+        // 后面只剩无法播放的歌曲，不从头开始
+        assert!(q.next(&Modes::Order).is_none());
+        assert_eq!(q.prev().map(|s| s.file).as_deref(), Some("c"));
+        assert_eq!(q.prev().map(|s| s.file).as_deref(), Some("a"));
+        // 单曲循环遇到无法播放的歌曲时继续下一首
+        let mut q = queue(Some(1));
+        q.mark_broken("b", "无法解码");
+        assert_eq!(q.next(&Modes::Repet).map(|s| s.file).as_deref(), Some("c"));
+    }
+
+    #[test]
+    fn random_wraps() {
+        // 随机播放到末尾后从头找可以播放的歌曲
+        let mut q = queue(Some(4));
+        for f in ["b", "c", "d", "e"] {
+            q.mark_broken(f, "无法解码");
+        }
+        for _ in 0..10 {
+            assert_eq!(q.next(&Modes::Random).map(|s| s.file).as_deref(), Some("a"));
+        }
+    }
+}
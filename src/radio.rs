@@ -0,0 +1,277 @@
+use crate::ffmpeg_source::FfmpegSource;
+use crate::player::SourceFormat;
+use crate::Song;
+use druid::{Data, ExtEventSink, Lens, Selector, Target};
+use ffmpeg_next as ffmpeg;
+use rodio::Source;
+use serde::{Deserialize, Serialize};
+use std::sync::mpsc::{sync_channel, Receiver, RecvTimeoutError, SyncSender, TryRecvError};
+use std::thread::{sleep, spawn};
+use std::time::Duration;
+
+// 电台正在播放的曲目，(地址, 曲目)
+pub const STREAM_TITLE: Selector<(String, String)> = Selector::new("flac-music.stream-title");
+
+// 解码线程和播放之间最多缓冲的数据块，每块 1024 帧
+const BUFFER_CHUNKS: usize = 256;
+// 开始播放前预先缓冲的数据块
+const PREBUFFER_CHUNKS: usize = 32;
+// 断线后重连次数和间隔
+const RECONNECT_TRIES: usize = 5;
+const RECONNECT_DELAY: Duration = Duration::from_secs(2);
+
+// 网络电台
+#[derive(Clone, Data, Lens, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Station {
+    pub name: String,
+    pub url: String,
+}
+
+impl Station {
+    // 电台作为歌曲加入播放队列，文件为电台地址
+    pub fn to_song(&self) -> Song {
+        Song {
+            title: self.name.to_owned(),
+            artist: "Radio".to_owned(),
+            file: self.url.to_owned(),
+            ..Song::default()
+        }
+    }
+}
+
+// 是否为网络流地址，HLS 的 .m3u8 地址也由 ffmpeg 直接播放
+pub fn is_stream(f: &str) -> bool {
+    let f = f.to_lowercase();
+    f.starts_with("http://") || f.starts_with("https://")
+}
+
+// 从 ICY 元数据中取出 StreamTitle。
+// 标题中的引号不转义，所以取到 StreamUrl 前或最后一个 "';" 为止，元数据块末尾用 \0 补齐
+pub fn parse_stream_title(metadata: &str) -> Option<String> {
+    let start = metadata.find("StreamTitle='")? + "StreamTitle='".len();
+    let rest = metadata[start..].trim_end_matches('\0');
+    let end = rest
+        .find("';StreamUrl=")
+        .or_else(|| rest.rfind("';"))
+        .or_else(|| rest.strip_suffix('\'').map(|r| r.len()))
+        .unwrap_or(rest.len());
+    let title = rest[..end].trim();
+    if title.is_empty() {
+        None
+    } else {
+        Some(title.to_owned())
+    }
+}
+
+// ffmpeg 打开网络流，断线时自动重连，并请求 ICY 元数据
+fn open_input(url: &str) -> Result<FfmpegSource, String> {
+    ffmpeg::init().map_err(|e| e.to_string())?;
+    let mut options = ffmpeg::Dictionary::new();
+    options.set("reconnect", "1");
+    options.set("reconnect_streamed", "1");
+    options.set("reconnect_delay_max", "10");
+    options.set("icy", "1");
+    // 读写超时(微秒)
+    options.set("rw_timeout", "15000000");
+    options.set("user_agent", "flac_music");
+    let input = ffmpeg::format::input_with_dictionary(&url, options).map_err(|e| e.to_string())?;
+    FfmpegSource::from_input(input).map_err(|e| e.to_string())
+}
+
+// 网络电台音频源。解码在单独的线程中进行，网络卡顿时输出静音，不阻塞音频输出线程。
+pub struct RadioSource {
+    receiver: Receiver<Vec<f32>>,
+    buffer: Vec<f32>,
+    pos: usize,
+    channels: u16,
+    rate: u32,
+    ended: bool,
+}
+
+// 连接电台并预先缓冲一部分数据
+pub fn open(
+    url: &str,
+    events: Option<ExtEventSink>,
+) -> Result<(RadioSource, SourceFormat), String> {
+    let source = open_input(url)?;
+    let channels = source.channels();
+    let rate = source.sample_rate();
+    let format = SourceFormat {
+        codec: source.codec_name().to_owned(),
+        rate,
+        channels,
+        bits: 0,
+    };
+    let (sender, receiver) = sync_channel(BUFFER_CHUNKS);
+    let stream_url = url.to_owned();
+    spawn(move || decode_loop(stream_url, source, sender, events));
+    let mut buffer = vec![];
+    for _ in 0..PREBUFFER_CHUNKS {
+        match receiver.recv_timeout(Duration::from_secs(10)) {
+            Ok(chunk) => buffer.extend(chunk),
+            Err(RecvTimeoutError::Timeout) => break,
+            Err(RecvTimeoutError::Disconnected) if buffer.is_empty() => {
+                return Err("电台没有返回音频数据".to_owned())
+            }
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+    }
+    Ok((
+        RadioSource {
+            receiver,
+            buffer,
+            pos: 0,
+            channels,
+            rate,
+            ended: false,
+        },
+        format,
+    ))
+}
+
+// 解码线程：读取网络流，断线后重连，曲目变化时通知界面。
+// RadioSource 被 drop 后发送失败，线程退出。
+fn decode_loop(
+    url: String,
+    mut source: FfmpegSource,
+    sender: SyncSender<Vec<f32>>,
+    events: Option<ExtEventSink>,
+) {
+    let channels = source.channels();
+    let rate = source.sample_rate();
+    let chunk_len = 1024 * channels as usize;
+    let mut title = "".to_owned();
+    loop {
+        let chunk: Vec<f32> = source.by_ref().take(chunk_len).collect();
+        if chunk.is_empty() {
+            println!("电台 {} 连接断开，正在重连", url);
+            match reconnect(&url) {
+                // rodio 的 Source 格式不能中途改变，格式变化时结束播放
+                Some(s) if s.channels() == channels && s.sample_rate() == rate => source = s,
+                _ => break,
+            }
+            continue;
+        }
+        if let Some(t) = source.icy_metadata().and_then(|m| parse_stream_title(&m)) {
+            if t != title {
+                title = t;
+                if let Some(events) = &events {
+                    let _ = events.submit_command(
+                        STREAM_TITLE,
+                        (url.to_owned(), title.to_owned()),
+                        Target::Auto,
+                    );
+                }
+            }
+        }
+        if sender.send(chunk).is_err() {
+            break;
+        }
+    }
+}
+
+fn reconnect(url: &str) -> Option<FfmpegSource> {
+    for _ in 0..RECONNECT_TRIES {
+        sleep(RECONNECT_DELAY);
+        match open_input(url) {
+            Ok(source) => return Some(source),
+            Err(e) => println!("重连电台 {} 失败: {}", url, e),
+        }
+    }
+    None
+}
+
+impl Iterator for RadioSource {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if self.pos >= self.buffer.len() {
+            if self.ended {
+                return None;
+            }
+            match self.receiver.try_recv() {
+                Ok(chunk) => self.buffer = chunk,
+                // 缓冲不足时输出一帧静音
+                Err(TryRecvError::Empty) => self.buffer = vec![0.0; self.channels as usize],
+                Err(TryRecvError::Disconnected) => {
+                    self.ended = true;
+                    return None;
+                }
+            }
+            self.pos = 0;
+        }
+        let sample = self.buffer[self.pos];
+        self.pos += 1;
+        Some(sample)
+    }
+}
+
+impl Source for RadioSource {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.rate
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stream_title() {
+        assert_eq!(
+            parse_stream_title("StreamTitle='Artist - Song';StreamUrl='';"),
+            Some("Artist - Song".to_owned())
+        );
+        assert_eq!(
+            parse_stream_title("StreamTitle='Artist - Song';"),
+            Some("Artist - Song".to_owned())
+        );
+    }
+
+    #[test]
+    fn quotes_in_title() {
+        assert_eq!(
+            parse_stream_title("StreamTitle='Guns N' Roses - Don't Cry';StreamUrl='';"),
+            Some("Guns N' Roses - Don't Cry".to_owned())
+        );
+        assert_eq!(
+            parse_stream_title("StreamTitle='Rock';n'Roll';StreamUrl='http://example.com';"),
+            Some("Rock';n'Roll".to_owned())
+        );
+        assert_eq!(
+            parse_stream_title("StreamTitle='It's Time';"),
+            Some("It's Time".to_owned())
+        );
+    }
+
+    #[test]
+    fn empty_title() {
+        assert_eq!(parse_stream_title("StreamTitle='';StreamUrl='';"), None);
+        assert_eq!(parse_stream_title("StreamTitle='  ';"), None);
+        assert_eq!(parse_stream_title("StreamUrl='http://example.com';"), None);
+    }
+
+    #[test]
+    fn padding() {
+        assert_eq!(
+            parse_stream_title("StreamTitle='Song';\0\0\0\0"),
+            Some("Song".to_owned())
+        );
+        assert_eq!(
+            parse_stream_title("StreamTitle='Song'\0\0"),
+            Some("Song".to_owned())
+        );
+    }
+}
@@ -0,0 +1,307 @@
+use druid::Data;
+use rodio::{Sample, Source};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::f64::consts::PI;
+use std::time::Duration;
+
+// 重采样质量，Off 使用 rodio 自带的线性插值
+#[derive(Clone, Copy, Data, PartialEq, Debug, Default, Serialize, Deserialize)]
+pub enum ResampleQuality {
+    Off,
+    Fast,
+    #[default]
+    Medium,
+    High,
+}
+
+impl ResampleQuality {
+    // 滤波器抽头数
+    fn taps(&self) -> usize {
+        match self {
+            ResampleQuality::Off => 0,
+            ResampleQuality::Fast => 16,
+            ResampleQuality::Medium => 32,
+            ResampleQuality::High => 64,
+        }
+    }
+}
+
+// 滤波器系数表的相位数，相位之间线性插值
+const PHASES: usize = 256;
+
+fn sinc(x: f64) -> f64 {
+    if x.abs() < 1e-9 {
+        1.0
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+// Blackman-Harris 窗，n 取值 0.0 - 1.0
+fn blackman_harris(n: f64) -> f64 {
+    let a = 2.0 * PI * n;
+    0.35875 - 0.48829 * a.cos() + 0.14128 * (2.0 * a).cos() - 0.01168 * (3.0 * a).cos()
+}
+
+// 加窗 sinc 多相重采样器，用于源文件采样率和输出设备不一致的情况
+pub struct Resampler<S> {
+    inner: S,
+    channels: usize,
+    to_rate: u32,
+    taps: usize,
+    // table[phase * taps + tap]
+    table: Vec<f32>,
+    // 每个声道最近 taps 个输入帧
+    history: Vec<VecDeque<f32>>,
+    // 下一个输出帧在 history[taps / 2 - 1] 和 history[taps / 2] 之间的位置
+    frac: f64,
+    step: f64,
+    // 输入结束后补零的帧数
+    tail: usize,
+    out_frame: Vec<f32>,
+    out_index: usize,
+}
+
+impl<S> Resampler<S>
+where
+    S: Source,
+    S::Item: Sample,
+{
+    pub fn new(inner: S, to_rate: u32, quality: ResampleQuality) -> Resampler<S> {
+        let channels = inner.channels().max(1) as usize;
+        let from_rate = inner.sample_rate();
+        let taps = quality.taps().max(2);
+        // 降采样时降低截止频率，防止混叠
+        let cutoff = (to_rate as f64 / from_rate as f64).min(1.0) * 0.95;
+        let half = (taps / 2) as f64;
+        let mut table = vec![0.0; (PHASES + 1) * taps];
+        for phase in 0..=PHASES {
+            let frac = phase as f64 / PHASES as f64;
+            for tap in 0..taps {
+                // 抽头到输出位置的距离(以输入采样为单位)
+                let x = tap as f64 - (half - 1.0) - frac;
+                let w = blackman_harris((x + half) / (2.0 * half));
+                table[phase * taps + tap] = (cutoff * sinc(cutoff * x) * w) as f32;
+            }
+        }
+        let mut resampler = Resampler {
+            inner,
+            channels,
+            to_rate,
+            taps,
+            table,
+            history: vec![VecDeque::with_capacity(taps); channels],
+            frac: 0.0,
+            step: from_rate as f64 / to_rate as f64,
+            tail: taps / 2,
+            out_frame: vec![0.0; channels],
+            out_index: channels,
+        };
+        // 前半部分用静音填充，后半部分读取输入
+        for h in resampler.history.iter_mut() {
+            h.extend(std::iter::repeat(0.0).take(taps / 2));
+        }
+        for _ in 0..taps - taps / 2 {
+            resampler.push_frame();
+        }
+        resampler
+    }
+
+    // 读入一帧，返回 false 表示输入和补零都已结束
+    fn push_frame(&mut self) -> bool {
+        let mut frame = vec![0.0f32; self.channels];
+        let mut got = 0;
+        for sample in frame.iter_mut() {
+            match self.inner.next() {
+                Some(s) => {
+                    *sample = s.to_f32();
+                    got += 1;
+                }
+                None => break,
+            }
+        }
+        if got == 0 {
+            if self.tail == 0 {
+                return false;
+            }
+            self.tail -= 1;
+        }
+        for (h, s) in self.history.iter_mut().zip(frame) {
+            if h.len() == self.taps {
+                h.pop_front();
+            }
+            h.push_back(s);
+        }
+        true
+    }
+
+    fn compute_frame(&mut self) {
+        let pos = self.frac * PHASES as f64;
+        let phase = (pos as usize).min(PHASES - 1);
+        let t = (pos - phase as f64) as f32;
+        let a = &self.table[phase * self.taps..(phase + 1) * self.taps];
+        let b = &self.table[(phase + 1) * self.taps..(phase + 2) * self.taps];
+        for (c, h) in self.history.iter().enumerate() {
+            let mut sum = 0.0f32;
+            for (i, s) in h.iter().enumerate() {
+                sum += s * (a[i] + (b[i] - a[i]) * t);
+            }
+            self.out_frame[c] = sum;
+        }
+    }
+}
+
+impl<S> Iterator for Resampler<S>
+where
+    S: Source,
+    S::Item: Sample,
+{
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if self.out_index >= self.channels {
+            self.compute_frame();
+            self.out_index = 0;
+            self.frac += self.step;
+            while self.frac >= 1.0 {
+                self.frac -= 1.0;
+                if !self.push_frame() {
+                    return None;
+                }
+            }
+        }
+        let sample = self.out_frame[self.out_index];
+        self.out_index += 1;
+        Some(sample)
+    }
+}
+
+impl<S> Source for Resampler<S>
+where
+    S: Source,
+    S::Item: Sample,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        self.channels as u16
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.to_rate
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.inner.total_duration()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rodio::buffer::SamplesBuffer;
+
+    fn sine(freq: f64, rate: u32, seconds: f64) -> Vec<f32> {
+        (0..(rate as f64 * seconds) as usize)
+            .map(|i| (0.5 * (2.0 * PI * freq * i as f64 / rate as f64).sin()) as f32)
+            .collect()
+    }
+
+    fn resample(
+        samples: Vec<f32>,
+        channels: u16,
+        from: u32,
+        to: u32,
+        q: ResampleQuality,
+    ) -> Vec<f32> {
+        Resampler::new(SamplesBuffer::new(channels, from, samples), to, q).collect()
+    }
+
+    // 去掉开头和结尾各 10%，避开补零的部分
+    fn middle(samples: &[f32]) -> &[f32] {
+        &samples[samples.len() / 10..samples.len() * 9 / 10]
+    }
+
+    fn rms(samples: &[f32]) -> f64 {
+        (samples.iter().map(|s| (*s as f64).powi(2)).sum::<f64>() / samples.len() as f64).sqrt()
+    }
+
+    #[test]
+    fn output_length() {
+        for q in [
+            ResampleQuality::Fast,
+            ResampleQuality::Medium,
+            ResampleQuality::High,
+        ] {
+            let out = resample(vec![0.0; 44100], 1, 44100, 48000, q);
+            assert!(
+                (out.len() as i64 - 48000).abs() <= 64,
+                "{:?} {}",
+                q,
+                out.len()
+            );
+            let out = resample(vec![0.0; 96000 * 2], 2, 96000, 44100, q);
+            assert_eq!(out.len() % 2, 0);
+            assert!(
+                (out.len() as i64 - 44100 * 2).abs() <= 128,
+                "{:?} {}",
+                q,
+                out.len()
+            );
+        }
+    }
+
+    #[test]
+    fn keeps_dc_level() {
+        let out = resample(vec![0.5; 44100], 1, 44100, 48000, ResampleQuality::Medium);
+        for s in middle(&out) {
+            assert!((s - 0.5).abs() < 0.005, "{}", s);
+        }
+    }
+
+    #[test]
+    fn keeps_tone() {
+        let out = resample(
+            sine(1000.0, 44100, 1.0),
+            1,
+            44100,
+            48000,
+            ResampleQuality::Medium,
+        );
+        let mid = middle(&out);
+        // 振幅 0.5 的正弦波 RMS 为 0.354
+        assert!((rms(mid) - 0.5 / 2f64.sqrt()).abs() < 0.005, "{}", rms(mid));
+        // 频率不变：每秒 2000 次过零
+        let crossings = mid
+            .windows(2)
+            .filter(|w| (w[0] < 0.0) != (w[1] < 0.0))
+            .count();
+        let expected = 2000.0 * mid.len() as f64 / 48000.0;
+        assert!((crossings as f64 - expected).abs() <= 2.0, "{}", crossings);
+    }
+
+    #[test]
+    fn removes_content_above_new_nyquist() {
+        // 15kHz 在 22050Hz 输出中会混叠，应衰减 40dB 以上
+        let input = sine(15000.0, 48000, 1.0);
+        let out = resample(input, 1, 48000, 22050, ResampleQuality::High);
+        assert!(rms(middle(&out)) < 0.0035, "{}", rms(middle(&out)));
+    }
+
+    #[test]
+    fn keeps_channels_apart() {
+        let stereo: Vec<f32> = (0..44100).flat_map(|_| [1.0, 0.0]).collect();
+        let out = resample(stereo, 2, 44100, 48000, ResampleQuality::Medium);
+        let frames = out.len() / 2;
+        for frame in out.chunks_exact(2).skip(frames / 10).take(frames * 8 / 10) {
+            assert!(
+                (frame[0] - 1.0).abs() < 0.01 && frame[1].abs() < 0.01,
+                "{:?}",
+                frame
+            );
+        }
+    }
+}
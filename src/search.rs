@@ -0,0 +1,234 @@
+use crate::Song;
+use druid::im::Vector;
+use unicode_normalization::char::is_combining_mark;
+use unicode_normalization::UnicodeNormalization;
+
+// 搜索字段，支持 artist:miles year:1959 这样的限定查询
+#[derive(Clone, Debug, PartialEq)]
+enum Field {
+    Any,
+    Title,
+    Artist,
+    Album,
+    Path,
+    Year,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Term {
+    field: Field,
+    value: String,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Query {
+    terms: Vec<Term>,
+}
+
+// 统一大小写和去掉变音符号，NFKD 同时会把全角字母数字转成半角，方便中英文混合标签匹配。
+pub fn normalize(s: &str) -> String {
+    s.nfkd()
+        .filter(|c| !is_combining_mark(*c))
+        .flat_map(|c| c.to_lowercase())
+        .collect()
+}
+
+// 按空白切分，双引号内的空白不切分
+fn split_tokens(input: &str) -> Vec<String> {
+    let mut tokens = vec![];
+    let mut current = String::new();
+    let mut in_quotes = false;
+    for c in input.chars() {
+        match c {
+            '"' => in_quotes = !in_quotes,
+            c if c.is_whitespace() && !in_quotes => {
+                if !current.is_empty() {
+                    tokens.push(current.clone());
+                    current.clear();
+                }
+            }
+            c => current.push(c),
+        }
+    }
+    if !current.is_empty() {
+        tokens.push(current);
+    }
+    tokens
+}
+
+impl Query {
+    pub fn parse(input: &str) -> Query {
+        let mut terms = vec![];
+        for token in split_tokens(input) {
+            let term = match token.split_once(':') {
+                Some((name, value)) if !value.is_empty() => {
+                    let field = match normalize(name).as_str() {
+                        "title" => Field::Title,
+                        "artist" => Field::Artist,
+                        "album" => Field::Album,
+                        "path" | "file" => Field::Path,
+                        "year" | "date" => Field::Year,
+                        _ => Field::Any,
+                    };
+                    if field == Field::Any {
+                        // 未知字段，整体当作普通关键词
+                        Term {
+                            field,
+                            value: normalize(&token),
+                        }
+                    } else {
+                        Term {
+                            field,
+                            value: normalize(value),
+                        }
+                    }
+                }
+                _ => Term {
+                    field: Field::Any,
+                    value: normalize(&token),
+                },
+            };
+            terms.push(term);
+        }
+        Query { terms }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.terms.is_empty()
+    }
+
+    // 所有关键词都要命中(AND)
+    pub fn matches(&self, song: &Song) -> bool {
+        self.terms.iter().all(|term| {
+            let hit = |s: &str| normalize(s).contains(&term.value);
+            match term.field {
+                Field::Any => {
+                    hit(&song.title) || hit(&song.artist) || hit(&song.album) || hit(&song.file)
+                }
+                Field::Title => hit(&song.title),
+                Field::Artist => hit(&song.artist),
+                Field::Album => hit(&song.album),
+                Field::Path => hit(&song.file),
+                Field::Year => normalize(&song.date).starts_with(&term.value),
+            }
+        })
+    }
+}
+
+// 按搜索框内容过滤歌曲列表，只生成显示用的视图，不影响播放列表。
+pub fn filter(songs: &Vector<Song>, input: &str) -> Vector<Song> {
+    let query = Query::parse(input);
+    if query.is_empty() {
+        return songs.clone();
+    }
+    songs.iter().filter(|s| query.matches(s)).cloned().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn term(field: Field, value: &str) -> Term {
+        Term {
+            field,
+            value: value.to_owned(),
+        }
+    }
+
+    fn song(title: &str, artist: &str, album: &str, date: &str) -> Song {
+        Song {
+            file: format!("/music/{}/{}.flac", artist, title),
+            title: title.to_owned(),
+            artist: artist.to_owned(),
+            album: album.to_owned(),
+            date: date.to_owned(),
+            ..Song::default()
+        }
+    }
+
+    #[test]
+    fn fold_case_and_marks() {
+        assert_eq!(normalize("Beyoncé"), "beyonce");
+        assert_eq!(normalize("SIGUR RÓS"), "sigur ros");
+        assert_eq!(normalize("Dvořák"), "dvorak");
+        // 全角字母数字转成半角
+        assert_eq!(normalize("ＡＢＣ１２３"), "abc123");
+        assert_eq!(normalize("周杰伦"), "周杰伦");
+    }
+
+    #[test]
+    fn tokens() {
+        assert_eq!(
+            split_tokens("  kind   of blue "),
+            vec!["kind", "of", "blue"]
+        );
+        assert_eq!(
+            split_tokens(r#"album:"kind of blue" miles"#),
+            vec!["album:kind of blue", "miles"]
+        );
+        assert!(split_tokens("   ").is_empty());
+    }
+
+    #[test]
+    fn parse_fields() {
+        let query = Query::parse(r#"Artist:Miles year:1959 title:"So What" Blue"#);
+        assert_eq!(
+            query.terms,
+            vec![
+                term(Field::Artist, "miles"),
+                term(Field::Year, "1959"),
+                term(Field::Title, "so what"),
+                term(Field::Any, "blue"),
+            ]
+        );
+        // 别名
+        let query = Query::parse("file:jazz date:1959-08");
+        assert_eq!(
+            query.terms,
+            vec![term(Field::Path, "jazz"), term(Field::Year, "1959-08")]
+        );
+        // 未知字段和空值整体当作普通关键词
+        let query = Query::parse("genre:jazz artist: 12:34");
+        assert_eq!(
+            query.terms,
+            vec![
+                term(Field::Any, "genre:jazz"),
+                term(Field::Any, "artist:"),
+                term(Field::Any, "12:34"),
+            ]
+        );
+        assert!(Query::parse("  ").is_empty());
+    }
+
+    #[test]
+    fn match_terms() {
+        let so_what = song("So What", "Miles Davis", "Kind of Blue", "1959-08-17");
+        let halo = song("Halo", "Beyoncé", "I Am... Sasha Fierce", "2008");
+        let matches = |input: &str, s: &Song| Query::parse(input).matches(s);
+        assert!(matches("miles blue", &so_what));
+        assert!(matches("artist:davis year:1959", &so_what));
+        // 所有关键词都要命中
+        assert!(!matches("miles halo", &so_what));
+        // 字段限定只在该字段中查找
+        assert!(!matches("title:miles", &so_what));
+        assert!(matches("path:davis", &so_what));
+        // 年份按前缀匹配
+        assert!(!matches("year:59", &so_what));
+        assert!(matches("BEYONCE", &halo));
+        assert!(matches("artist:beyoncé", &halo));
+    }
+
+    #[test]
+    fn filter_view() {
+        let songs: Vector<Song> = vec![
+            song("So What", "Miles Davis", "Kind of Blue", "1959"),
+            song("Halo", "Beyoncé", "I Am... Sasha Fierce", "2008"),
+        ]
+        .into();
+        assert_eq!(filter(&songs, "").len(), 2);
+        let found = filter(&songs, "halo");
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].title, "Halo");
+        assert!(filter(&songs, "nothing").is_empty());
+    }
+}
@@ -0,0 +1,164 @@
+use crate::radio::Station;
+use crate::resample::ResampleQuality;
+use crate::Modes;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+
+// 需要在重启后恢复的播放状态
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct Session {
+    // 没有保存过时使用配置文件中的值
+    pub volume: Option<f64>,
+    pub play_mode: Modes,
+    pub music_dirs: Vec<String>,
+    // 播放队列中的歌曲文件
+    pub queue: Vec<String>,
+    pub current: Option<usize>,
+    // 当前歌曲播放进度(毫秒)
+    pub position_ms: u64,
+    pub window_width: Option<f64>,
+    pub window_height: Option<f64>,
+    // 列表各列宽度：标题，专辑，艺人，时长，日期
+    pub column_widths: Vec<f64>,
+    // 启动后自动继续播放
+    pub auto_resume: bool,
+    // 上次选择的输出设备，空字符串为系统默认设备
+    pub device: Option<String>,
+    // bit-perfect 输出模式
+    pub bit_perfect: bool,
+    pub resample_quality: ResampleQuality,
+    // 保存的播放列表。
+    // TOML 的表数组必须放在最后，空数组会被写成普通值，所以为空时不写
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub playlists: Vec<SavedPlaylist>,
+    // 网络电台列表
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub stations: Vec<Station>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct SavedPlaylist {
+    pub name: String,
+    pub files: Vec<String>,
+    // 上次是否勾选同步到设备
+    pub sync: bool,
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Session {
+            volume: None,
+            play_mode: Modes::Order,
+            music_dirs: vec![],
+            queue: vec![],
+            current: None,
+            position_ms: 0,
+            window_width: None,
+            window_height: None,
+            column_widths: vec![120.0; 5],
+            auto_resume: false,
+            device: None,
+            bit_perfect: false,
+            resample_quality: ResampleQuality::default(),
+            playlists: vec![],
+            stations: vec![],
+        }
+    }
+}
+
+fn session_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|d| d.join("flac_music").join("session.toml"))
+}
+
+// 读取上次保存的状态，文件不存在或格式错误时使用默认值
+pub fn load() -> Session {
+    let path = match session_path() {
+        Some(p) => p,
+        None => return Session::default(),
+    };
+    match fs::read_to_string(&path) {
+        Ok(text) => toml::from_str(&text).unwrap_or_else(|e| {
+            println!("读取会话文件失败 {}: {}", path.display(), e);
+            Session::default()
+        }),
+        Err(_) => Session::default(),
+    }
+}
+
+// 先写临时文件再改名，避免退出时写了一半
+pub fn save(session: &Session) {
+    let path = match session_path() {
+        Some(p) => p,
+        None => return,
+    };
+    let text = match toml::to_string(session) {
+        Ok(t) => t,
+        Err(e) => {
+            println!("保存会话失败: {}", e);
+            return;
+        }
+    };
+    if let Some(dir) = path.parent() {
+        let _ = fs::create_dir_all(dir);
+    }
+    let tmp = path.with_extension("toml.tmp");
+    if let Err(e) = fs::write(&tmp, text).and_then(|_| fs::rename(&tmp, &path)) {
+        println!("保存会话失败 {}: {}", path.display(), e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(session: &Session) -> Session {
+        let text = toml::to_string(session).expect("serialize session");
+        toml::from_str(&text).expect("parse session")
+    }
+
+    #[test]
+    fn stations_without_playlists() {
+        let session = Session {
+            volume: Some(0.5),
+            queue: vec!["/music/a.flac".to_owned()],
+            current: Some(0),
+            position_ms: 1234,
+            stations: vec![Station {
+                name: "Radio".to_owned(),
+                url: "http://example.com/stream".to_owned(),
+            }],
+            ..Session::default()
+        };
+        let loaded = round_trip(&session);
+        assert_eq!(loaded.volume, Some(0.5));
+        assert_eq!(loaded.queue, session.queue);
+        assert_eq!(loaded.current, Some(0));
+        assert_eq!(loaded.position_ms, 1234);
+        assert_eq!(loaded.stations, session.stations);
+        assert!(loaded.playlists.is_empty());
+    }
+
+    #[test]
+    fn stations_and_playlists() {
+        let session = Session {
+            stations: vec![Station {
+                name: "Radio".to_owned(),
+                url: "http://example.com/stream".to_owned(),
+            }],
+            playlists: vec![SavedPlaylist {
+                name: "Car".to_owned(),
+                files: vec!["/music/a.flac".to_owned()],
+                sync: true,
+            }],
+            ..Session::default()
+        };
+        let loaded = round_trip(&session);
+        assert_eq!(loaded.stations, session.stations);
+        assert_eq!(loaded.playlists.len(), 1);
+        assert_eq!(loaded.playlists[0].files, session.playlists[0].files);
+        assert!(loaded.playlists[0].sync);
+    }
+}
@@ -0,0 +1,252 @@
+use crate::config::Config;
+use ffmpeg_next as ffmpeg;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+// 按文件头识别出的音频格式
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AudioKind {
+    Flac,
+    Mpeg,
+    Wav,
+    Ogg,
+    Opus,
+    Ape,
+    WavPack,
+    Aiff,
+    Mp4,
+    Dsf,
+    Dff,
+    Tta,
+    Matroska,
+}
+
+impl AudioKind {
+    // 这些容器也可能是视频文件，需要再用 ffmpeg 确认
+    fn may_contain_video(&self) -> bool {
+        matches!(self, AudioKind::Mp4 | AudioKind::Matroska)
+    }
+}
+
+// 按文件头判断的结果
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Sniffed {
+    Audio(AudioKind),
+    // 可以确定不是音频，例如文本、图片、压缩包和视频
+    NotAudio,
+    // 识别不出的二进制数据，由扩展名决定
+    Unknown,
+}
+
+// 常见的非音频文件头
+const OTHER_MAGIC: [&[u8]; 8] = [
+    b"\x89PNG",
+    &[0xff, 0xd8, 0xff],
+    b"GIF8",
+    b"%PDF",
+    b"PK\x03\x04",
+    b"Rar!",
+    b"7z\xbc\xaf",
+    b"BM",
+];
+
+// 只认音频类的 MP4 品牌，其他品牌(mp42, isom 等)交给 ffmpeg 判断
+const MP4_AUDIO_BRANDS: [&[u8]; 4] = [b"M4A ", b"M4B ", b"M4P ", b"F4A "];
+
+// 读取文件头，跳过 ID3v2 标签，FLAC, APE, TTA 文件前面也可能带 ID3
+fn read_head(f: &str) -> Option<Vec<u8>> {
+    let mut file = File::open(f).ok()?;
+    let mut head = [0u8; 512];
+    let mut n = file.read(&mut head).ok()?;
+    if n >= 10 && &head[0..3] == b"ID3" {
+        let size = head[6..10]
+            .iter()
+            .fold(0u64, |acc, b| (acc << 7) | u64::from(b & 0x7f));
+        // 带页脚的标签多 10 字节
+        let footer = if head[5] & 0x10 != 0 { 10 } else { 0 };
+        file.seek(SeekFrom::Start(10 + size + footer)).ok()?;
+        n = file.read(&mut head).ok()?;
+    }
+    Some(head[..n].to_vec())
+}
+
+// 没有控制字符的文件头按文本处理
+fn is_text(head: &[u8]) -> bool {
+    head.iter()
+        .all(|b| *b >= 0x20 || b"\t\n\r\x0c\x1b".contains(b))
+}
+
+// 按文件头的魔数识别音频格式
+pub fn sniff(head: &[u8]) -> Sniffed {
+    let at = |offset: usize, magic: &[u8]| {
+        head.len() >= offset + magic.len() && &head[offset..offset + magic.len()] == magic
+    };
+    let kind = if at(0, b"fLaC") {
+        AudioKind::Flac
+    } else if at(0, b"OggS") && at(28, b"OpusHead") {
+        AudioKind::Opus
+    } else if at(0, b"OggS") {
+        AudioKind::Ogg
+    } else if (at(0, b"RIFF") || at(0, b"RF64")) && at(8, b"WAVE") {
+        AudioKind::Wav
+    } else if at(0, b"FORM") && (at(8, b"AIFF") || at(8, b"AIFC")) {
+        AudioKind::Aiff
+    } else if at(0, b"MAC ") {
+        AudioKind::Ape
+    } else if at(0, b"wvpk") {
+        AudioKind::WavPack
+    } else if at(0, b"TTA1") {
+        AudioKind::Tta
+    } else if at(0, b"DSD ") {
+        AudioKind::Dsf
+    } else if at(0, b"FRM8") && at(12, b"DSD ") {
+        AudioKind::Dff
+    } else if at(4, b"ftyp") {
+        AudioKind::Mp4
+    } else if at(0, &[0x1a, 0x45, 0xdf, 0xa3]) {
+        AudioKind::Matroska
+    } else if head.len() >= 2 && head[0] == 0xff && head[1] & 0xe0 == 0xe0 {
+        // MPEG 音频帧同步字，包括 MP3 和 ADTS 封装的 AAC
+        AudioKind::Mpeg
+    } else if OTHER_MAGIC.iter().any(|m| at(0, m)) || is_text(head) {
+        // 空文件也算作文本
+        return Sniffed::NotAudio;
+    } else {
+        return Sniffed::Unknown;
+    };
+    Sniffed::Audio(kind)
+}
+
+// 按文件内容识别，MP4 和 Matroska 再用 ffmpeg 确认不是视频
+fn classify(f: &str) -> Sniffed {
+    let head = match read_head(f) {
+        Some(h) => h,
+        None => return Sniffed::Unknown,
+    };
+    match sniff(&head) {
+        Sniffed::Audio(AudioKind::Mp4)
+            if head.len() >= 12 && MP4_AUDIO_BRANDS.iter().any(|b| &head[8..12] == *b) =>
+        {
+            Sniffed::Audio(AudioKind::Mp4)
+        }
+        Sniffed::Audio(kind) if kind.may_contain_video() && !audio_only(f) => Sniffed::NotAudio,
+        sniffed => sniffed,
+    }
+}
+
+// 按文件头的魔数识别音频格式，不依赖扩展名
+pub fn detect(f: &str) -> Option<AudioKind> {
+    match classify(f) {
+        Sniffed::Audio(kind) => Some(kind),
+        _ => None,
+    }
+}
+
+// 有音频流并且没有视频流(专辑封面除外)
+fn audio_only(f: &str) -> bool {
+    if ffmpeg::init().is_err() {
+        return false;
+    }
+    let context = match ffmpeg::format::input(&Path::new(f)) {
+        Ok(c) => c,
+        Err(_) => return false,
+    };
+    let mut has_audio = false;
+    for stream in context.streams() {
+        match stream.parameters().medium() {
+            ffmpeg::media::Type::Audio => has_audio = true,
+            ffmpeg::media::Type::Video
+                if !stream
+                    .disposition()
+                    .contains(ffmpeg::format::stream::Disposition::ATTACHED_PIC) =>
+            {
+                return false
+            }
+            _ => {}
+        }
+    }
+    has_audio
+}
+
+// 是否导入到音乐库：按文件内容识别，只有识别不出时才看配置中的扩展名
+pub fn is_music_file(f: &str, config: &Config) -> bool {
+    if !Path::new(f).is_file() {
+        return false;
+    }
+    match classify(f) {
+        Sniffed::Audio(_) => true,
+        Sniffed::NotAudio => false,
+        Sniffed::Unknown => config.is_music_ext(f),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn magic_bytes() {
+        assert_eq!(sniff(b"fLaC\0\0\0\x22"), Sniffed::Audio(AudioKind::Flac));
+        let mut ogg = b"OggS\0\x02".to_vec();
+        ogg.resize(28, 0);
+        assert_eq!(sniff(&ogg), Sniffed::Audio(AudioKind::Ogg));
+        ogg.extend(b"OpusHead");
+        assert_eq!(sniff(&ogg), Sniffed::Audio(AudioKind::Opus));
+        assert_eq!(
+            sniff(b"RIFF\x24\0\0\0WAVEfmt "),
+            Sniffed::Audio(AudioKind::Wav)
+        );
+        assert_eq!(sniff(b"RIFF\x24\0\0\0AVI "), Sniffed::Unknown);
+        assert_eq!(
+            sniff(b"\0\0\0\x20ftypM4A \0\0\0\0"),
+            Sniffed::Audio(AudioKind::Mp4)
+        );
+        assert_eq!(
+            sniff(&[0xff, 0xfb, 0x90, 0x64]),
+            Sniffed::Audio(AudioKind::Mpeg)
+        );
+    }
+
+    #[test]
+    fn not_audio() {
+        assert_eq!(sniff(b"just some text\r\nline two\n"), Sniffed::NotAudio);
+        assert_eq!(sniff("中文歌词".as_bytes()), Sniffed::NotAudio);
+        assert_eq!(sniff(b""), Sniffed::NotAudio);
+        assert_eq!(sniff(b"\x89PNG\r\n\x1a\n"), Sniffed::NotAudio);
+        assert_eq!(sniff(&[0xff, 0xd8, 0xff, 0xe0]), Sniffed::NotAudio);
+        assert_eq!(sniff(b"PK\x03\x04\x14\0"), Sniffed::NotAudio);
+        // 无法识别的二进制数据
+        assert_eq!(
+            sniff(&[0x30, 0x26, 0xb2, 0x75, 0x00, 0x01]),
+            Sniffed::Unknown
+        );
+    }
+
+    #[test]
+    fn skip_id3() {
+        let dir = std::env::temp_dir().join(format!("flac_music_sniff_{}", std::process::id()));
+        fs::create_dir_all(&dir).expect("create test dir");
+        let mut data = b"ID3\x04\0\0\0\0\x01\x00".to_vec();
+        data.resize(10 + 128, 0);
+        let mut flac = data.to_owned();
+        flac.extend(b"fLaC\0\0\0\x22");
+        let mut mp3 = data.to_owned();
+        mp3.extend([0xff, 0xfb, 0x90, 0x64]);
+        let flac_path = dir.join("a.mp3");
+        let mp3_path = dir.join("b.flac");
+        let text_path = dir.join("c.flac");
+        fs::write(&flac_path, flac).expect("write test file");
+        fs::write(&mp3_path, mp3).expect("write test file");
+        fs::write(&text_path, "not music").expect("write test file");
+        let flac = detect(&flac_path.display().to_string());
+        let mp3 = detect(&mp3_path.display().to_string());
+        let text = classify(&text_path.display().to_string());
+        let _ = fs::remove_dir_all(&dir);
+        // 按内容识别，和扩展名无关
+        assert_eq!(flac, Some(AudioKind::Flac));
+        assert_eq!(mp3, Some(AudioKind::Mpeg));
+        assert_eq!(text, Sniffed::NotAudio);
+    }
+}
@@ -0,0 +1,522 @@
+use crate::fingerprint;
+use crate::organize;
+use crate::player;
+use crate::transcode::{self, ExportSettings};
+use crate::Song;
+use druid::im::Vector;
+use druid::{Data, ExtEventSink, Selector, Target};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Component, Path};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::spawn;
+
+// 同步进度，(已处理, 总数)
+pub const SYNC_PROGRESS: Selector<(usize, usize)> = Selector::new("flac-music.sync-progress");
+// 同步结束，结果说明
+pub const SYNC_DONE: Selector<String> = Selector::new("flac-music.sync-done");
+
+// 设备上记录已同步文件的清单，只删除清单中的文件，不会动设备上的其他文件
+const MANIFEST: &str = ".flac_music_sync.txt";
+
+// 什么时候转换格式
+#[derive(Clone, Copy, Data, PartialEq, Debug, Default, Serialize, Deserialize)]
+pub enum SyncTranscode {
+    // 全部直接复制
+    #[serde(rename = "never")]
+    Never,
+    // 无损文件转换为导出格式，有损文件直接复制
+    #[default]
+    #[serde(rename = "lossless")]
+    Lossless,
+    // 全部转换为导出格式
+    #[serde(rename = "always")]
+    Always,
+}
+
+// 要同步的播放列表
+pub struct SyncPlaylist {
+    pub name: String,
+    pub songs: Vector<Song>,
+}
+
+// 清单中的一项，mode 为 "copy" 或转换设置，设置改变后重新转换
+#[derive(Clone, Debug, PartialEq)]
+struct Entry {
+    source: String,
+    mtime: u64,
+    size: u64,
+    mode: String,
+}
+
+fn load_manifest(dest: &Path) -> HashMap<String, Entry> {
+    match fs::read_to_string(dest.join(MANIFEST)) {
+        Ok(text) => parse_manifest(&text),
+        Err(_) => HashMap::new(),
+    }
+}
+
+// 清单在设备上，可能被修改过，只接受同步目录内的相对路径，否则删除时会删到目录外的文件
+fn is_safe_target(target: &str) -> bool {
+    let path = Path::new(target);
+    path.components().next().is_some()
+        && path.components().all(|c| matches!(c, Component::Normal(_)))
+}
+
+// 每行: 设备上的相对路径\t原文件\t修改时间\t大小\t方式
+fn parse_manifest(text: &str) -> HashMap<String, Entry> {
+    text.lines()
+        .filter_map(|line| {
+            let mut parts = line.split('\t');
+            let target = parts.next().filter(|t| is_safe_target(t))?.to_owned();
+            let entry = Entry {
+                source: parts.next()?.to_owned(),
+                mtime: parts.next()?.parse().ok()?,
+                size: parts.next()?.parse().ok()?,
+                mode: parts.next()?.to_owned(),
+            };
+            Some((target, entry))
+        })
+        .collect()
+}
+
+fn save_manifest(dest: &Path, manifest: &HashMap<String, Entry>) -> Result<(), String> {
+    let mut lines: Vec<String> = manifest
+        .iter()
+        .filter(|(target, e)| !target.contains(['\t', '\n']) && !e.source.contains(['\t', '\n']))
+        .map(|(target, e)| {
+            format!(
+                "{}\t{}\t{}\t{}\t{}",
+                target, e.source, e.mtime, e.size, e.mode
+            )
+        })
+        .collect();
+    lines.sort();
+    let path = dest.join(MANIFEST);
+    let tmp = dest.join(format!("{}.tmp", MANIFEST));
+    fs::write(&tmp, lines.join("\n") + "\n")
+        .and_then(|_| fs::rename(&tmp, &path))
+        .map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+// 设备上的相对路径，统一使用 /，每一级去掉 FAT 文件系统不支持的字符
+fn device_path(relative: &Path) -> String {
+    relative
+        .components()
+        .map(|c| organize::sanitize(&c.as_os_str().to_string_lossy()))
+        .filter(|c| !c.is_empty())
+        .collect::<Vec<String>>()
+        .join("/")
+}
+
+// FAT 和 exFAT 不区分大小写，不同歌曲得到相同的路径时在文件名后加上序号
+fn unique_target(target: String, used: &HashSet<String>) -> String {
+    if !used.contains(&organize::collision_key(&target)) {
+        return target;
+    }
+    let (dir, name) = match target.rfind('/') {
+        Some(i) => (&target[..=i], &target[i + 1..]),
+        None => ("", target.as_str()),
+    };
+    let (stem, ext) = match name.rfind('.') {
+        Some(i) if i > 0 => (&name[..i], &name[i..]),
+        _ => (name, ""),
+    };
+    let mut n = 2;
+    loop {
+        let candidate = format!("{}{} ({}){}", dir, stem, n, ext);
+        if !used.contains(&organize::collision_key(&candidate)) {
+            return candidate;
+        }
+        n += 1;
+    }
+}
+
+fn needs_transcode(file: &str, when: SyncTranscode) -> bool {
+    match when {
+        SyncTranscode::Never => false,
+        SyncTranscode::Always => true,
+        SyncTranscode::Lossless => {
+            matches!(player::probe_format(file), Some(f) if fingerprint::is_lossless(&f.codec))
+        }
+    }
+}
+
+fn settings_key(settings: &ExportSettings) -> String {
+    format!(
+        "{:?}/{}/{}",
+        settings.format, settings.sample_rate, settings.bit_depth
+    )
+}
+
+// 先复制到临时文件再改名，中途拔出设备时不会留下不完整的文件
+fn copy_file(from: &str, to: &Path) -> Result<(), String> {
+    if let Some(dir) = to.parent() {
+        fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+    }
+    let name = to
+        .file_name()
+        .map_or("".to_owned(), |n| n.to_string_lossy().to_string());
+    let tmp = to.with_file_name(format!(".{}.syncing", name));
+    let result = fs::copy(from, &tmp)
+        .and_then(|_| fs::rename(&tmp, to))
+        .map_err(|e| e.to_string());
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+    result
+}
+
+// 删除文件后删除变空的目录，同步目录本身保留
+fn remove_synced(dest: &Path, target: &str) {
+    let path = dest.join(target);
+    if !is_safe_target(target) || !path.starts_with(dest) {
+        return;
+    }
+    let _ = fs::remove_file(&path);
+    let mut dir = path.parent();
+    while let Some(d) = dir {
+        if d == dest || !d.starts_with(dest) || fs::remove_dir(d).is_err() {
+            break;
+        }
+        dir = d.parent();
+    }
+}
+
+// 原文件暂时不可用（例如网络存储没有挂载）的歌曲在旧清单中的项，保留设备上已同步的版本
+fn offline_entries<'a>(
+    songs: impl Iterator<Item = &'a Song>,
+    old: &HashMap<String, Entry>,
+) -> Vec<(String, Entry)> {
+    let missing: HashSet<&str> = songs
+        .map(|s| s.file.as_str())
+        .filter(|f| !Path::new(f).is_file())
+        .collect();
+    old.iter()
+        .filter(|(_, e)| e.mode != "m3u" && missing.contains(e.source.as_str()))
+        .map(|(target, e)| (target.to_owned(), e.to_owned()))
+        .collect()
+}
+
+// 旧清单中有但新清单中没有的文件，只是大小写不同的路径在设备上是同一个文件
+fn orphaned(old: &HashMap<String, Entry>, manifest: &HashMap<String, Entry>) -> Vec<String> {
+    let keep: HashSet<String> = manifest
+        .keys()
+        .map(|t| organize::collision_key(t))
+        .collect();
+    let mut targets: Vec<String> = old
+        .keys()
+        .filter(|t| !keep.contains(&organize::collision_key(t)))
+        .cloned()
+        .collect();
+    targets.sort();
+    targets
+}
+
+// 设备上的 M3U 播放列表，使用相对路径
+fn write_m3u(dest: &Path, name: &str, entries: &[(String, &Song)]) -> Result<String, String> {
+    let file_name = format!("{}.m3u8", organize::sanitize(name));
+    let mut text = "#EXTM3U\n".to_owned();
+    for (target, song) in entries {
+        text.push_str(&format!(
+            "#EXTINF:{},{} - {}\n{}\n",
+            song.duration.round() as i64,
+            song.artist,
+            song.title,
+            target
+        ));
+    }
+    fs::write(dest.join(&file_name), text).map_err(|e| format!("{}: {}", file_name, e))?;
+    Ok(file_name)
+}
+
+// 把播放列表同步到目录：复制或转换新增和修改过的歌曲，删除不再需要的文件，写入 M3U 和清单
+fn sync(
+    playlists: &[SyncPlaylist],
+    dest: &Path,
+    roots: &Vector<String>,
+    when: SyncTranscode,
+    settings: &ExportSettings,
+    cancel: &AtomicBool,
+    events: &ExtEventSink,
+) -> Result<String, String> {
+    fs::create_dir_all(dest).map_err(|e| format!("{}: {}", dest.display(), e))?;
+    let old = load_manifest(dest);
+    let mut manifest: HashMap<String, Entry> = HashMap::new();
+    // 每首歌只同步一次，多个播放列表共用
+    let mut targets: HashMap<String, String> = HashMap::new();
+    // 已经使用的设备路径，不区分大小写
+    let mut used: HashSet<String> = HashSet::new();
+    let mut jobs: Vec<(String, String, String)> = vec![];
+    for (target, entry) in offline_entries(playlists.iter().flat_map(|p| p.songs.iter()), &old) {
+        used.insert(organize::collision_key(&target));
+        targets.insert(entry.source.to_owned(), target.to_owned());
+        manifest.insert(target, entry);
+    }
+    for song in playlists.iter().flat_map(|p| p.songs.iter()) {
+        if targets.contains_key(&song.file) || !Path::new(&song.file).is_file() {
+            continue;
+        }
+        let transcoding = needs_transcode(&song.file, when);
+        let mut relative = organize::relative_path(&song.file, roots);
+        let mode = if transcoding {
+            relative.set_extension(settings.format.extension());
+            settings_key(settings)
+        } else {
+            "copy".to_owned()
+        };
+        let target = unique_target(device_path(&relative), &used);
+        used.insert(organize::collision_key(&target));
+        targets.insert(song.file.to_owned(), target.to_owned());
+        jobs.push((song.file.to_owned(), target, mode));
+    }
+
+    let total = jobs.len();
+    let (mut copied, mut converted, mut unchanged) = (0, 0, 0);
+    let mut errors = vec![];
+    for (i, (file, target, mode)) in jobs.iter().enumerate() {
+        if cancel.load(Ordering::Relaxed) {
+            break;
+        }
+        let (mtime, size) = fingerprint::file_stamp(file).unwrap_or((0, 0));
+        let entry = Entry {
+            source: file.to_owned(),
+            mtime,
+            size,
+            mode: mode.to_owned(),
+        };
+        let path = dest.join(target);
+        if old.get(target) == Some(&entry) && path.is_file() {
+            unchanged += 1;
+        } else {
+            let result = if mode == "copy" {
+                copy_file(file, &path)
+            } else {
+                // 先删除旧版本，Windows 上改名不能覆盖已存在的文件
+                let _ = fs::remove_file(&path);
+                transcode::transcode(file, &path, settings, cancel)
+            };
+            match result {
+                Ok(_) if mode == "copy" => copied += 1,
+                Ok(_) => converted += 1,
+                Err(e) => {
+                    if !cancel.load(Ordering::Relaxed) {
+                        errors.push(format!("{}: {}", file, e));
+                    }
+                    // 失败时保留设备上原有的版本
+                    if let Some(previous) = old.get(target) {
+                        manifest.insert(target.to_owned(), previous.to_owned());
+                    }
+                    let _ = events.submit_command(SYNC_PROGRESS, (i + 1, total), Target::Auto);
+                    continue;
+                }
+            }
+        }
+        manifest.insert(target.to_owned(), entry);
+        let _ = events.submit_command(SYNC_PROGRESS, (i + 1, total), Target::Auto);
+    }
+
+    if cancel.load(Ordering::Relaxed) {
+        let done = copied + converted + unchanged;
+        // 取消时保留旧清单中还没有处理的项，下次同步时再删除
+        for (target, entry) in old {
+            manifest.entry(target).or_insert(entry);
+        }
+        save_manifest(dest, &manifest)?;
+        return Ok(format!("已取消同步，完成 {}/{} 首", done, total));
+    }
+
+    for playlist in playlists {
+        let entries: Vec<(String, &Song)> = playlist
+            .songs
+            .iter()
+            .filter_map(|s| {
+                let target = targets.get(&s.file)?;
+                manifest
+                    .contains_key(target)
+                    .then(|| (target.to_owned(), s))
+            })
+            .collect();
+        match write_m3u(dest, &playlist.name, &entries) {
+            Ok(name) => {
+                let entry = Entry {
+                    source: "".to_owned(),
+                    mtime: 0,
+                    size: 0,
+                    mode: "m3u".to_owned(),
+                };
+                manifest.insert(name, entry);
+            }
+            Err(e) => errors.push(e),
+        }
+    }
+
+    // 删除清单中有但这次不再需要的文件
+    let removed = orphaned(&old, &manifest);
+    for target in removed.iter() {
+        remove_synced(dest, target);
+    }
+    save_manifest(dest, &manifest)?;
+    for e in errors.iter() {
+        println!("同步失败 {}", e);
+    }
+    Ok(format!(
+        "同步完成: 复制 {} 首，转换 {} 首，未变化 {} 首，删除 {} 个文件，失败 {} 个",
+        copied,
+        converted,
+        unchanged,
+        removed.len(),
+        errors.len()
+    ))
+}
+
+// 在后台同步，完成后通知界面
+pub fn sync_async(
+    playlists: Vec<SyncPlaylist>,
+    dest: String,
+    roots: Vector<String>,
+    when: SyncTranscode,
+    settings: ExportSettings,
+    cancel: Arc<AtomicBool>,
+    events: ExtEventSink,
+) {
+    spawn(move || {
+        let message = sync(
+            &playlists,
+            Path::new(&dest),
+            &roots,
+            when,
+            &settings,
+            &cancel,
+            &events,
+        )
+        .unwrap_or_else(|e| format!("同步失败 {}", e));
+        let _ = events.submit_command(SYNC_DONE, message, Target::Auto);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(source: &str) -> Entry {
+        Entry {
+            source: source.to_owned(),
+            mtime: 1,
+            size: 2,
+            mode: "copy".to_owned(),
+        }
+    }
+
+    fn manifest(targets: &[&str]) -> HashMap<String, Entry> {
+        targets
+            .iter()
+            .map(|t| (t.to_string(), entry(&format!("/music/{}", t))))
+            .collect()
+    }
+
+    #[test]
+    fn parse_lines() {
+        let text = "A/b.flac\t/music/A/b.flac\t100\t200\tcopy\n\
+                    x.m3u8\t\t0\t0\tm3u\n\
+                    broken line\n\
+                    c.mp3\t/music/c.flac\tnot a number\t1\tcopy\n";
+        let m = parse_manifest(text);
+        assert_eq!(m.len(), 2);
+        assert_eq!(
+            m["A/b.flac"],
+            Entry {
+                source: "/music/A/b.flac".to_owned(),
+                mtime: 100,
+                size: 200,
+                mode: "copy".to_owned(),
+            }
+        );
+        assert_eq!(m["x.m3u8"].mode, "m3u");
+    }
+
+    #[test]
+    fn parse_rejects_paths_outside_dest() {
+        let text = "/etc/passwd\t/a\t1\t1\tcopy\n\
+                    ../outside.flac\t/a\t1\t1\tcopy\n\
+                    A/../../outside.flac\t/a\t1\t1\tcopy\n\
+                    ./a.flac\t/a\t1\t1\tcopy\n\
+                    \t/a\t1\t1\tcopy\n\
+                    ok.flac\t/a\t1\t1\tcopy\n";
+        let m = parse_manifest(text);
+        assert_eq!(m.keys().collect::<Vec<_>>(), vec!["ok.flac"]);
+    }
+
+    #[test]
+    fn remove_outside_dest_is_ignored() {
+        let dir = std::env::temp_dir().join(format!("flac_music_sync_{}", std::process::id()));
+        let dest = dir.join("device");
+        fs::create_dir_all(dest.join("A")).expect("create test dir");
+        fs::write(dir.join("outside.flac"), b"").expect("write test file");
+        fs::write(dest.join("A").join("b.flac"), b"").expect("write test file");
+        remove_synced(&dest, "../outside.flac");
+        remove_synced(&dest, "A/b.flac");
+        let outside_kept = dir.join("outside.flac").is_file();
+        let empty_dir_removed = !dest.join("A").exists();
+        let dest_kept = dest.is_dir();
+        let _ = fs::remove_dir_all(&dir);
+        assert!(outside_kept);
+        assert!(empty_dir_removed);
+        assert!(dest_kept);
+    }
+
+    #[test]
+    fn unique_target_suffix() {
+        let mut used = HashSet::new();
+        assert_eq!(unique_target("A/b.flac".to_owned(), &used), "A/b.flac");
+        used.insert(organize::collision_key("A/b.flac"));
+        assert_eq!(unique_target("a/B.FLAC".to_owned(), &used), "a/B (2).FLAC");
+        used.insert(organize::collision_key("a/B (2).FLAC"));
+        assert_eq!(unique_target("A/b.flac".to_owned(), &used), "A/b (3).flac");
+        used.insert(organize::collision_key("noext"));
+        assert_eq!(unique_target("noext".to_owned(), &used), "noext (2)");
+        used.insert(organize::collision_key(".hidden"));
+        assert_eq!(unique_target(".hidden".to_owned(), &used), ".hidden (2)");
+    }
+
+    #[test]
+    fn orphaned_ignores_case() {
+        let old = manifest(&["A/b.flac", "c.flac", "list.m3u8"]);
+        let new = manifest(&["a/B.flac", "list.m3u8", "d.flac"]);
+        assert_eq!(orphaned(&old, &new), vec!["c.flac"]);
+        assert!(orphaned(&HashMap::new(), &new).is_empty());
+    }
+
+    #[test]
+    fn offline_songs_are_kept() {
+        let dir = std::env::temp_dir().join(format!("flac_music_offline_{}", std::process::id()));
+        fs::create_dir_all(&dir).expect("create test dir");
+        let present = dir.join("present.flac").display().to_string();
+        fs::write(&present, b"").expect("write test file");
+        let missing = dir.join("missing.flac").display().to_string();
+        let songs = vec![
+            Song {
+                file: present.to_owned(),
+                ..Song::default()
+            },
+            Song {
+                file: missing.to_owned(),
+                ..Song::default()
+            },
+        ];
+        let mut old = HashMap::new();
+        old.insert("present.flac".to_owned(), entry(&present));
+        old.insert("missing.flac".to_owned(), entry(&missing));
+        old.insert("removed.flac".to_owned(), entry("/music/removed.flac"));
+        let kept = offline_entries(songs.iter(), &old);
+        let _ = fs::remove_dir_all(&dir);
+        assert_eq!(kept, vec![("missing.flac".to_owned(), entry(&missing))]);
+
+        // 保留的项不会被当作多余的文件删除
+        let mut new: HashMap<String, Entry> = kept.into_iter().collect();
+        new.insert("present.flac".to_owned(), entry(&present));
+        assert_eq!(orphaned(&old, &new), vec!["removed.flac"]);
+    }
+}
@@ -0,0 +1,457 @@
+use crate::lyrics::{be32, syncsafe};
+use druid::{Data, ExtEventSink, Lens, Selector, Target};
+use ffmpeg::format::stream::Disposition;
+use ffmpeg::{codec, encoder, format, media, Dictionary, DictionaryRef, Rational};
+use ffmpeg_next as ffmpeg;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::thread::spawn;
+
+// 标签写入完成，(文件, 结果)
+pub const TAGS_WRITTEN: Selector<(String, Result<(), String>)> =
+    Selector::new("flac-music.tags-written");
+
+// 标签编辑器中的一个字段。多首歌曲的值不同时 mixed 为 true，keep 表示保留各自原有的值
+#[derive(Clone, Data, Lens, Debug, Default, PartialEq)]
+pub struct TagField {
+    // ffmpeg 的通用标签名，写入时由 ffmpeg 转换为各格式的名称
+    pub key: String,
+    pub value: String,
+    pub original: String,
+    pub mixed: bool,
+    pub keep: bool,
+}
+
+impl TagField {
+    pub fn new(key: &str, values: &[&str]) -> TagField {
+        let first = values.first().copied().unwrap_or("");
+        let mixed = values.iter().any(|v| *v != first);
+        let value = if mixed { "" } else { first };
+        TagField {
+            key: key.to_owned(),
+            value: value.to_owned(),
+            original: value.to_owned(),
+            mixed,
+            keep: mixed,
+        }
+    }
+
+    // 需要写入的字段：单首歌曲只写修改过的，混合值取消"保留原值"后写入
+    pub fn changed(&self) -> bool {
+        if self.mixed {
+            !self.keep
+        } else {
+            self.value != self.original
+        }
+    }
+}
+
+// 统一标签名，各格式中 album_artist, ALBUMARTIST, TRACKNUMBER 等写法不同
+fn canonical(key: &str) -> String {
+    let key: String = key
+        .to_lowercase()
+        .chars()
+        .filter(|c| *c != '_' && *c != ' ')
+        .collect();
+    match key.as_str() {
+        "tracknumber" => "track".to_owned(),
+        "discnumber" => "disc".to_owned(),
+        _ => key,
+    }
+}
+
+// 是否为要替换的标签，ID3 的 lyrics-eng 等带语言后缀的也算
+fn same_key(existing: &str, key: &str) -> bool {
+    let existing = canonical(existing);
+    let key = canonical(key);
+    existing == key || existing.starts_with(&format!("{}-", key))
+}
+
+// 复制原有标签并替换 tags 中的项，值为空表示删除该标签
+fn updated(metadata: &DictionaryRef, tags: &[(String, String)]) -> Dictionary<'static> {
+    let mut result = Dictionary::new();
+    for (k, v) in metadata.iter() {
+        if !tags.iter().any(|(key, _)| same_key(k, key)) {
+            result.set(k, v);
+        }
+    }
+    for (key, value) in tags {
+        if !value.is_empty() {
+            result.set(key, value);
+        }
+    }
+    result
+}
+
+// 用 ffmpeg 重新封装文件来写入标签，音频数据直接复制不重新编码。
+// 先写到同目录的临时文件，成功后再替换原文件。
+pub fn write_tags(file: &str, tags: &[(String, String)]) -> Result<(), String> {
+    write_tags_and_cover(file, tags, None)
+}
+
+// 同时用 cover 图片替换内嵌的封面，不支持内嵌封面的格式只写标签
+pub fn write_tags_and_cover(
+    file: &str,
+    tags: &[(String, String)],
+    cover: Option<&Path>,
+) -> Result<(), String> {
+    ffmpeg::init().map_err(|e| e.to_string())?;
+    let path = Path::new(file);
+    let ext = path
+        .extension()
+        .map(|e| e.to_string_lossy().to_string())
+        .ok_or_else(|| "文件没有扩展名，无法确定格式".to_owned())?;
+    let stem = path
+        .file_stem()
+        .map_or("".to_owned(), |s| s.to_string_lossy().to_string());
+    // 保留扩展名，ffmpeg 按扩展名选择封装格式
+    let tmp = path.with_file_name(format!(".{}.tagging.{}", stem, ext));
+    let extra = extra_id3_frames(file)?;
+    let result = remux(file, &tmp, tags, cover, extra.as_ref().map(|(v, _)| *v))
+        .and_then(|_| match extra.as_ref() {
+            Some((version, frames)) => restore_id3_frames(&tmp, *version, frames),
+            None => Ok(()),
+        })
+        .and_then(|_| fs::rename(&tmp, path).map_err(|e| e.to_string()));
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+    result
+}
+
+// ID3v2.3/2.4 标签
+#[derive(Debug, PartialEq)]
+struct Id3Tag {
+    version: u8,
+    // 整个标签使用了不同步编码，帧内容不能直接复制到其他标签中
+    unsynchronised: bool,
+    // 每一帧的帧头和内容
+    frames: Vec<Vec<u8>>,
+    // 标签在文件中结束的位置
+    end: usize,
+}
+
+fn parse_id3(data: &[u8]) -> Option<Id3Tag> {
+    if data.len() < 10 || &data[0..3] != b"ID3" || !(3..=4).contains(&data[3]) {
+        return None;
+    }
+    let version = data[3];
+    let flags = data[5];
+    let size = syncsafe(&data[6..10]);
+    let tag = data.get(10..10 + size)?;
+    let footer = if version == 4 && flags & 0x10 != 0 {
+        10
+    } else {
+        0
+    };
+    let mut pos = 0;
+    // 跳过扩展头
+    if flags & 0x40 != 0 && tag.len() >= 4 {
+        pos = if version == 4 {
+            syncsafe(&tag[0..4])
+        } else {
+            be32(&tag[0..4]) + 4
+        };
+    }
+    let mut frames = vec![];
+    while pos + 10 <= tag.len() && tag[pos] != 0 {
+        let frame_size = if version == 4 {
+            syncsafe(&tag[pos + 4..pos + 8])
+        } else {
+            be32(&tag[pos + 4..pos + 8])
+        };
+        frames.push(tag.get(pos..pos + 10 + frame_size)?.to_vec());
+        pos += 10 + frame_size;
+    }
+    Some(Id3Tag {
+        version,
+        unsynchronised: flags & 0x80 != 0,
+        frames,
+        end: 10 + size + footer,
+    })
+}
+
+// 文件开头的 ID3 标签数据
+fn read_id3(file: &str) -> Option<Vec<u8>> {
+    let mut f = File::open(file).ok()?;
+    let mut data = vec![0u8; 10];
+    f.read_exact(&mut data).ok()?;
+    if &data[0..3] != b"ID3" {
+        return None;
+    }
+    data.resize(10 + syncsafe(&data[6..10]), 0);
+    f.read_exact(&mut data[10..]).ok()?;
+    Some(data)
+}
+
+// ffmpeg 读取并会重新写入的帧：文本，注释，歌词，封面和私有数据。
+// SYLT 同步歌词、链接、章节等其他帧重新封装后会丢失
+fn kept_by_ffmpeg(id: &[u8]) -> bool {
+    id.first() == Some(&b'T')
+        || [b"COMM", b"USLT", b"APIC", b"PRIV"]
+            .iter()
+            .any(|k| id == &k[..])
+}
+
+// 原文件 ID3 标签中 ffmpeg 不保留的帧，(版本, 帧)
+fn extra_id3_frames(file: &str) -> Result<Option<(u8, Vec<Vec<u8>>)>, String> {
+    let tag = match read_id3(file).as_deref().and_then(parse_id3) {
+        Some(t) => t,
+        None => return Ok(None),
+    };
+    let frames: Vec<Vec<u8>> = tag
+        .frames
+        .into_iter()
+        .filter(|f| !kept_by_ffmpeg(&f[0..4]))
+        .collect();
+    if frames.is_empty() {
+        return Ok(None);
+    }
+    if tag.unsynchronised {
+        return Err("ID3 标签使用了不同步编码，修改标签会丢失 SYLT 等帧".to_owned());
+    }
+    Ok(Some((tag.version, frames)))
+}
+
+// 把 frames 加到 data 开头的 ID3 标签中，标签版本需要相同
+fn append_id3_frames(data: &[u8], version: u8, frames: &[Vec<u8>]) -> Option<Vec<u8>> {
+    let tag = parse_id3(data).filter(|t| t.version == version && !t.unsynchronised)?;
+    let body: Vec<u8> = tag.frames.iter().chain(frames).flatten().copied().collect();
+    let size = body.len();
+    let mut result = b"ID3".to_vec();
+    result.extend([version, 0, 0]);
+    result.extend((0..4).rev().map(|i| ((size >> (i * 7)) & 0x7f) as u8));
+    result.extend(body);
+    result.extend(data.get(tag.end..)?);
+    Some(result)
+}
+
+// 把原文件中 ffmpeg 不保留的帧写回重新封装后的文件
+fn restore_id3_frames(tmp: &Path, version: u8, frames: &[Vec<u8>]) -> Result<(), String> {
+    let data = fs::read(tmp).map_err(|e| e.to_string())?;
+    let data = append_id3_frames(&data, version, frames)
+        .ok_or_else(|| "无法保留 ID3 标签中的 SYLT 等帧".to_owned())?;
+    fs::write(tmp, data).map_err(|e| e.to_string())
+}
+
+// 封装格式是否支持 ATTACHED_PIC 封面
+fn supports_cover(format_name: &str) -> bool {
+    ["mp3", "flac", "mp4", "ipod", "mov"]
+        .iter()
+        .any(|n| format_name.contains(n))
+}
+
+// id3_version 不为空时按该版本写入 ID3 标签，之后才能把原来的帧复制回去
+fn remux(
+    file: &str,
+    tmp: &Path,
+    tags: &[(String, String)],
+    cover: Option<&Path>,
+    id3_version: Option<u8>,
+) -> Result<(), String> {
+    let mut input = format::input(&Path::new(file)).map_err(|e| e.to_string())?;
+    let mut output = format::output(&tmp).map_err(|e| e.to_string())?;
+    let format_name = output.format().name().to_owned();
+    if id3_version.is_some() && format_name != "mp3" {
+        return Err("ffmpeg 写入这种格式时会丢失 ID3 标签中的 SYLT 等帧".to_owned());
+    }
+    // Ogg 和 Opus 的标签保存在音频流中
+    let stream_tags = format_name.contains("ogg") || format_name.contains("opus");
+    // 图片文件由 ffmpeg 的 image2 按一个视频帧读取
+    let mut cover_input = match cover {
+        Some(path) if supports_cover(output.format().name()) => {
+            Some(format::input(&path).map_err(|e| format!("无法读取封面: {}", e))?)
+        }
+        _ => None,
+    };
+    let mut mapping = vec![-1i32; input.nb_streams() as usize];
+    let mut time_bases = vec![Rational(0, 1); input.nb_streams() as usize];
+    let mut next_index = 0;
+    for (index, stream) in input.streams().enumerate() {
+        let medium = stream.parameters().medium();
+        // 专辑封面是视频流，一起复制
+        if medium != media::Type::Audio && medium != media::Type::Video {
+            continue;
+        }
+        // 替换封面时不复制原来的封面
+        if cover_input.is_some() && stream.disposition().contains(Disposition::ATTACHED_PIC) {
+            continue;
+        }
+        mapping[index] = next_index;
+        time_bases[index] = stream.time_base();
+        next_index += 1;
+        let mut out_stream = output
+            .add_stream(encoder::find(codec::Id::None))
+            .map_err(|e| e.to_string())?;
+        out_stream.set_parameters(stream.parameters());
+        if stream_tags && medium == media::Type::Audio {
+            out_stream.set_metadata(updated(&stream.metadata(), tags));
+        } else {
+            out_stream.set_metadata(stream.metadata().to_owned());
+        }
+        unsafe {
+            (*out_stream.parameters().as_mut_ptr()).codec_tag = 0;
+            (*out_stream.as_mut_ptr()).disposition = (*stream.as_ptr()).disposition;
+        }
+    }
+    let mut cover_index = None;
+    if let Some(cover_input) = cover_input.as_ref() {
+        let stream = cover_input
+            .streams()
+            .best(media::Type::Video)
+            .ok_or_else(|| "封面文件不是图片".to_owned())?;
+        let mut out_stream = output
+            .add_stream(encoder::find(codec::Id::None))
+            .map_err(|e| e.to_string())?;
+        out_stream.set_parameters(stream.parameters());
+        unsafe {
+            (*out_stream.parameters().as_mut_ptr()).codec_tag = 0;
+            (*out_stream.as_mut_ptr()).disposition = ffmpeg::ffi::AV_DISPOSITION_ATTACHED_PIC;
+        }
+        cover_index = Some(out_stream.index());
+    }
+    output.set_metadata(updated(&input.metadata(), tags));
+    let mut options = Dictionary::new();
+    if let Some(version) = id3_version {
+        options.set("id3v2_version", &version.to_string());
+    }
+    output
+        .write_header_with(options)
+        .map_err(|e| e.to_string())?;
+    // 封面只有一个数据包，在音频之前写入
+    if let (Some(cover_input), Some(index)) = (cover_input.as_mut(), cover_index) {
+        if let Some((_, mut packet)) = cover_input.packets().next() {
+            packet.set_stream(index);
+            packet.set_pts(Some(0));
+            packet.set_dts(Some(0));
+            packet.set_position(-1);
+            packet
+                .write_interleaved(&mut output)
+                .map_err(|e| e.to_string())?;
+        }
+    }
+    for (stream, mut packet) in input.packets() {
+        let index = stream.index();
+        let out_index = mapping[index];
+        if out_index < 0 {
+            continue;
+        }
+        let out_time_base = output
+            .stream(out_index as usize)
+            .map(|s| s.time_base())
+            .unwrap_or(time_bases[index]);
+        packet.rescale_ts(time_bases[index], out_time_base);
+        packet.set_position(-1);
+        packet.set_stream(out_index as usize);
+        packet
+            .write_interleaved(&mut output)
+            .map_err(|e| e.to_string())?;
+    }
+    output.write_trailer().map_err(|e| e.to_string())
+}
+
+// 后台依次写入多个文件的标签，每个文件完成后通知界面
+pub fn write_tags_async(jobs: Vec<(String, Vec<(String, String)>)>, events: ExtEventSink) {
+    write_album_async(jobs, None, events);
+}
+
+// 写入标签，cover 不为空时同时替换每个文件的内嵌封面
+pub fn write_album_async(
+    jobs: Vec<(String, Vec<(String, String)>)>,
+    cover: Option<PathBuf>,
+    events: ExtEventSink,
+) {
+    spawn(move || {
+        for (file, tags) in jobs {
+            let result = write_tags_and_cover(&file, &tags, cover.as_deref());
+            let _ = events.submit_command(TAGS_WRITTEN, (file, result), Target::Auto);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(id: &[u8], body: &[u8], version: u8) -> Vec<u8> {
+        let size = body.len();
+        let mut f = id.to_vec();
+        if version == 4 {
+            f.extend((0..4).rev().map(|i| ((size >> (i * 7)) & 0x7f) as u8));
+        } else {
+            f.extend((size as u32).to_be_bytes());
+        }
+        f.extend([0, 0]);
+        f.extend(body);
+        f
+    }
+
+    fn tag(version: u8, frames: &[Vec<u8>], padding: usize) -> Vec<u8> {
+        let size = frames.iter().map(|f| f.len()).sum::<usize>() + padding;
+        let mut data = b"ID3".to_vec();
+        data.extend([version, 0, 0]);
+        data.extend((0..4).rev().map(|i| ((size >> (i * 7)) & 0x7f) as u8));
+        data.extend(frames.iter().flatten());
+        data.extend(vec![0; padding]);
+        data
+    }
+
+    #[test]
+    fn parse_frames() {
+        let title = frame(b"TIT2", b"\x03Song", 4);
+        let sylt = frame(b"SYLT", b"\x03eng\x02\x01\x00a\x00\x00\x00\x00\x00", 4);
+        let mut data = tag(4, &[title.to_owned(), sylt.to_owned()], 5);
+        let end = data.len();
+        data.extend(b"\xff\xfbaudio");
+        let parsed = parse_id3(&data).unwrap();
+        assert_eq!(
+            parsed,
+            Id3Tag {
+                version: 4,
+                unsynchronised: false,
+                frames: vec![title, sylt],
+                end,
+            }
+        );
+        assert!(parse_id3(b"\xff\xfbaudio").is_none());
+        // ID3v2.2 的帧头格式不同
+        assert!(parse_id3(&tag(2, &[], 0)).is_none());
+    }
+
+    #[test]
+    fn parse_v23_sizes() {
+        // ID3v2.3 的帧大小不是 syncsafe 整数
+        let body = vec![b'x'; 200];
+        let geob = frame(b"GEOB", &body, 3);
+        let parsed = parse_id3(&tag(3, &[geob.to_owned()], 0)).unwrap();
+        assert_eq!(parsed.frames, vec![geob]);
+    }
+
+    #[test]
+    fn frames_ffmpeg_drops() {
+        assert!(kept_by_ffmpeg(b"TIT2"));
+        assert!(kept_by_ffmpeg(b"TXXX"));
+        assert!(kept_by_ffmpeg(b"USLT"));
+        assert!(kept_by_ffmpeg(b"APIC"));
+        assert!(!kept_by_ffmpeg(b"SYLT"));
+        assert!(!kept_by_ffmpeg(b"WXXX"));
+        assert!(!kept_by_ffmpeg(b"CHAP"));
+        assert!(!kept_by_ffmpeg(b"POPM"));
+    }
+
+    #[test]
+    fn append_keeps_audio() {
+        let title = frame(b"TIT2", b"\x03New", 4);
+        let sylt = frame(b"SYLT", b"\x03eng\x02\x01\x00a\x00\x00\x00\x00\x00", 4);
+        let mut data = tag(4, &[title.to_owned()], 10);
+        data.extend(b"\xff\xfbaudio");
+        let merged = append_id3_frames(&data, 4, &[sylt.to_owned()]).unwrap();
+        let parsed = parse_id3(&merged).unwrap();
+        assert_eq!(parsed.frames, vec![title, sylt]);
+        assert_eq!(&merged[parsed.end..], b"\xff\xfbaudio");
+        // 版本不同时帧头格式不同，不能直接复制
+        assert!(append_id3_frames(&data, 3, &[]).is_none());
+        assert!(append_id3_frames(b"\xff\xfbaudio", 4, &[]).is_none());
+    }
+}
@@ -0,0 +1,694 @@
+use crate::organize;
+use crate::player;
+use druid::{Data, ExtEventSink, Selector, Target};
+use ffmpeg::format::sample::Type as SampleType;
+use ffmpeg::format::stream::Disposition;
+use ffmpeg::format::Sample as SampleFormat;
+use ffmpeg::{codec, encoder, filter, format, frame, media, ChannelLayout, Dictionary, Rational};
+use ffmpeg_next as ffmpeg;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{available_parallelism, spawn};
+
+// 导出进度，(已处理, 总数)
+pub const EXPORT_PROGRESS: Selector<(usize, usize)> = Selector::new("flac-music.export-progress");
+// 导出结束，结果说明
+pub const EXPORT_DONE: Selector<String> = Selector::new("flac-music.export-done");
+
+// 导出格式
+#[derive(Clone, Copy, Data, PartialEq, Debug, Default, Serialize, Deserialize)]
+pub enum ExportFormat {
+    // LAME VBR 最高质量
+    #[default]
+    #[serde(rename = "mp3_v0")]
+    Mp3V0,
+    #[serde(rename = "mp3_320")]
+    Mp3Cbr320,
+    #[serde(rename = "aac")]
+    Aac,
+    #[serde(rename = "opus")]
+    Opus,
+    #[serde(rename = "flac")]
+    Flac,
+}
+
+impl ExportFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Mp3V0 | ExportFormat::Mp3Cbr320 => "mp3",
+            ExportFormat::Aac => "m4a",
+            ExportFormat::Opus => "opus",
+            ExportFormat::Flac => "flac",
+        }
+    }
+
+    fn encoder_name(&self) -> &'static str {
+        match self {
+            ExportFormat::Mp3V0 | ExportFormat::Mp3Cbr320 => "libmp3lame",
+            ExportFormat::Aac => "aac",
+            ExportFormat::Opus => "libopus",
+            ExportFormat::Flac => "flac",
+        }
+    }
+
+    // 固定码率，VBR 和无损为 None
+    fn bit_rate(&self) -> Option<usize> {
+        match self {
+            ExportFormat::Mp3Cbr320 => Some(320_000),
+            ExportFormat::Aac => Some(256_000),
+            ExportFormat::Opus => Some(160_000),
+            _ => None,
+        }
+    }
+}
+
+// 导出设置，sample_rate 和 bit_depth 为 0 表示和原文件相同
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExportSettings {
+    pub format: ExportFormat,
+    pub sample_rate: u32,
+    pub bit_depth: u16,
+}
+
+// 实际使用的采样率：Opus 只支持 48kHz，MP3 和 AAC 最高 48kHz，高于 48kHz 时按倍数关系降到 44.1kHz 或 48kHz
+fn output_rate(format: ExportFormat, requested: u32, source: u32) -> u32 {
+    let rate = if requested > 0 { requested } else { source };
+    match format {
+        ExportFormat::Opus => 48000,
+        ExportFormat::Flac => rate,
+        _ if rate > 48000 && rate % 44100 == 0 => 44100,
+        _ if rate > 48000 => 48000,
+        _ => rate,
+    }
+}
+
+// 编码器的采样格式，FLAC 按位深选择 16 位或 32 位(24 位有效)
+fn sample_format(
+    codec: &codec::Audio,
+    format: ExportFormat,
+    bits: u16,
+) -> Result<SampleFormat, String> {
+    let formats: Vec<SampleFormat> = codec
+        .formats()
+        .ok_or_else(|| "编码器没有可用的采样格式".to_owned())?
+        .collect();
+    if format == ExportFormat::Flac {
+        let wanted = if bits > 16 {
+            SampleFormat::I32(SampleType::Packed)
+        } else {
+            SampleFormat::I16(SampleType::Packed)
+        };
+        if formats.contains(&wanted) {
+            return Ok(wanted);
+        }
+    }
+    formats
+        .first()
+        .copied()
+        .ok_or_else(|| "编码器没有可用的采样格式".to_owned())
+}
+
+// 封装格式自动写入的信息，不从原文件复制
+fn skip_tag(key: &str) -> bool {
+    let key = key.to_lowercase();
+    [
+        "encoder",
+        "handler_name",
+        "vendor_id",
+        "metadata_block_picture",
+    ]
+    .contains(&key.as_str())
+}
+
+// 解码，转换采样格式和采样率，再编码
+struct Pipeline {
+    decoder: codec::decoder::Audio,
+    layout: ChannelLayout,
+    graph: filter::Graph,
+    encoder: codec::encoder::Audio,
+    stream: usize,
+    // 编码器按采样数计时，写入时换算为输出流的时间基
+    encoder_time_base: Rational,
+    stream_time_base: Rational,
+    next_pts: i64,
+}
+
+impl Pipeline {
+    fn decode(&mut self, output: &mut format::context::Output) -> Result<(), String> {
+        let mut decoded = frame::Audio::empty();
+        while self.decoder.receive_frame(&mut decoded).is_ok() {
+            if decoded.channel_layout().is_empty() {
+                decoded.set_channel_layout(self.layout);
+            }
+            let timestamp = decoded.timestamp();
+            decoded.set_pts(timestamp);
+            if let Some(mut source) = self.graph.get("in") {
+                source.source().add(&decoded).map_err(|e| e.to_string())?;
+            }
+            self.filter(output)?;
+        }
+        Ok(())
+    }
+
+    fn filter(&mut self, output: &mut format::context::Output) -> Result<(), String> {
+        let mut filtered = frame::Audio::empty();
+        while let Some(mut sink) = self.graph.get("out") {
+            if sink.sink().frame(&mut filtered).is_err() {
+                break;
+            }
+            filtered.set_pts(Some(self.next_pts));
+            self.next_pts += filtered.samples() as i64;
+            self.encoder
+                .send_frame(&filtered)
+                .map_err(|e| e.to_string())?;
+            self.encode(output)?;
+        }
+        Ok(())
+    }
+
+    fn encode(&mut self, output: &mut format::context::Output) -> Result<(), String> {
+        let mut packet = ffmpeg::Packet::empty();
+        while self.encoder.receive_packet(&mut packet).is_ok() {
+            packet.set_stream(self.stream);
+            packet.rescale_ts(self.encoder_time_base, self.stream_time_base);
+            packet
+                .write_interleaved(output)
+                .map_err(|e| e.to_string())?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self, output: &mut format::context::Output) -> Result<(), String> {
+        let _ = self.decoder.send_eof();
+        self.decode(output)?;
+        if let Some(mut source) = self.graph.get("in") {
+            source.source().flush().map_err(|e| e.to_string())?;
+        }
+        self.filter(output)?;
+        self.encoder.send_eof().map_err(|e| e.to_string())?;
+        self.encode(output)
+    }
+}
+
+// 音频缓冲到编码器的滤镜，由 ffmpeg 自动插入采样格式和采样率转换
+fn filter_graph(
+    decoder: &codec::decoder::Audio,
+    layout: ChannelLayout,
+    time_base: Rational,
+    encoder: &codec::encoder::Audio,
+) -> Result<filter::Graph, String> {
+    let mut graph = filter::Graph::new();
+    let args = format!(
+        "time_base={}:sample_rate={}:sample_fmt={}:channel_layout=0x{:x}",
+        time_base,
+        decoder.rate(),
+        decoder.format().name(),
+        layout.bits()
+    );
+    let abuffer = filter::find("abuffer").ok_or_else(|| "ffmpeg 没有 abuffer 滤镜".to_owned())?;
+    let abuffersink =
+        filter::find("abuffersink").ok_or_else(|| "ffmpeg 没有 abuffersink 滤镜".to_owned())?;
+    graph
+        .add(&abuffer, "in", &args)
+        .map_err(|e| e.to_string())?;
+    graph
+        .add(&abuffersink, "out", "")
+        .map_err(|e| e.to_string())?;
+    if let Some(mut out) = graph.get("out") {
+        out.set_sample_format(encoder.format());
+        out.set_channel_layout(encoder.channel_layout());
+        out.set_sample_rate(encoder.rate());
+    }
+    graph
+        .output("in", 0)
+        .and_then(|p| p.input("out", 0))
+        .and_then(|p| p.parse("anull"))
+        .map_err(|e| e.to_string())?;
+    graph.validate().map_err(|e| e.to_string())?;
+    // 固定帧长的编码器(MP3, AAC, Opus)需要按帧长切分
+    if let Some(codec) = encoder.codec() {
+        if !codec
+            .capabilities()
+            .contains(codec::capabilities::Capabilities::VARIABLE_FRAME_SIZE)
+        {
+            if let Some(mut out) = graph.get("out") {
+                out.sink().set_frame_size(encoder.frame_size());
+            }
+        }
+    }
+    Ok(graph)
+}
+
+fn encode_file(
+    file: &str,
+    tmp: &Path,
+    settings: &ExportSettings,
+    cancel: &AtomicBool,
+) -> Result<(), String> {
+    let mut input = format::input(&Path::new(file)).map_err(|e| e.to_string())?;
+    let mut output = format::output(&tmp).map_err(|e| e.to_string())?;
+    let stream = input
+        .streams()
+        .best(media::Type::Audio)
+        .ok_or_else(|| "没有音频流".to_owned())?;
+    let audio_index = stream.index();
+    let in_time_base = stream.time_base();
+    let decoder = codec::context::Context::from_parameters(stream.parameters())
+        .and_then(|c| c.decoder().audio())
+        .map_err(|e| e.to_string())?;
+    // 部分格式没有声道布局，按声道数取默认布局
+    let layout = if decoder.channel_layout().is_empty() {
+        ChannelLayout::default(decoder.channels() as i32)
+    } else {
+        decoder.channel_layout()
+    };
+    // 标签保存在容器或音频流中(Ogg)，合并后写入
+    let mut tags = Dictionary::new();
+    let mut keys = HashSet::new();
+    for (k, v) in input.metadata().iter().chain(stream.metadata().iter()) {
+        if !skip_tag(k) && keys.insert(k.to_lowercase()) {
+            tags.set(k, v);
+        }
+    }
+    let source_bits = player::probe_format(file).map_or(16, |f| f.bits);
+    let bits = if settings.bit_depth > 0 {
+        settings.bit_depth
+    } else {
+        source_bits.clamp(16, 24)
+    };
+    let rate = output_rate(settings.format, settings.sample_rate, decoder.rate());
+
+    let codec = encoder::find_by_name(settings.format.encoder_name())
+        .ok_or_else(|| format!("ffmpeg 没有 {} 编码器", settings.format.encoder_name()))?
+        .audio()
+        .map_err(|e| e.to_string())?;
+    let global_header = output
+        .format()
+        .flags()
+        .contains(format::flag::Flags::GLOBAL_HEADER);
+    let ogg = output.format().name().contains("ogg") || output.format().name().contains("opus");
+    let mut out_stream = output.add_stream(codec).map_err(|e| e.to_string())?;
+    let stream_index = out_stream.index();
+    let mut context = codec::context::Context::from_parameters(out_stream.parameters())
+        .and_then(|c| c.encoder().audio())
+        .map_err(|e| e.to_string())?;
+    let out_layout = codec
+        .channel_layouts()
+        .map_or(layout, |layouts| layouts.best(layout.channels()));
+    let mut flags = codec::flag::Flags::empty();
+    if global_header {
+        flags |= codec::flag::Flags::GLOBAL_HEADER;
+    }
+    if settings.format == ExportFormat::Mp3V0 {
+        flags |= codec::flag::Flags::QSCALE;
+    }
+    context.set_flags(flags);
+    context.set_rate(rate as i32);
+    context.set_channel_layout(out_layout);
+    context.set_channels(out_layout.channels());
+    context.set_format(sample_format(&codec, settings.format, bits)?);
+    context.set_time_base((1, rate as i32));
+    if let Some(bit_rate) = settings.format.bit_rate() {
+        context.set_bit_rate(bit_rate);
+    }
+    unsafe {
+        match settings.format {
+            // V0 对应 LAME 的 -q:a 0
+            ExportFormat::Mp3V0 => (*context.as_mut_ptr()).global_quality = 0,
+            ExportFormat::Flac if bits > 16 => {
+                (*context.as_mut_ptr()).bits_per_raw_sample = bits.min(24) as i32
+            }
+            _ => {}
+        }
+    }
+    out_stream.set_time_base((1, rate as i32));
+    let encoder = context.open_as(codec).map_err(|e| e.to_string())?;
+    out_stream.set_parameters(&encoder);
+    if ogg {
+        out_stream.set_metadata(tags.to_owned());
+    }
+
+    // 复制封面，Ogg 不支持封面流
+    let mut cover = None;
+    if !ogg {
+        if let Some(pic) = input
+            .streams()
+            .find(|s| s.disposition().contains(Disposition::ATTACHED_PIC))
+        {
+            let mut out = output
+                .add_stream(encoder::find(codec::Id::None))
+                .map_err(|e| e.to_string())?;
+            out.set_parameters(pic.parameters());
+            unsafe {
+                (*out.parameters().as_mut_ptr()).codec_tag = 0;
+                (*out.as_mut_ptr()).disposition = ffmpeg::ffi::AV_DISPOSITION_ATTACHED_PIC;
+            }
+            cover = Some((pic.index(), out.index()));
+        }
+    }
+    if !ogg {
+        output.set_metadata(tags);
+    }
+
+    let graph = filter_graph(&decoder, layout, in_time_base, &encoder)?;
+    output.write_header().map_err(|e| e.to_string())?;
+    let stream_time_base = output
+        .stream(stream_index)
+        .map_or(Rational(1, rate as i32), |s| s.time_base());
+    let mut pipeline = Pipeline {
+        decoder,
+        layout,
+        graph,
+        encoder,
+        stream: stream_index,
+        encoder_time_base: Rational(1, rate as i32),
+        stream_time_base,
+        next_pts: 0,
+    };
+    for (stream, mut packet) in input.packets() {
+        if cancel.load(Ordering::Relaxed) {
+            return Err("已取消".to_owned());
+        }
+        match cover {
+            Some((from, to)) if stream.index() == from => {
+                packet.set_stream(to);
+                packet.set_pts(Some(0));
+                packet.set_dts(Some(0));
+                packet.set_position(-1);
+                packet
+                    .write_interleaved(&mut output)
+                    .map_err(|e| e.to_string())?;
+                continue;
+            }
+            _ => {}
+        }
+        if stream.index() != audio_index {
+            continue;
+        }
+        // 损坏的数据包跳过，继续解码后面的部分
+        if pipeline.decoder.send_packet(&packet).is_err() {
+            continue;
+        }
+        pipeline.decode(&mut output)?;
+    }
+    pipeline.finish(&mut output)?;
+    output.write_trailer().map_err(|e| e.to_string())
+}
+
+// fpcalc 和 AcoustID 只使用开头两分钟
+const CHROMAPRINT_SECONDS: i64 = 120;
+
+// 用 ffmpeg 的 chromaprint 封装格式把文件的 Chromaprint 指纹写到 target，
+// 格式为原始的 32 位整数。需要 ffmpeg 编译时启用了 chromaprint
+pub fn chromaprint(file: &str, target: &Path) -> Result<(), String> {
+    ffmpeg::init().map_err(|e| e.to_string())?;
+    let mut input = format::input(&Path::new(file)).map_err(|e| e.to_string())?;
+    let mut output = format::output_as(&target, "chromaprint")
+        .map_err(|e| format!("ffmpeg 没有启用 chromaprint: {}", e))?;
+    let stream = input
+        .streams()
+        .best(media::Type::Audio)
+        .ok_or_else(|| "没有音频流".to_owned())?;
+    let audio_index = stream.index();
+    let in_time_base = stream.time_base();
+    let decoder = codec::context::Context::from_parameters(stream.parameters())
+        .and_then(|c| c.decoder().audio())
+        .map_err(|e| e.to_string())?;
+    let layout = if decoder.channel_layout().is_empty() {
+        ChannelLayout::default(decoder.channels() as i32)
+    } else {
+        decoder.channel_layout()
+    };
+    let rate = decoder.rate();
+    // chromaprint 只接受 16 位 PCM，自己降采样到 11025Hz
+    let codec = encoder::find(codec::Id::PCM_S16LE)
+        .ok_or_else(|| "ffmpeg 没有 pcm_s16le 编码器".to_owned())?
+        .audio()
+        .map_err(|e| e.to_string())?;
+    let mut out_stream = output.add_stream(codec).map_err(|e| e.to_string())?;
+    let stream_index = out_stream.index();
+    let mut context = codec::context::Context::from_parameters(out_stream.parameters())
+        .and_then(|c| c.encoder().audio())
+        .map_err(|e| e.to_string())?;
+    context.set_rate(rate as i32);
+    context.set_channel_layout(ChannelLayout::MONO);
+    context.set_channels(1);
+    context.set_format(SampleFormat::I16(SampleType::Packed));
+    context.set_time_base((1, rate as i32));
+    out_stream.set_time_base((1, rate as i32));
+    let encoder = context.open_as(codec).map_err(|e| e.to_string())?;
+    out_stream.set_parameters(&encoder);
+
+    let graph = filter_graph(&decoder, layout, in_time_base, &encoder)?;
+    let mut options = Dictionary::new();
+    options.set("fp_format", "raw");
+    output
+        .write_header_with(options)
+        .map_err(|e| e.to_string())?;
+    let stream_time_base = output
+        .stream(stream_index)
+        .map_or(Rational(1, rate as i32), |s| s.time_base());
+    let mut pipeline = Pipeline {
+        decoder,
+        layout,
+        graph,
+        encoder,
+        stream: stream_index,
+        encoder_time_base: Rational(1, rate as i32),
+        stream_time_base,
+        next_pts: 0,
+    };
+    for (stream, packet) in input.packets() {
+        if stream.index() != audio_index {
+            continue;
+        }
+        if pipeline.decoder.send_packet(&packet).is_err() {
+            continue;
+        }
+        pipeline.decode(&mut output)?;
+        if pipeline.next_pts >= CHROMAPRINT_SECONDS * rate as i64 {
+            break;
+        }
+    }
+    pipeline.finish(&mut output)?;
+    output.write_trailer().map_err(|e| e.to_string())
+}
+
+// 转换一个文件。先写到同目录的临时文件，完成后再改名，取消或失败时不留下不完整的文件
+pub fn transcode(
+    file: &str,
+    target: &Path,
+    settings: &ExportSettings,
+    cancel: &AtomicBool,
+) -> Result<(), String> {
+    ffmpeg::init().map_err(|e| e.to_string())?;
+    if let Some(dir) = target.parent() {
+        fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+    }
+    let stem = target
+        .file_stem()
+        .map_or("".to_owned(), |s| s.to_string_lossy().to_string());
+    // 保留扩展名，ffmpeg 按扩展名选择封装格式
+    let tmp = target.with_file_name(format!(
+        ".{}.exporting.{}",
+        stem,
+        settings.format.extension()
+    ));
+    let result = encode_file(file, &tmp, settings, cancel)
+        .and_then(|_| fs::rename(&tmp, target).map_err(|e| e.to_string()));
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+    result
+}
+
+// 导出后的路径：保持文件在资料库中的相对路径，扩展名换成导出格式的
+pub fn target_path(relative: &Path, dest: &Path, format: ExportFormat) -> PathBuf {
+    dest.join(relative).with_extension(format.extension())
+}
+
+// 不同的原文件可能得到相同的目标文件(例如 a.flac 和 a.wav 都导出为 a.mp3)，
+// 并行转换时会共用临时文件，在后面的文件名后加上序号。同一个原文件只导出一次
+pub fn unique_targets(jobs: Vec<(String, PathBuf)>) -> Vec<(String, PathBuf)> {
+    let mut sources = HashSet::new();
+    let mut used = HashSet::new();
+    jobs.into_iter()
+        .filter(|(file, _)| sources.insert(file.to_owned()))
+        .map(|(file, target)| {
+            let stem = target
+                .file_stem()
+                .map_or("".to_owned(), |s| s.to_string_lossy().to_string());
+            let ext = target
+                .extension()
+                .map_or("".to_owned(), |e| format!(".{}", e.to_string_lossy()));
+            let mut candidate = target.to_owned();
+            let mut n = 2;
+            while !used.insert(organize::collision_key(&candidate.to_string_lossy())) {
+                candidate = target.with_file_name(format!("{} ({}){}", stem, n, ext));
+                n += 1;
+            }
+            (file, candidate)
+        })
+        .collect()
+}
+
+// 工作线程数量，0 表示和 CPU 核数相同
+pub fn worker_count(configured: usize) -> usize {
+    if configured > 0 {
+        configured
+    } else {
+        available_parallelism().map_or(2, |n| n.get())
+    }
+}
+
+// 多个线程并行转换 (原文件, 目标文件)，目标已存在的跳过。目标文件需要先经过 unique_targets 去重，
+// 每个线程处理不同的文件。cancel 设置后不再开始新的文件
+pub fn export_async(
+    jobs: Vec<(String, PathBuf)>,
+    settings: ExportSettings,
+    workers: usize,
+    cancel: Arc<AtomicBool>,
+    events: ExtEventSink,
+) {
+    spawn(move || {
+        let total = jobs.len();
+        let queue = Arc::new(Mutex::new(jobs));
+        let finished = Arc::new(AtomicUsize::new(0));
+        let skipped = Arc::new(AtomicUsize::new(0));
+        let errors = Arc::new(Mutex::new(vec![]));
+        let handles: Vec<_> = (0..workers.max(1).min(total.max(1)))
+            .map(|_| {
+                let queue = Arc::clone(&queue);
+                let finished = Arc::clone(&finished);
+                let skipped = Arc::clone(&skipped);
+                let errors = Arc::clone(&errors);
+                let cancel = Arc::clone(&cancel);
+                let settings = settings.to_owned();
+                let events = events.to_owned();
+                spawn(move || loop {
+                    if cancel.load(Ordering::Relaxed) {
+                        break;
+                    }
+                    let job = queue.lock().unwrap().pop();
+                    let (file, target) = match job {
+                        Some(job) => job,
+                        None => break,
+                    };
+                    if target.exists() {
+                        skipped.fetch_add(1, Ordering::Relaxed);
+                    } else if let Err(e) = transcode(&file, &target, &settings, &cancel) {
+                        if !cancel.load(Ordering::Relaxed) {
+                            errors.lock().unwrap().push(format!("{}: {}", file, e));
+                        }
+                    }
+                    let done = finished.fetch_add(1, Ordering::Relaxed) + 1;
+                    let _ = events.submit_command(EXPORT_PROGRESS, (done, total), Target::Auto);
+                })
+            })
+            .collect();
+        for handle in handles {
+            let _ = handle.join();
+        }
+        let errors = errors.lock().unwrap();
+        for e in errors.iter() {
+            println!("导出失败 {}", e);
+        }
+        let skipped = skipped.load(Ordering::Relaxed);
+        let done = finished.load(Ordering::Relaxed);
+        let message = if cancel.load(Ordering::Relaxed) {
+            format!("已取消导出，完成 {}/{} 首", done, total)
+        } else {
+            format!(
+                "导出完成: 转换 {} 首，跳过已存在的 {} 首，失败 {} 首",
+                done - skipped - errors.len(),
+                skipped,
+                errors.len()
+            )
+        };
+        let _ = events.submit_command(EXPORT_DONE, message, Target::Auto);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn job(file: &str, target: &str) -> (String, PathBuf) {
+        (file.to_owned(), PathBuf::from(target))
+    }
+
+    #[test]
+    fn target_keeps_relative_path() {
+        let target = target_path(
+            Path::new("Artist/Album/01 Song.flac"),
+            Path::new("/export"),
+            ExportFormat::Mp3V0,
+        );
+        assert_eq!(target, PathBuf::from("/export/Artist/Album/01 Song.mp3"));
+        let target = target_path(Path::new("a.wav"), Path::new("/export"), ExportFormat::Aac);
+        assert_eq!(target, PathBuf::from("/export/a.m4a"));
+        // 文件名中的点不是扩展名
+        let target = target_path(
+            Path::new("Vol. 1.flac"),
+            Path::new("/e"),
+            ExportFormat::Opus,
+        );
+        assert_eq!(target, PathBuf::from("/e/Vol. 1.opus"));
+    }
+
+    #[test]
+    fn rate_limits() {
+        assert_eq!(output_rate(ExportFormat::Opus, 0, 44100), 48000);
+        assert_eq!(output_rate(ExportFormat::Opus, 96000, 192000), 48000);
+        assert_eq!(output_rate(ExportFormat::Flac, 0, 192000), 192000);
+        assert_eq!(output_rate(ExportFormat::Flac, 48000, 96000), 48000);
+        assert_eq!(output_rate(ExportFormat::Mp3V0, 0, 44100), 44100);
+        assert_eq!(output_rate(ExportFormat::Mp3V0, 0, 88200), 44100);
+        assert_eq!(output_rate(ExportFormat::Aac, 0, 176400), 44100);
+        assert_eq!(output_rate(ExportFormat::Aac, 0, 96000), 48000);
+        assert_eq!(output_rate(ExportFormat::Mp3Cbr320, 0, 192000), 48000);
+        assert_eq!(output_rate(ExportFormat::Mp3Cbr320, 32000, 96000), 32000);
+    }
+
+    #[test]
+    fn colliding_targets_are_renamed() {
+        let jobs = unique_targets(vec![
+            job("/m/a.flac", "/e/a.mp3"),
+            job("/m/a.wav", "/e/a.mp3"),
+            job("/m/A.ape", "/e/A.mp3"),
+            job("/m/b.flac", "/e/b.mp3"),
+        ]);
+        assert_eq!(
+            jobs,
+            vec![
+                job("/m/a.flac", "/e/a.mp3"),
+                job("/m/a.wav", "/e/a (2).mp3"),
+                job("/m/A.ape", "/e/A (3).mp3"),
+                job("/m/b.flac", "/e/b.mp3"),
+            ]
+        );
+    }
+
+    #[test]
+    fn renamed_target_does_not_collide() {
+        let jobs = unique_targets(vec![
+            job("/m/a.flac", "/e/a.mp3"),
+            job("/m/a (2).flac", "/e/a (2).mp3"),
+            job("/m/a.wav", "/e/a.mp3"),
+            // 同一个原文件只导出一次
+            job("/m/a.flac", "/e/a.mp3"),
+        ]);
+        assert_eq!(
+            jobs,
+            vec![
+                job("/m/a.flac", "/e/a.mp3"),
+                job("/m/a (2).flac", "/e/a (2).mp3"),
+                job("/m/a.wav", "/e/a (3).mp3"),
+            ]
+        );
+    }
+}